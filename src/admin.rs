@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use actix_web::dev::Server;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use serde::Serialize;
+use validator::Validate;
+
+use crate::audit;
+use crate::auth::require_api_key;
+use crate::config::Config;
+use crate::error::{field_errors, ApiError, FieldError};
+use crate::geo;
+use crate::locale::Lang;
+use crate::news::{Day, NewsStore};
+use crate::store;
+use crate::teams::TeamStore;
+
+/// the running server, filled in once `HttpServer::run` has started it, so a
+/// handler can stop it
+#[derive(Default)]
+pub struct StopHandle {
+    server: Mutex<Option<Server>>,
+}
+
+impl StopHandle {
+    pub fn register(&self, server: Server) -> Result<(), ApiError> {
+        *store::lock(&self.server, "stop handle")? = Some(server);
+        Ok(())
+    }
+}
+
+/// admin endpoints only exist (otherwise `404`) with `ENABLE_ADMIN`, and
+/// always need the `API_KEY`; without one configured they can't be used at all
+pub fn require_admin(req: &HttpRequest, config: &Config) -> Result<(), ApiError> {
+    if !config.enable_admin {
+        return Err(ApiError::NotFound("admin endpoints are disabled".to_string()));
+    }
+    require_api_key(req, config, true)
+}
+
+/// stop the server gracefully: answer `202` and let in-flight requests drain
+pub async fn shutdown(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    stop: web::Data<StopHandle>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+
+    let server = store::lock(&stop.server, "stop handle")?.clone();
+    let server =
+        server.ok_or_else(|| ApiError::Unavailable("the server is still starting".to_string()))?;
+    log::warn!("shutdown requested through /admin/shutdown");
+    actix_rt::spawn(server.stop(true));
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Debug, Serialize)]
+struct Restored {
+    teams: usize,
+    news: usize,
+}
+
+/// put the built-in teams and news back. Both stores are locked before either
+/// is touched, so readers see either the old contents or the seed, never a
+/// mix, and a poisoned lock leaves both as they were.
+pub async fn reset(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    teams: web::Data<TeamStore>,
+    news: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let (mut teams, mut news) = (teams.lock_for_reset()?, news.lock_for_reset()?);
+    let res = Restored { teams: teams.apply(), news: news.apply() };
+    drop((teams, news));
+    log::warn!("stores reset to the seed data through /admin/reset");
+    audit::record(&req, "reset", "*", None, audit::snapshot(&res));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct InvalidTeam {
+    team_abbreviation: String,
+    errors: HashMap<&'static str, Vec<FieldError>>,
+}
+
+#[derive(Debug, Serialize)]
+struct InvalidNews {
+    index: usize,
+    day: Day,
+    errors: HashMap<&'static str, Vec<FieldError>>,
+}
+
+#[derive(Debug, Serialize)]
+struct StoreReport {
+    valid: bool,
+    teams: Vec<InvalidTeam>,
+    news: Vec<InvalidNews>,
+}
+
+/// validate every stored team and news item as if it were being submitted
+/// now, listing the ones that fail, e.g. after validation was tightened or
+/// old data imported. Only reads the stores.
+pub async fn validate_store(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    teams: web::Data<TeamStore>,
+    news: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let lang = Lang::from_headers(req.headers());
+    let teams: Vec<InvalidTeam> = teams
+        .all()?
+        .iter()
+        .filter_map(|team| {
+            let errors = team.validate_at(config.current_year).err()?;
+            Some(InvalidTeam {
+                team_abbreviation: team.abbreviation().to_string(),
+                errors: field_errors(&errors, lang),
+            })
+        })
+        .collect();
+    let news: Vec<InvalidNews> = news
+        .all()?
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            let errors = item.validate().err()?;
+            Some(InvalidNews { index, day: item.day(), errors: field_errors(&errors, lang) })
+        })
+        .collect();
+    let res = StoreReport { valid: teams.is_empty() && news.is_empty(), teams, news };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct UnmappedArea {
+    active_area: String,
+    teams: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CoordsReport {
+    valid: bool,
+    unmapped: Vec<UnmappedArea>,
+}
+
+/// every `active_area` in the store that has no coordinates for the geo
+/// endpoints to use, in order, with the abbreviations of the teams in it.
+/// Only reads the store.
+pub async fn validate_coords(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    teams: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let mut unmapped: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for team in teams.all()? {
+        if geo::coordinates(team.active_area()).is_none() {
+            let area = unmapped.entry(team.active_area().to_string()).or_default();
+            area.push(team.abbreviation().to_string());
+        }
+    }
+    let unmapped: Vec<UnmappedArea> =
+        unmapped.into_iter().map(|(active_area, teams)| UnmappedArea { active_area, teams }).collect();
+    let res = CoordsReport { valid: unmapped.is_empty(), unmapped };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::config::Secret;
+    use crate::test_support;
+
+    fn admin_config() -> web::Data<Config> {
+        let mut config = test_support::config();
+        config.enable_admin = true;
+        config.api_key = Some(Secret::new("admin key".to_string()));
+        web::Data::new(config)
+    }
+
+    async fn reset_with(
+        teams: &web::Data<TeamStore>,
+        news: &web::Data<NewsStore>,
+    ) -> Result<HttpResponse, Error> {
+        let req = TestRequest::post().header("x-api-key", "admin key").to_http_request();
+        reset(req, admin_config(), teams.clone(), news.clone()).await
+    }
+
+    fn kofu() -> crate::teams::Team {
+        serde_json::from_value(serde_json::json!({
+            "team_abbreviation": "甲府", "active_area": "山梨県", "join_year": 1999, "division": "J2",
+        }))
+        .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn reset_puts_the_seed_teams_and_news_back() {
+        let teams = web::Data::new(TeamStore::new(vec![kofu()], 10, false));
+        let news = web::Data::new(NewsStore::seeded());
+        let res = reset_with(&teams, &news).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(teams.all().unwrap(), crate::teams::seed_teams());
+        assert_eq!(news.all().unwrap().len(), NewsStore::seeded().all().unwrap().len());
+    }
+
+    #[actix_rt::test]
+    async fn a_poisoned_news_store_leaves_the_teams_as_they_were() {
+        let teams = web::Data::new(TeamStore::new(vec![kofu()], 10, false));
+        let news = web::Data::new(NewsStore::seeded());
+        // a handler panicking with the news locked
+        let poisoner = news.clone();
+        let panicked = thread::spawn(move || {
+            let _locked = poisoner.lock_for_reset().unwrap();
+            panic!("poisoning the news store");
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        let e = reset_with(&teams, &news).await.unwrap_err();
+        assert_eq!(e.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(teams.all().unwrap(), vec![kofu()]);
+
+        // the poison is cleared, so the next reset goes through
+        assert_eq!(reset_with(&teams, &news).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(teams.all().unwrap(), crate::teams::seed_teams());
+    }
+}
@@ -0,0 +1,38 @@
+use actix_web::http::header;
+use actix_web::HttpResponse;
+
+/// the icon browsers ask for on their own, built into the binary
+pub const FAVICON: &[u8] = include_bytes!("../static/favicon.ico");
+
+/// serve `FAVICON`, cacheable for a day, so that opening the service in a
+/// browser doesn't leave a 404 in the logs each time
+pub async fn favicon() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("image/x-icon")
+        .header(header::CACHE_CONTROL, "public, max-age=86400")
+        .body(FAVICON)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+
+    use super::*;
+    use crate::test_support;
+
+    #[actix_rt::test]
+    async fn the_favicon_is_served_as_a_cacheable_icon() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let res = app.call(TestRequest::get().uri("/favicon.ico").to_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "image/x-icon");
+        assert_eq!(res.headers().get(header::CACHE_CONTROL).unwrap(), "public, max-age=86400");
+        let body = test::read_body(res).await;
+        assert_eq!(body, FAVICON);
+        // an ICO header: reserved 0, type 1 (icon)
+        assert!(body.starts_with(&[0, 0, 1, 0]));
+    }
+}
@@ -0,0 +1,51 @@
+//! With `AUDIT_LOG` set, every change the mutating endpoints make to the
+//! teams or news is logged as one json line under the `audit` log target,
+//! apart from the request logs: `{"timestamp","action","target","request_id",
+//! "before","after"}`. Dry runs change nothing and log nothing.
+
+use actix_web::{web, HttpRequest};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::request_id;
+
+/// the log target audit entries go to
+pub const TARGET: &str = "audit";
+
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    action: &'a str,
+    /// the team's abbreviation, or the news item's day
+    target: &'a str,
+    /// the request's id, as sent back in `REQUEST_ID_HEADER`
+    request_id: Option<String>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+/// a snapshot of `item` for `before` or `after`
+pub fn snapshot<T: Serialize>(item: &T) -> Option<Value> {
+    serde_json::to_value(item).ok()
+}
+
+/// log one change made on behalf of `req`, when `AUDIT_LOG` is set
+pub fn record(req: &HttpRequest, action: &str, target: &str, before: Option<Value>, after: Option<Value>) {
+    let enabled = req.app_data::<web::Data<Config>>().is_some_and(|config| config.audit_log);
+    if !enabled {
+        return;
+    }
+    let entry = Entry {
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        action,
+        target,
+        request_id: request_id::of(req),
+        before,
+        after,
+    };
+    match serde_json::to_string(&entry) {
+        Ok(line) => log::info!(target: TARGET, "{}", line),
+        Err(e) => log::error!("couldn't write an audit entry for {} {}: {}", action, target, e),
+    }
+}
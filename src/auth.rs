@@ -0,0 +1,37 @@
+use actix_web::{http::header, HttpRequest};
+use openssl::memcmp;
+
+use crate::config::Config;
+use crate::error::ApiError;
+
+/// the key presented with the request, as `X-API-Key: <key>` or
+/// `Authorization: Bearer <key>`
+fn presented_key(req: &HttpRequest) -> Option<&str> {
+    let headers = req.headers();
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key);
+    }
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// `401` unless the request carries `API_KEY`. Does nothing when no key is
+/// configured, unless `required` says the endpoint must never be open.
+pub fn require_api_key(req: &HttpRequest, config: &Config, required: bool) -> Result<(), ApiError> {
+    let expected = match &config.api_key {
+        Some(key) => key.expose(),
+        None if required => {
+            return Err(ApiError::Unauthorized("this endpoint needs API_KEY to be set".to_string()))
+        }
+        None => return Ok(()),
+    };
+    match presented_key(req) {
+        Some(key) if key.len() == expected.len() && memcmp::eq(key.as_bytes(), expected.as_bytes()) => {
+            Ok(())
+        }
+        Some(_) => Err(ApiError::Unauthorized("invalid api key".to_string())),
+        None => Err(ApiError::Unauthorized("missing api key".to_string())),
+    }
+}
@@ -0,0 +1,232 @@
+//! `DEBUG_BODY_LOG=true` logs request and response bodies, truncated to
+//! `DEBUG_BODY_LOG_MAX` bytes (default 1024). Nothing is captured or logged
+//! when the flag is off.
+//!
+//! Neither body is buffered: chunks are passed on as they arrive or are
+//! produced and only the first `DEBUG_BODY_LOG_MAX` bytes are copied aside, so
+//! a large upload costs no more memory than with the flag off and streaming
+//! responses such as `/something?stream=true` still stream. A request body is
+//! logged once the handler is done reading it (or drops it), so only what it
+//! read is logged; a response body once it has been fully sent (or dropped).
+
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{
+    BodySize, MessageBody, Payload, ResponseBody, Service, ServiceRequest, ServiceResponse,
+    Transform,
+};
+use actix_web::error::PayloadError;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::{Stream, StreamExt};
+
+use crate::config::{env_flag, env_or};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLog {
+    enabled: bool,
+    max_len: usize,
+}
+
+impl BodyLog {
+    pub fn from_env() -> Self {
+        BodyLog {
+            enabled: env_flag("DEBUG_BODY_LOG"),
+            max_len: env_or("DEBUG_BODY_LOG_MAX", 1024),
+        }
+    }
+}
+
+/// the first `max_len` bytes of `body`, noting the full size when cut short
+fn truncated(body: &[u8], total: usize, max_len: usize) -> String {
+    let shown = String::from_utf8_lossy(&body[..body.len().min(max_len)]).into_owned();
+    if total > max_len {
+        format!("{}... ({} bytes total)", shown, total)
+    } else {
+        shown
+    }
+}
+
+impl<S, B> Transform<S> for BodyLog
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BodyLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BodyLogMiddleware { service: Rc::new(RefCell::new(service)), config: *self })
+    }
+}
+
+pub struct BodyLogMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    config: BodyLog,
+}
+
+impl<S, B> Service for BodyLogMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let config = self.config;
+        // the payload of an upgraded request is the connection itself
+        if !config.enabled || req.head().upgrade() {
+            let fut = self.service.borrow_mut().call(req);
+            return Box::pin(async move {
+                let res = fut.await?;
+                Ok(res.map_body(|_, body| ResponseBody::Body(LoggedBody::new(body, None))))
+            });
+        }
+
+        let label = format!("{} {}", req.method(), req.path());
+        let payload = LoggedPayload { payload: req.take_payload(), capture: Capture::new(&label, config) };
+        req.set_payload(Payload::Stream(Box::pin(payload)));
+        let fut = self.service.borrow_mut().call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            let capture = Capture::new(&label, config);
+            Ok(res.map_body(|_, body| ResponseBody::Body(LoggedBody::new(body, Some(capture)))))
+        })
+    }
+}
+
+/// the head of a body on its way through, and how long the whole body was
+struct Capture {
+    label: String,
+    max_len: usize,
+    data: BytesMut,
+    total: usize,
+}
+
+impl Capture {
+    fn new(label: &str, config: BodyLog) -> Self {
+        Capture { label: label.to_string(), max_len: config.max_len, data: BytesMut::new(), total: 0 }
+    }
+
+    /// copy what still fits of `chunk` aside and count all of it
+    fn record(&mut self, chunk: &[u8]) {
+        let room = self.max_len.saturating_sub(self.data.len());
+        self.data.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        self.total += chunk.len();
+    }
+
+    fn log(&self, which: &str) {
+        log::info!("{} body {}: {}", which, self.label, truncated(&self.data, self.total, self.max_len));
+    }
+}
+
+/// a request payload passed through unchanged, copying its head aside for the log
+struct LoggedPayload {
+    payload: Payload,
+    capture: Capture,
+}
+
+impl Stream for LoggedPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let polled = self.payload.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            self.capture.record(chunk);
+        }
+        polled
+    }
+}
+
+impl Drop for LoggedPayload {
+    fn drop(&mut self) {
+        self.capture.log("request");
+    }
+}
+
+/// a response body passed through unchanged, copying its head aside for the log
+pub struct LoggedBody<B> {
+    body: ResponseBody<B>,
+    capture: Option<Capture>,
+}
+
+impl<B> LoggedBody<B> {
+    fn new(body: ResponseBody<B>, capture: Option<Capture>) -> Self {
+        LoggedBody { body, capture }
+    }
+}
+
+impl<B: MessageBody> MessageBody for LoggedBody<B> {
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, Error>>> {
+        let polled = self.body.poll_next(cx);
+        if let (Some(capture), Poll::Ready(Some(Ok(chunk)))) = (&mut self.capture, &polled) {
+            capture.record(chunk);
+        }
+        polled
+    }
+}
+
+impl<B> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        if let Some(capture) = &self.capture {
+            capture.log("response");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use futures::stream;
+
+    use super::*;
+
+    fn chunked(chunks: &[&'static str]) -> Payload {
+        let chunks: Vec<Result<Bytes, PayloadError>> = chunks.iter().map(|c| Ok(Bytes::from(*c))).collect();
+        Payload::Stream(Box::pin(stream::iter(chunks)))
+    }
+
+    #[actix_rt::test]
+    async fn a_request_body_is_passed_on_whole_with_only_its_head_kept() {
+        let config = BodyLog { enabled: true, max_len: 4 };
+        let capture = Capture::new("POST /", config);
+        let mut payload = LoggedPayload { payload: chunked(&["abc", "defg", "hij"]), capture };
+        let mut body = BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(&body[..], b"abcdefghij");
+        assert_eq!((&payload.capture.data[..], payload.capture.total), (&b"abcd"[..], 10));
+    }
+
+    #[actix_rt::test]
+    async fn a_handler_still_reads_the_whole_logged_body() {
+        let config = BodyLog { enabled: true, max_len: 4 };
+        let app = App::new().wrap(config).route("/", web::post().to(|body: Bytes| async move { body }));
+        let mut app = test::init_service(app).await;
+        let req = test::TestRequest::post().uri("/").set_payload("a body longer than max_len").to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(&body[..], b"a body longer than max_len");
+    }
+}
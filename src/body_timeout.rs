@@ -0,0 +1,184 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_rt::time::{delay_for, Delay};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::{Stream, StreamExt};
+
+use crate::config::env_or;
+use crate::error::ApiError;
+
+/// answer `408` when the client takes longer than `BODY_READ_TIMEOUT_MS`
+/// (10000 by default) to send its request body, so a client trickling the
+/// body can't hold a worker's attention indefinitely. The body isn't
+/// buffered: the deadline starts when the handler first reads the payload and
+/// is checked as each chunk is waited for, so a handler that streams its body
+/// still gets it a chunk at a time. Once it passes the payload fails, and
+/// whatever the handler made of that is answered as the `408`.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyTimeout {
+    limit: Duration,
+}
+
+impl BodyTimeout {
+    pub fn from_env() -> Self {
+        BodyTimeout { limit: Duration::from_millis(env_or("BODY_READ_TIMEOUT_MS", 10_000)) }
+    }
+}
+
+impl<S, B> Transform<S> for BodyTimeout
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BodyTimeoutMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BodyTimeoutMiddleware { service: Rc::new(RefCell::new(service)), limit: self.limit })
+    }
+}
+
+pub struct BodyTimeoutMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    limit: Duration,
+}
+
+impl<S, B> Service for BodyTimeoutMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        if req.head().upgrade() {
+            // the payload of an upgraded request is the connection itself
+            let fut = self.service.borrow_mut().call(req);
+            return Box::pin(fut);
+        }
+        let limit = self.limit;
+        let timed_out = Rc::new(Cell::new(false));
+        let payload = req.take_payload();
+        let payload = DeadlinePayload { payload, limit, deadline: None, timed_out: timed_out.clone() };
+        req.set_payload(Payload::Stream(Box::pin(payload)));
+        let fut = self.service.borrow_mut().call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            if timed_out.get() {
+                let message = format!("request body not received within {}ms", limit.as_millis());
+                return Err(ApiError::RequestTimeout(message).into());
+            }
+            res
+        })
+    }
+}
+
+/// a request payload passed through unchanged until `limit` after it was
+/// first polled, then failed for good, noting so in `timed_out`
+struct DeadlinePayload {
+    payload: Payload,
+    limit: Duration,
+    deadline: Option<Pin<Box<Delay>>>,
+    timed_out: Rc<Cell<bool>>,
+}
+
+impl Stream for DeadlinePayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.timed_out.get() {
+            return Poll::Ready(None);
+        }
+        let limit = self.limit;
+        let deadline = self.deadline.get_or_insert_with(|| Box::pin(delay_for(limit)));
+        if deadline.as_mut().poll(cx).is_ready() {
+            self.timed_out.set(true);
+            let e = io::Error::new(io::ErrorKind::TimedOut, "request body read timed out");
+            return Poll::Ready(Some(Err(PayloadError::Io(e))));
+        }
+        self.payload.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::client::Client;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestServer};
+    use actix_web::{web, App};
+    use futures::stream;
+    use serde_json::json;
+
+    use super::*;
+
+    /// a server that echoes a posted body, or a posted json body, read under a
+    /// `limit` body timeout
+    fn echo_server(limit: Duration) -> TestServer {
+        test::start(move || {
+            App::new()
+                .wrap(BodyTimeout { limit })
+                .route("/bytes", web::post().to(|body: Bytes| async move { body }))
+                .route("/json", web::post().to(|body: web::Json<serde_json::Value>| async move { body }))
+        })
+    }
+
+    /// `pieces` of a json string, each sent `gap` after the last
+    fn trickle(pieces: usize, gap: Duration) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+        let mut chunks = vec![Bytes::from("\"")];
+        chunks.extend((0..pieces).map(|i| Bytes::from(i.to_string())));
+        chunks.push(Bytes::from("\""));
+        Box::pin(stream::iter(chunks).then(move |chunk| async move {
+            delay_for(gap).await;
+            Ok::<_, Error>(chunk)
+        }))
+    }
+
+    #[actix_rt::test]
+    async fn a_body_sent_within_the_limit_is_passed_on_unchanged() {
+        let srv = echo_server(Duration::from_millis(2000));
+        let body = trickle(3, Duration::from_millis(20));
+        let mut res = Client::new().post(srv.url("/bytes")).send_stream(body).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(&res.body().await.unwrap()[..], b"\"012\"");
+
+        let mut res = Client::new().post(srv.url("/json")).send_json(&json!({"a": [1, 2]})).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.json::<serde_json::Value>().await.unwrap(), json!({"a": [1, 2]}));
+    }
+
+    #[actix_rt::test]
+    async fn a_body_trickling_past_the_limit_is_a_408_whatever_reads_it() {
+        let srv = echo_server(Duration::from_millis(100));
+        for path in ["/bytes", "/json"] {
+            let body = trickle(10, Duration::from_millis(50));
+            let req = Client::new().post(srv.url(path)).content_type("application/json");
+            let mut res = req.send_stream(body).await.unwrap();
+            assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT, "{}", path);
+            let body: serde_json::Value = res.json().await.unwrap();
+            assert_eq!(body["code"], "request_timeout");
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::env_or;
+use crate::store;
+
+struct Entry<V> {
+    value: V,
+    stored: Instant,
+    last_used: u64,
+}
+
+struct Entries<V> {
+    map: HashMap<String, Entry<V>>,
+    /// bumped on every hit and insert; the entry with the lowest `last_used`
+    /// is the least recently used
+    clock: u64,
+}
+
+/// a small least-recently-used cache shared by all workers, holding at most
+/// `capacity` entries for at most `ttl` each. A capacity of 0 turns it off.
+/// Eviction scans every entry, which is fine at the sizes this is meant for.
+pub struct Lru<V> {
+    entries: Mutex<Entries<V>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<V: Clone> Lru<V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Lru { entries: Mutex::new(Entries { map: HashMap::new(), clock: 0 }), capacity, ttl }
+    }
+
+    /// sized by `STEP_CACHE_SIZE` (0, off, by default) with entries kept for
+    /// `STEP_CACHE_TTL_SECS` (60 by default)
+    pub fn from_env() -> Self {
+        Lru::new(
+            env_or("STEP_CACHE_SIZE", 0),
+            Duration::from_secs(env_or("STEP_CACHE_TTL_SECS", 60)),
+        )
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// the live value for `key`, if any. A cache that can't be locked is
+    /// treated as empty rather than failing the request.
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = store::lock(&self.entries, "cache").ok()?;
+        let expired = entries.map.get(key)?.stored.elapsed() > self.ttl;
+        if expired {
+            entries.map.remove(key);
+            return None;
+        }
+        entries.clock += 1;
+        let clock = entries.clock;
+        let entry = entries.map.get_mut(key)?;
+        entry.last_used = clock;
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&self, key: String, value: V) {
+        if !self.enabled() {
+            return;
+        }
+        let mut entries = match store::lock(&self.entries, "cache") {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        if entries.map.len() >= self.capacity && !entries.map.contains_key(&key) {
+            let oldest = entries.map.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        entries.clock += 1;
+        let last_used = entries.clock;
+        entries.map.insert(key, Entry { value, stored: Instant::now(), last_used });
+    }
+}
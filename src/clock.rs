@@ -0,0 +1,61 @@
+use actix_web::{web, Error, HttpResponse};
+use chrono::{FixedOffset, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+
+/// the zones `GET /now` knows, with their offset east of UTC in minutes. Only
+/// zones without daylight saving time are listed, so a fixed offset is always
+/// right for them.
+const ZONES: &[(&str, i32)] = &[
+    ("UTC", 0),
+    ("GMT", 0),
+    ("Asia/Tokyo", 9 * 60),
+    ("JST", 9 * 60),
+    ("Asia/Seoul", 9 * 60),
+    ("Asia/Shanghai", 8 * 60),
+    ("Asia/Singapore", 8 * 60),
+    ("Asia/Kolkata", 5 * 60 + 30),
+    ("Australia/Brisbane", 10 * 60),
+];
+
+fn offset(tz: &str) -> Result<FixedOffset, ApiError> {
+    ZONES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(tz))
+        .and_then(|(_, minutes)| FixedOffset::east_opt(minutes * 60))
+        .ok_or_else(|| {
+            let known: Vec<&str> = ZONES.iter().map(|(name, _)| *name).collect();
+            ApiError::BadRequest(format!("unknown tz {}; known zones are {}", tz, known.join(", ")))
+        })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NowQuery {
+    tz: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Now {
+    tz: String,
+    iso8601: String,
+    unix: i64,
+    rfc2822: String,
+}
+
+/// the current time in `?tz=` (UTC by default) as RFC 3339, unix seconds and
+/// RFC 2822, for clients syncing their clocks
+pub async fn now(query: web::Query<NowQuery>) -> Result<HttpResponse, Error> {
+    let tz = query.tz.as_deref().unwrap_or("UTC");
+    let now = Utc::now().with_timezone(&offset(tz)?);
+    let res = Now {
+        tz: tz.to_string(),
+        iso8601: now.to_rfc3339_opts(SecondsFormat::Millis, false),
+        unix: now.timestamp(),
+        rfc2822: now.to_rfc2822(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
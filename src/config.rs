@@ -0,0 +1,565 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use actix_web::http::{header, HeaderName};
+use chrono::Datelike;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use serde::Serialize;
+
+use crate::auth::require_api_key;
+use crate::cors;
+use crate::deprecation;
+use crate::retry::Backoff;
+use crate::teams::{CHAR_CLASSES, DIVISIONS};
+use crate::timeout::{self, ScaledTimeout};
+
+/// read `key` from the environment, falling back to `default` when it is
+/// unset or doesn't parse
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// `true` when `key` is set to "true" or "1"
+pub fn env_flag(key: &str) -> bool {
+    env::var(key).map(|v| is_on(&v)).unwrap_or(false)
+}
+
+fn is_on(value: &str) -> bool {
+    value == "true" || value == "1"
+}
+
+/// numeric settings; when set they must parse rather than silently fall back
+/// to their defaults
+const NUMERIC_VARS: &[&str] = &[
+    "PORT",
+    "MAX_TEAMS",
+    "MAX_QUERY_LENGTH",
+    "MAX_QUERY_PARAMS",
+    "MAX_PATH_LENGTH",
+    "MAX_PATH_SEGMENTS",
+    "DEBUG_BODY_LOG_MAX",
+    "SLOW_REQUEST_MS",
+    "CURRENT_YEAR",
+    "BATCH_CONCURRENCY",
+    "CLIENT_TIMEOUT_SECS",
+    "WORKERS",
+    "REQUEST_TIMEOUT_MS",
+    "MAX_BODY_BYTES",
+    "MAX_RESPONSE_BYTES",
+    "WARMUP_MS",
+    "BODY_READ_TIMEOUT_MS",
+    "STEP_CACHE_SIZE",
+    "STEP_CACHE_TTL_SECS",
+    "MAX_CONCURRENT_REQUESTS",
+    "QUEUE_DEPTH",
+    "QUEUE_WAIT_MS",
+    "CREATE_TIMEOUT_BASE_MS",
+    "CREATE_TIMEOUT_PER_KB_MS",
+    "CREATE_TIMEOUT_MAX_MS",
+    "FAST_REQUEST_TIMEOUT_MS",
+    "SLOW_REQUEST_TIMEOUT_MS",
+    "NEWS_POLL_MAX_MS",
+    "WS_SESSION_MAX_SECS",
+    "STEP_RETRIES",
+    "STEP_RETRY_BASE_MS",
+    "DEDUP_WINDOW_MS",
+    "MAX_OUTBOUND_REQUESTS",
+    "OUTBOUND_WAIT_MS",
+    "CORS_MAX_AGE_SECS",
+    "VALIDATION_ERROR_STATUS",
+    "RECENT_REQUESTS",
+    "RECENT_REQUEST_BODY_MAX",
+    "MAX_CHAIN_STEPS",
+    "UPSTREAM_CHUNK_TIMEOUT_MS",
+];
+
+/// every problem with the environment: malformed numbers and route timeouts,
+/// and settings that need companions (the TLS certificate and key go together
+/// and must exist)
+fn env_problems() -> Vec<String> {
+    problems_in(|key| env::var(key).ok())
+}
+
+/// the problems `env_problems` finds, with the variables `var` looks up
+fn problems_in(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for key in NUMERIC_VARS {
+        if let Some(value) = var(key) {
+            if value.parse::<u64>().is_err() {
+                problems.push(format!("{} must be a non-negative number, got {:?}", key, value));
+            }
+        }
+    }
+
+    if let Some(division) = var("DEFAULT_DIVISION") {
+        if !DIVISIONS.contains(&division.as_str()) {
+            problems.push(format!(
+                "DEFAULT_DIVISION must be one of {}, got {:?}",
+                DIVISIONS.join(", "),
+                division
+            ));
+        }
+    }
+
+    if let Some(charset) = var("ABBR_CHARSET") {
+        for class in charset_classes(&charset) {
+            if !CHAR_CLASSES.contains(&class) {
+                problems.push(format!(
+                    "ABBR_CHARSET classes must be among {}, got {:?}",
+                    CHAR_CLASSES.join(", "),
+                    class
+                ));
+            }
+        }
+    }
+
+    if let Some(status) = var("VALIDATION_ERROR_STATUS") {
+        if status.parse::<u16>().map_or(true, |status| !(400..500).contains(&status)) {
+            problems.push(format!("VALIDATION_ERROR_STATUS must be a 4xx status, got {:?}", status));
+        }
+    }
+
+    if let Some(date) = var("API_V0_SUNSET") {
+        if deprecation::sunset(&date).is_none() {
+            problems.push(format!("API_V0_SUNSET must be a YYYY-MM-DD date, got {:?}", date));
+        }
+    }
+
+    if let Some(name) = var("REQUEST_ID_HEADER") {
+        if HeaderName::from_bytes(name.trim().as_bytes()).is_err() {
+            problems.push(format!("REQUEST_ID_HEADER must be a header name, got {:?}", name));
+        }
+    }
+
+    let cors_origins = var("CORS_ALLOWED_ORIGINS").unwrap_or_default();
+    let cors_credentials = var("CORS_ALLOW_CREDENTIALS").is_some_and(|v| is_on(&v));
+    if cors_credentials && cors::origins(&cors_origins).any(|o| o == cors::ANY_ORIGIN) {
+        problems.push(
+            "CORS_ALLOW_CREDENTIALS can't be combined with CORS_ALLOWED_ORIGINS=*; list the origins instead"
+                .to_string(),
+        );
+    }
+
+    if let Err(problem) = timeout::parse_routes(&var("ROUTE_TIMEOUTS").unwrap_or_default()) {
+        problems.push(problem);
+    }
+
+    let cert = var("TLS_CERT_FILE");
+    let key = var("TLS_KEY_FILE");
+    match (&cert, &key) {
+        (Some(_), None) => problems.push("TLS_KEY_FILE is required when TLS_CERT_FILE is set".to_string()),
+        (None, Some(_)) => problems.push("TLS_CERT_FILE is required when TLS_KEY_FILE is set".to_string()),
+        _ => {}
+    }
+    for (name, path) in [("TLS_CERT_FILE", &cert), ("TLS_KEY_FILE", &key)].iter() {
+        if let Some(path) = path {
+            if !Path::new(path).is_file() {
+                problems.push(format!("{} {} does not exist", name, path));
+            }
+        }
+    }
+
+    problems
+}
+
+/// check the environment up front, failing with every problem at once
+/// instead of on first use
+pub fn validate_env() -> io::Result<()> {
+    let problems = env_problems();
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!("invalid configuration:\n  - {}", problems.join("\n  - "));
+    log::error!("{}", message);
+    Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+/// inbound headers that carry credentials; they are left out of
+/// `FORWARD_HEADERS` unless `FORWARD_SENSITIVE_HEADERS` is set, and redacted
+/// by `/debug/headers` unless `DEBUG_SENSITIVE_HEADERS` is set
+pub const SENSITIVE_HEADERS: &[HeaderName] =
+    &[header::AUTHORIZATION, header::PROXY_AUTHORIZATION, header::COOKIE];
+
+/// headers the outbound request sets itself, which are never copied over
+const OWN_HEADERS: &[HeaderName] = &[
+    header::HOST,
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::TRANSFER_ENCODING,
+    header::CONNECTION,
+];
+
+fn charset_classes(charset: &str) -> impl Iterator<Item = &str> {
+    charset.split(',').map(str::trim).filter(|class| !class.is_empty())
+}
+
+/// the header request ids travel in, `X-Request-Id` unless `REQUEST_ID_HEADER`
+/// names a valid one
+fn request_id_header() -> HeaderName {
+    env::var("REQUEST_ID_HEADER")
+        .ok()
+        .and_then(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .unwrap_or_else(|| HeaderName::from_static("x-request-id"))
+}
+
+/// parse the comma-separated `FORWARD_HEADERS` allowlist
+fn forward_headers() -> Vec<HeaderName> {
+    let list = env::var("FORWARD_HEADERS").unwrap_or_default();
+    allowed_forward_headers(&list, env_flag("FORWARD_SENSITIVE_HEADERS"))
+}
+
+/// the headers of the comma-separated `list` that may be forwarded, leaving
+/// out the credentials unless `allow_sensitive`
+pub(crate) fn allowed_forward_headers(list: &str, allow_sensitive: bool) -> Vec<HeaderName> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) => Some(name),
+            Err(_) => {
+                log::warn!("FORWARD_HEADERS: ignoring invalid header name {:?}", name);
+                None
+            }
+        })
+        .filter(|name| {
+            if OWN_HEADERS.contains(name) {
+                log::warn!("FORWARD_HEADERS: {} is set by the outbound request itself", name);
+                return false;
+            }
+            if SENSITIVE_HEADERS.contains(name) && !allow_sensitive {
+                log::warn!(
+                    "FORWARD_HEADERS: not forwarding sensitive header {} without FORWARD_SENSITIVE_HEADERS",
+                    name
+                );
+                return false;
+            }
+            true
+        })
+        .collect()
+}
+
+/// a setting that must not end up in logs or responses; `Debug` prints it
+/// redacted
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+/// settings resolved from the environment once at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `HOST`: the address to listen on, 0.0.0.0 by default
+    pub host: String,
+    /// `PORT`: the port to listen on, 3000 by default
+    pub port: u16,
+    /// `HTTPBIN_URL`: base url of the httpbin the chain posts to,
+    /// https://httpbin.org by default
+    pub httpbin_url: String,
+    /// `CLIENT_TIMEOUT_SECS`: how long an httpbin request may take, 5 seconds
+    /// by default
+    pub client_timeout: Duration,
+    /// `UPSTREAM_CHUNK_TIMEOUT_MS`: how long httpbin may go without sending
+    /// more of a response body, 5000ms by default. The client timeout stops
+    /// at the response head; this covers a body that trickles in.
+    pub upstream_chunk_timeout: Duration,
+    /// `CREATE_TIMEOUT_BASE_MS` and friends: a deadline for the
+    /// `POST /something` chain that scales with the size of the input, see
+    /// [`ScaledTimeout`]. Unset, only `REQUEST_TIMEOUT_MS` applies.
+    pub create_timeout: Option<ScaledTimeout>,
+    /// `STEP_RETRIES`/`STEP_RETRY_BASE_MS`: how a failed httpbin call is
+    /// retried, not at all by default
+    pub step_retry: Backoff,
+    /// `MAX_CHAIN_STEPS`: the most steps `POST /something?steps=` may run, 10
+    /// by default
+    pub max_chain_steps: usize,
+    /// `WS_SESSION_MAX_SECS`: how long a `/ws/something` session may last,
+    /// 300 seconds by default
+    pub ws_session_max: Duration,
+    /// `WORKERS`: worker threads, one per cpu by default
+    pub workers: usize,
+    /// `OFFLINE_MODE`: answer the httpbin-backed endpoints with `503` instead
+    /// of attempting any network I/O. The teams and news endpoints are local
+    /// and keep working.
+    pub offline_mode: bool,
+    /// `MAX_TEAMS`: how many teams the store accepts, 1000 by default
+    pub max_teams: usize,
+    /// `DEFAULT_DIVISION`: the division a team created without one is put
+    /// in, J2 by default
+    pub default_division: String,
+    /// `CURRENT_YEAR`: the year the date-dependent rules (no `join_year` in
+    /// the future, tenure, team ages) take as this one, so they needn't
+    /// depend on the wall clock; the calendar year at startup by default
+    pub current_year: u32,
+    /// `ABBR_CHARSET`: the comma-separated character classes
+    /// `/api/v0/teams/validate-abbr` allows in an abbreviation, `kanji,kana` by
+    /// default
+    pub abbr_charset: Vec<String>,
+    /// `FORWARD_HEADERS`: inbound headers copied onto the httpbin requests.
+    /// Nothing is forwarded by default.
+    pub forward_headers: Vec<HeaderName>,
+    /// `REQUEST_ID_HEADER`: the header request ids are read from, sent back in
+    /// and passed on to httpbin in, `X-Request-Id` by default
+    pub request_id_header: HeaderName,
+    /// `TLS_CERT_FILE`/`TLS_KEY_FILE`: PEM certificate chain and private key;
+    /// with both set the server speaks https (HTTP/2 and HTTP/1.1)
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    /// `H2C`: serve prior-knowledge cleartext HTTP/2, instead of HTTP/1.1, on
+    /// the plain http listener
+    pub h2c: bool,
+    /// `BATCH_CONCURRENCY`: how many `/something/batch` items run at once,
+    /// 4 by default
+    pub batch_concurrency: usize,
+    /// `MAX_BODY_BYTES`: the largest raw request body accepted, 256KiB by
+    /// default
+    pub max_body_bytes: usize,
+    /// `MAX_RESPONSE_BYTES`: the largest serialized team list sent, 1MiB by
+    /// default
+    pub max_response_bytes: usize,
+    /// `DEBUG_BODY_LOG`: log request and response bodies
+    pub debug_body_log: bool,
+    /// `GRAPHEME_LENGTH`: count `SomeData`'s length limits in grapheme
+    /// clusters rather than `char`s
+    pub grapheme_length: bool,
+    /// `API_KEY`: the key clients present as `X-API-Key` or a bearer token.
+    /// Unset, the endpoints that take it are open, except admin ones.
+    pub api_key: Option<Secret>,
+    /// `WEBHOOK_SECRET`: the key the `X-Signature` HMAC of mutating requests
+    /// is checked against; unset, they aren't checked
+    pub webhook_secret: Option<Secret>,
+    /// `ENABLE_ADMIN`: serve the `/admin` endpoints
+    pub enable_admin: bool,
+    /// `AUDIT_LOG`: log every change to the teams and news under the `audit`
+    /// target
+    pub audit_log: bool,
+    /// `SOFT_DELETE`: keep deleted teams out of sight for a restore instead
+    /// of dropping them
+    pub soft_delete: bool,
+    /// `DEBUG_ENDPOINTS`: serve the `/debug/bench` endpoints and `/debug/flaky`
+    pub debug_endpoints: bool,
+    /// `DEBUG_SENSITIVE_HEADERS`: show credential headers in `/debug/headers`
+    /// instead of redacting them; for trusted environments only
+    pub debug_sensitive_headers: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Config {
+            host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
+            port: env_or("PORT", 3000),
+            httpbin_url: env::var("HTTPBIN_URL")
+                .unwrap_or_else(|_| "https://httpbin.org".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            client_timeout: Duration::from_secs(env_or("CLIENT_TIMEOUT_SECS", 5)),
+            upstream_chunk_timeout: Duration::from_millis(env_or("UPSTREAM_CHUNK_TIMEOUT_MS", 5000)),
+            create_timeout: ScaledTimeout::create_from_env(),
+            step_retry: Backoff::from_env(),
+            max_chain_steps: env_or("MAX_CHAIN_STEPS", 10),
+            ws_session_max: Duration::from_secs(env_or("WS_SESSION_MAX_SECS", 300)),
+            workers: env_or("WORKERS", cpus).max(1),
+            offline_mode: env_flag("OFFLINE_MODE"),
+            max_teams: env_or("MAX_TEAMS", 1000),
+            default_division: env::var("DEFAULT_DIVISION").unwrap_or_else(|_| "J2".to_string()),
+            current_year: env::var("CURRENT_YEAR")
+                .ok()
+                .and_then(|y| y.parse().ok())
+                .unwrap_or_else(|| chrono::Utc::now().year() as u32),
+            abbr_charset: charset_classes(&env::var("ABBR_CHARSET").unwrap_or_else(|_| "kanji,kana".to_string()))
+                .map(str::to_string)
+                .collect(),
+            forward_headers: forward_headers(),
+            request_id_header: request_id_header(),
+            tls_cert_file: env::var("TLS_CERT_FILE").ok(),
+            tls_key_file: env::var("TLS_KEY_FILE").ok(),
+            h2c: env_flag("H2C"),
+            batch_concurrency: env_or("BATCH_CONCURRENCY", 4).max(1),
+            max_body_bytes: env_or("MAX_BODY_BYTES", 256 * 1024),
+            max_response_bytes: env_or("MAX_RESPONSE_BYTES", 1024 * 1024),
+            debug_body_log: env_flag("DEBUG_BODY_LOG"),
+            grapheme_length: env_flag("GRAPHEME_LENGTH"),
+            debug_endpoints: env_flag("DEBUG_ENDPOINTS"),
+            debug_sensitive_headers: env_flag("DEBUG_SENSITIVE_HEADERS"),
+            api_key: env::var("API_KEY").ok().filter(|k| !k.is_empty()).map(Secret::new),
+            webhook_secret: env::var("WEBHOOK_SECRET").ok().filter(|k| !k.is_empty()).map(Secret::new),
+            enable_admin: env_flag("ENABLE_ADMIN"),
+            audit_log: env_flag("AUDIT_LOG"),
+            soft_delete: env_flag("SOFT_DELETE"),
+        }
+    }
+}
+
+/// the on/off switches, as reported by `GET /config`
+#[derive(Debug, Serialize)]
+struct Features {
+    offline_mode: bool,
+    tls: bool,
+    h2c: bool,
+    debug_body_log: bool,
+    grapheme_length: bool,
+    debug_endpoints: bool,
+    debug_sensitive_headers: bool,
+    api_key: bool,
+    webhook_signatures: bool,
+    admin: bool,
+    audit_log: bool,
+    soft_delete: bool,
+}
+
+/// what `GET /config` reports. Each setting is copied over by hand rather than
+/// serializing `Config` itself, so a secret added to `Config` later stays out
+/// of the report; the TLS files, `API_KEY` and `WEBHOOK_SECRET` only show up
+/// as whether they're set.
+#[derive(Debug, Serialize)]
+struct ConfigReport<'a> {
+    host: &'a str,
+    port: u16,
+    httpbin_url: &'a str,
+    client_timeout_secs: u64,
+    workers: usize,
+    max_teams: usize,
+    default_division: &'a str,
+    abbr_charset: &'a [String],
+    batch_concurrency: usize,
+    max_chain_steps: usize,
+    max_body_bytes: usize,
+    max_response_bytes: usize,
+    forward_headers: Vec<&'a str>,
+    request_id_header: &'a str,
+    features: Features,
+}
+
+impl<'a> From<&'a Config> for ConfigReport<'a> {
+    fn from(config: &'a Config) -> Self {
+        ConfigReport {
+            host: &config.host,
+            port: config.port,
+            httpbin_url: &config.httpbin_url,
+            client_timeout_secs: config.client_timeout.as_secs(),
+            workers: config.workers,
+            max_teams: config.max_teams,
+            default_division: &config.default_division,
+            abbr_charset: &config.abbr_charset,
+            batch_concurrency: config.batch_concurrency,
+            max_chain_steps: config.max_chain_steps,
+            max_body_bytes: config.max_body_bytes,
+            max_response_bytes: config.max_response_bytes,
+            forward_headers: config.forward_headers.iter().map(HeaderName::as_str).collect(),
+            request_id_header: config.request_id_header.as_str(),
+            features: Features {
+                offline_mode: config.offline_mode,
+                tls: config.tls_cert_file.is_some() && config.tls_key_file.is_some(),
+                h2c: config.h2c,
+                debug_body_log: config.debug_body_log,
+                grapheme_length: config.grapheme_length,
+                debug_endpoints: config.debug_endpoints,
+                debug_sensitive_headers: config.debug_sensitive_headers,
+                api_key: config.api_key.is_some(),
+                webhook_signatures: config.webhook_secret.is_some(),
+                admin: config.enable_admin,
+                audit_log: config.audit_log,
+                soft_delete: config.soft_delete,
+            },
+        }
+    }
+}
+
+/// the effective configuration, secrets left out; needs the `API_KEY` when
+/// one is set
+pub async fn show_config(req: HttpRequest, config: web::Data<Config>) -> Result<HttpResponse, Error> {
+    require_api_key(&req, &config, false)?;
+    let res = ConfigReport::from(config.get_ref());
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// what `problems_in` finds with only `vars` set
+    fn problems(vars: &[(&str, &str)]) -> Vec<String> {
+        problems_in(|key| vars.iter().find(|(name, _)| *name == key).map(|(_, value)| value.to_string()))
+    }
+
+    #[test]
+    fn an_empty_environment_is_fine() {
+        assert!(problems(&[]).is_empty());
+        let fine =
+            [("PORT", "8080"), ("VALIDATION_ERROR_STATUS", "422"), ("ROUTE_TIMEOUTS", "/slow=100, /fast=5")];
+        assert!(problems(&fine).is_empty(), "{:?}", problems(&fine));
+    }
+
+    #[test]
+    fn numbers_must_parse() {
+        assert_eq!(
+            problems(&[("PORT", "eighty"), ("MAX_TEAMS", "-1")]),
+            [
+                "PORT must be a non-negative number, got \"eighty\"",
+                "MAX_TEAMS must be a non-negative number, got \"-1\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn validation_errors_must_be_sent_with_a_4xx() {
+        assert_eq!(
+            problems(&[("VALIDATION_ERROR_STATUS", "500")]),
+            ["VALIDATION_ERROR_STATUS must be a 4xx status, got \"500\""]
+        );
+        assert_eq!(problems(&[("VALIDATION_ERROR_STATUS", "399")]).len(), 1);
+        assert!(problems(&[("VALIDATION_ERROR_STATUS", "400")]).is_empty());
+        assert!(problems(&[("VALIDATION_ERROR_STATUS", "499")]).is_empty());
+    }
+
+    #[test]
+    fn route_timeouts_must_be_path_ms_pairs() {
+        for bad in ["/slow", "/slow=soon", "slow=100", "/fast=5,/slow"] {
+            let found = problems(&[("ROUTE_TIMEOUTS", bad)]);
+            assert_eq!(found.len(), 1, "{:?}", bad);
+            assert!(found[0].contains("must look like /path=ms"), "{:?}: {:?}", bad, found);
+        }
+    }
+
+    #[test]
+    fn cors_credentials_need_listed_origins() {
+        let listed = "https://a.example, *";
+        let found = problems(&[("CORS_ALLOW_CREDENTIALS", "true"), ("CORS_ALLOWED_ORIGINS", listed)]);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("CORS_ALLOW_CREDENTIALS can't be combined with CORS_ALLOWED_ORIGINS=*"));
+        assert_eq!(problems(&[("CORS_ALLOW_CREDENTIALS", "1"), ("CORS_ALLOWED_ORIGINS", "*")]).len(), 1);
+
+        assert!(problems(&[("CORS_ALLOW_CREDENTIALS", "true"), ("CORS_ALLOWED_ORIGINS", "https://a.example")])
+            .is_empty());
+        assert!(problems(&[("CORS_ALLOW_CREDENTIALS", "false"), ("CORS_ALLOWED_ORIGINS", "*")]).is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let found = problems(&[("PORT", "x"), ("VALIDATION_ERROR_STATUS", "500"), ("ROUTE_TIMEOUTS", "/x")]);
+        assert_eq!(found.len(), 3, "{:?}", found);
+    }
+}
@@ -0,0 +1,219 @@
+use std::env;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderValue, Method};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::config::{env_flag, env_or};
+
+/// the origin list that allows any origin
+pub const ANY_ORIGIN: &str = "*";
+
+#[derive(Debug)]
+struct Policy {
+    origins: Vec<String>,
+    max_age: u64,
+    credentials: bool,
+}
+
+impl Policy {
+    fn any_origin(&self) -> bool {
+        self.origins.iter().any(|o| o == ANY_ORIGIN)
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.any_origin() || self.origins.iter().any(|o| o == origin)
+    }
+
+    /// `*` only goes out for a wildcard policy without credentials; otherwise
+    /// the request's own origin is echoed back
+    fn allow_origin(&self, origin: &HeaderValue) -> HeaderValue {
+        if self.any_origin() && !self.credentials {
+            HeaderValue::from_static(ANY_ORIGIN)
+        } else {
+            origin.clone()
+        }
+    }
+}
+
+/// the origins in a `CORS_ALLOWED_ORIGINS` value
+pub fn origins(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|o| !o.is_empty())
+}
+
+/// cross-origin access for browsers, from the `CORS_ALLOWED_ORIGINS` comma
+/// list (or `*`); off while that is unset. Preflights are answered here with
+/// `204`, cached by the browser for `CORS_MAX_AGE_SECS` (60 by default), and
+/// `CORS_ALLOW_CREDENTIALS` lets cookies and auth headers through, off by
+/// default. Credentials with a `*` origin are refused at startup.
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    policy: Option<Arc<Policy>>,
+}
+
+impl Cors {
+    pub fn from_env() -> Self {
+        let origins: Vec<String> = origins(&env::var("CORS_ALLOWED_ORIGINS").unwrap_or_default())
+            .map(str::to_string)
+            .collect();
+        if origins.is_empty() {
+            return Cors::default();
+        }
+        let policy = Policy {
+            origins,
+            max_age: env_or("CORS_MAX_AGE_SECS", 60),
+            credentials: env_flag("CORS_ALLOW_CREDENTIALS"),
+        };
+        Cors { policy: Some(Arc::new(policy)) }
+    }
+}
+
+impl<S, B> Transform<S> for Cors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware { service, policy: self.policy.clone() })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    policy: Option<Arc<Policy>>,
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let origin = req.headers().get(header::ORIGIN).cloned();
+        let (policy, origin) = match (&self.policy, origin) {
+            (Some(policy), Some(origin)) if origin.to_str().is_ok_and(|o| policy.allows(o)) => {
+                (policy.clone(), origin)
+            }
+            _ => return Box::pin(self.service.call(req)),
+        };
+
+        let requested = req.headers().get(header::ACCESS_CONTROL_REQUEST_METHOD).cloned();
+        if let (&Method::OPTIONS, Some(method)) = (req.method(), requested) {
+            let mut res = HttpResponse::NoContent();
+            res.header(header::ACCESS_CONTROL_ALLOW_ORIGIN, policy.allow_origin(&origin))
+                .header(header::ACCESS_CONTROL_ALLOW_METHODS, method)
+                .header(header::ACCESS_CONTROL_MAX_AGE, policy.max_age.to_string())
+                .header(header::VARY, "Origin");
+            if let Some(headers) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+                res.header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers.clone());
+            }
+            if policy.credentials {
+                res.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+            }
+            return Box::pin(ok(req.into_response(res.finish().into_body())));
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let headers = res.headers_mut();
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, policy.allow_origin(&origin));
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            if policy.credentials {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App};
+
+    use super::*;
+
+    fn cors(origins: &[&str], max_age: u64, credentials: bool) -> Cors {
+        let origins = origins.iter().map(|o| o.to_string()).collect();
+        Cors { policy: Some(Arc::new(Policy { origins, max_age, credentials })) }
+    }
+
+    /// the status and headers `cors` answers `req` with, in front of a route that takes
+    /// any method
+    async fn answer(cors: Cors, req: TestRequest) -> (StatusCode, header::HeaderMap) {
+        let app = App::new().wrap(cors).route("/teams", web::to(HttpResponse::Ok));
+        let mut app = test::init_service(app).await;
+        let res = test::call_service(&mut app, req.uri("/teams").to_request()).await;
+        (res.status(), res.headers().clone())
+    }
+
+    fn preflight(origin: &str) -> TestRequest {
+        TestRequest::with_uri("/teams")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type, x-api-key")
+    }
+
+    #[actix_rt::test]
+    async fn preflights_are_cached_for_max_age_and_allow_credentials_when_set() {
+        let allowed = cors(&["https://a.example"], 600, true);
+        let (status, headers) = answer(allowed, preflight("https://a.example")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://a.example");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "POST");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "content-type, x-api-key");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert_eq!(headers.get(header::VARY).unwrap(), "Origin");
+
+        let (status, headers) = answer(cors(&["*"], 60, false), preflight("https://b.example")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "60");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[actix_rt::test]
+    async fn responses_to_allowed_origins_carry_the_credentials_header() {
+        let req = TestRequest::get().header(header::ORIGIN, "https://a.example");
+        let (status, headers) = answer(cors(&["https://a.example"], 60, true), req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://a.example");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_MAX_AGE));
+    }
+
+    #[actix_rt::test]
+    async fn other_origins_get_no_cors_headers() {
+        let allowed = cors(&["https://a.example"], 600, true);
+        let (status, headers) = answer(allowed, preflight("https://c.example")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_MAX_AGE));
+    }
+}
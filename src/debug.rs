@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    web::{self, BytesMut},
+    Error, HttpRequest, HttpResponse,
+};
+use futures::future::ready;
+use futures::StreamExt;
+use openssl::sha::sha256;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{Config, SENSITIVE_HEADERS};
+use crate::error::ApiError;
+use crate::teams::Team;
+
+/// a header value as shown by `/debug/headers`; values that aren't visible
+/// ascii are shown lossily rather than dropped
+fn shown(value: &[u8]) -> Value {
+    Value::String(String::from_utf8_lossy(value).into_owned())
+}
+
+/// the inbound request headers as a json object sorted by name, with repeated
+/// headers as arrays. Credential headers are redacted unless
+/// `DEBUG_SENSITIVE_HEADERS` is set.
+pub async fn echo_headers(
+    req: HttpRequest,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let headers = req.headers();
+    let res: BTreeMap<&str, Value> = headers
+        .keys()
+        .map(|name| {
+            let redact = SENSITIVE_HEADERS.contains(name) && !config.debug_sensitive_headers;
+            let mut values: Vec<Value> = headers
+                .get_all(name)
+                .map(|v| if redact { Value::from("[redacted]") } else { shown(v.as_bytes()) })
+                .collect();
+            let value = if values.len() == 1 { values.remove(0) } else { Value::Array(values) };
+            (name.as_str(), value)
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct RawBody {
+    length: usize,
+    sha256: String,
+}
+
+/// the length and sha-256 of the raw request body, read chunk by chunk so
+/// anything, json or not, can be checked for integrity through a proxy.
+/// Bodies over `MAX_BODY_BYTES` are refused with `413`.
+pub async fn echo_raw(
+    mut payload: web::Payload,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > config.max_body_bytes {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "request body must be at most {} bytes",
+                config.max_body_bytes
+            ))
+            .into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let digest = sha256(&body);
+    let res = RawBody {
+        length: body.len(),
+        sha256: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the most teams `/debug/bench/serialize` will build
+const BENCH_MAX: usize = 100_000;
+
+#[derive(Debug, Deserialize)]
+pub struct BenchQuery {
+    n: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct BenchResult {
+    n: usize,
+    millis: f64,
+    bytes: usize,
+}
+
+/// time serializing `?n=` synthetic teams (1000 by default, at most
+/// `BENCH_MAX`) to json. Only exists with `DEBUG_ENDPOINTS`.
+pub async fn bench_serialize(
+    query: web::Query<BenchQuery>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !config.debug_endpoints {
+        return Err(ApiError::NotFound("debug endpoints are disabled".to_string()).into());
+    }
+    let n = query.n.unwrap_or(1000);
+    if n > BENCH_MAX {
+        return Err(ApiError::BadRequest(format!("n must be at most {}", BENCH_MAX)).into());
+    }
+
+    let teams: Vec<Team> = (0..n).map(Team::synthetic).collect();
+    let started = Instant::now();
+    let json = serde_json::to_vec(&teams)?;
+    let elapsed = started.elapsed();
+    let res = BenchResult { n, millis: elapsed.as_secs_f64() * 1000.0, bytes: json.len() };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the counters behind `/debug/flaky`, one per `fail_times`, shared by every
+/// worker
+#[derive(Debug, Default)]
+pub struct Flaky {
+    failures: Mutex<HashMap<u32, u32>>,
+}
+
+impl Flaky {
+    /// fail the first `fail_times` calls, then let one through and start over
+    fn call(&self, fail_times: u32) -> Result<(), ApiError> {
+        let mut failures = self
+            .failures
+            .lock()
+            .map_err(|_| ApiError::Internal("flaky counters are unavailable".to_string()))?;
+        let failed = failures.entry(fail_times).or_default();
+        if *failed < fail_times {
+            *failed += 1;
+            return Err(ApiError::Upstream(format!("flaky failure {} of {}", failed, fail_times)));
+        }
+        failures.remove(&fail_times);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlakyQuery {
+    #[serde(default)]
+    fail_times: u32,
+    #[serde(default)]
+    retry: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct FlakyResult {
+    fail_times: u32,
+    attempts: u32,
+}
+
+/// answer `502` for the first `?fail_times=` calls with that value, then
+/// `200`, after which the counter for that value starts over. With
+/// `?retry=true` the calls go through the `STEP_RETRIES` backoff in-process,
+/// so the retries can be checked deterministically without any network.
+/// Only exists with `DEBUG_ENDPOINTS`.
+pub async fn flaky(
+    query: web::Query<FlakyQuery>,
+    flaky: web::Data<Flaky>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !config.debug_endpoints {
+        return Err(ApiError::NotFound("debug endpoints are disabled".to_string()).into());
+    }
+    let fail_times = query.fail_times;
+    let (result, attempts) = if query.retry {
+        config.step_retry.run(|| ready(flaky.call(fail_times))).await
+    } else {
+        (flaky.call(fail_times), 1)
+    };
+    result?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&FlakyResult { fail_times, attempts })?))
+}
+
+/// per-request stand-ins for some settings, for trying out code paths without
+/// a restart. They're only read with `DEBUG_ENDPOINTS`; otherwise the headers
+/// are ignored entirely. Exactly these are overridable:
+///
+/// - `X-Debug-Offline: true` answers the httpbin-backed endpoints as
+///   `OFFLINE_MODE` would (it can't turn a configured offline mode off)
+/// - `X-Debug-Timeout-Ms: N` gives each httpbin call N milliseconds instead
+///   of `CLIENT_TIMEOUT_SECS`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Overrides {
+    pub offline: bool,
+    pub client_timeout: Option<Duration>,
+}
+
+impl Overrides {
+    /// the overrides `req` asks for, none at all without `DEBUG_ENDPOINTS`; a
+    /// value that doesn't parse is a `400`, so a typo isn't mistaken for the
+    /// setting having no effect
+    pub fn of(req: &HttpRequest, config: &Config) -> Result<Self, ApiError> {
+        if !config.debug_endpoints {
+            return Ok(Overrides::default());
+        }
+        let header = |name: &str| -> Result<Option<&str>, ApiError> {
+            req.headers()
+                .get(name)
+                .map(|v| v.to_str().map(str::trim))
+                .transpose()
+                .map_err(|_| ApiError::BadRequest(format!("{} must be visible ascii", name)))
+        };
+        let offline = match header("x-debug-offline")? {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => {
+                let message = format!("X-Debug-Offline must be true or false, got {:?}", other);
+                return Err(ApiError::BadRequest(message));
+            }
+        };
+        let client_timeout = header("x-debug-timeout-ms")?
+            .map(|ms| {
+                ms.parse().map(Duration::from_millis).map_err(|_| {
+                    let message = format!("X-Debug-Timeout-Ms must be a number of milliseconds, got {:?}", ms);
+                    ApiError::BadRequest(message)
+                })
+            })
+            .transpose()?;
+        Ok(Overrides { offline, client_timeout })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn overrides(debug_endpoints: bool, headers: &[(&str, &str)]) -> Result<Overrides, ApiError> {
+        let mut config = Config::from_env();
+        config.debug_endpoints = debug_endpoints;
+        let req = headers.iter().fold(TestRequest::default(), |req, (name, value)| req.header(*name, *value));
+        Overrides::of(&req.to_http_request(), &config)
+    }
+
+    #[test]
+    fn overrides_apply_with_debug_endpoints() {
+        let on = overrides(true, &[("X-Debug-Offline", "true"), ("X-Debug-Timeout-Ms", " 250 ")]).unwrap();
+        assert!(on.offline);
+        assert_eq!(on.client_timeout, Some(Duration::from_millis(250)));
+
+        let off = overrides(true, &[("X-Debug-Offline", "false")]).unwrap();
+        assert!(!off.offline);
+        assert_eq!(off.client_timeout, None);
+
+        for (name, value) in [("X-Debug-Offline", "yes"), ("X-Debug-Timeout-Ms", "soon")] {
+            assert_eq!(overrides(true, &[(name, value)]).unwrap_err().code(), "bad_request", "{}", name);
+        }
+    }
+
+    #[test]
+    fn overrides_are_ignored_without_debug_endpoints() {
+        let headers = [("X-Debug-Offline", "true"), ("X-Debug-Timeout-Ms", "250")];
+        let ignored = overrides(false, &headers).unwrap();
+        assert!(!ignored.offline);
+        assert_eq!(ignored.client_timeout, None);
+
+        // not even checked, let alone refused
+        let garbage = [("X-Debug-Offline", "yes"), ("X-Debug-Timeout-Ms", "soon")];
+        let ignored = overrides(false, &garbage).unwrap();
+        assert!(!ignored.offline);
+        assert_eq!(ignored.client_timeout, None);
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+
+use crate::config::env_or;
+use crate::store;
+
+type Waiters<V> = Arc<Mutex<Vec<oneshot::Sender<V>>>>;
+
+struct Flight<V> {
+    started: Instant,
+    waiters: Waiters<V>,
+}
+
+/// single-flight for identical requests, shared by every worker: while one
+/// request for a key is running, an identical one that arrives within
+/// `window` of it waits for its result instead of doing the same work again.
+/// Requests are only identical when their whole keys are equal, not just a
+/// hash of them. A window of 0 turns it off.
+pub struct SingleFlight<K, V> {
+    flights: Mutex<HashMap<K, Flight<V>>>,
+    window: Duration,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new(window: Duration) -> Self {
+        SingleFlight { flights: Mutex::new(HashMap::new()), window }
+    }
+
+    /// joining for `DEDUP_WINDOW_MS` (0, off, by default)
+    pub fn from_env() -> Self {
+        SingleFlight::new(Duration::from_millis(env_or("DEDUP_WINDOW_MS", 0)))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.window > Duration::from_millis(0)
+    }
+
+    /// the result of `work` for `key`, and whether it was another request's.
+    /// When the request being waited for goes away before it has a result
+    /// (its client disconnected, say), or the flights can't be looked up, the
+    /// wait falls back to doing `work`.
+    pub async fn run<F: Future<Output = V>>(&self, key: K, work: F) -> (V, bool) {
+        match self.join(key) {
+            Some(Ok(leader)) => {
+                let value = work.await;
+                leader.finish(&value);
+                (value, false)
+            }
+            Some(Err(result)) => match result.await {
+                Ok(value) => (value, true),
+                Err(_) => (work.await, false),
+            },
+            None => (work.await, false),
+        }
+    }
+
+    /// lead a new flight for `key`, or the receiving end of one still open
+    /// for joining; `None` when a lock was poisoned
+    fn join(&self, key: K) -> Option<Result<Leader<'_, K, V>, oneshot::Receiver<V>>> {
+        let mut flights = store::lock(&self.flights, "dedup flights").ok()?;
+        if let Some(flight) = flights.get(&key).filter(|f| f.started.elapsed() <= self.window) {
+            let (tx, rx) = oneshot::channel();
+            store::lock(&flight.waiters, "dedup waiters").ok()?.push(tx);
+            return Some(Err(rx));
+        }
+        let waiters = Waiters::default();
+        flights.insert(key.clone(), Flight { started: Instant::now(), waiters: waiters.clone() });
+        Some(Ok(Leader { flights: self, key, waiters }))
+    }
+}
+
+/// the request doing the work for a flight. Dropping it unfinished closes
+/// the flight, and its waiters see the channel cancelled.
+struct Leader<'a, K: Eq + Hash, V> {
+    flights: &'a SingleFlight<K, V>,
+    key: K,
+    waiters: Waiters<V>,
+}
+
+impl<K: Eq + Hash, V: Clone> Leader<'_, K, V> {
+    fn finish(self, value: &V) {
+        // close the flight first so nobody joins after the result went out
+        self.close();
+        if let Ok(mut waiters) = store::lock(&self.waiters, "dedup waiters") {
+            for waiter in waiters.drain(..) {
+                let _ = waiter.send(value.clone());
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Leader<'_, K, V> {
+    /// forget the flight, unless a later one past the window replaced it
+    fn close(&self) {
+        if let Ok(mut flights) = store::lock(&self.flights.flights, "dedup flights") {
+            if flights.get(&self.key).is_some_and(|f| Arc::ptr_eq(&f.waiters, &self.waiters)) {
+                flights.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hasher;
+
+    use actix_rt::time::delay_for;
+    use futures::future::join;
+
+    use super::*;
+
+    /// keys that all hash the same, so only comparing them tells them apart
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Colliding(&'static str);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write_u8(0);
+        }
+    }
+
+    async fn slow(value: u32) -> u32 {
+        delay_for(Duration::from_millis(50)).await;
+        value
+    }
+
+    #[actix_rt::test]
+    async fn identical_keys_share_a_result() {
+        let flights = SingleFlight::new(Duration::from_secs(5));
+        let (first, second) =
+            join(flights.run(Colliding("a"), slow(1)), flights.run(Colliding("a"), slow(2))).await;
+        assert_eq!((first, second), ((1, false), (1, true)));
+    }
+
+    #[actix_rt::test]
+    async fn keys_whose_hashes_collide_do_not_share() {
+        let flights = SingleFlight::new(Duration::from_secs(5));
+        let (first, second) =
+            join(flights.run(Colliding("a"), slow(1)), flights.run(Colliding("b"), slow(2))).await;
+        assert_eq!((first, second), ((1, false), (2, false)));
+    }
+
+    #[actix_rt::test]
+    async fn finished_flights_are_not_joined() {
+        let flights = SingleFlight::new(Duration::from_secs(5));
+        assert_eq!(flights.run(Colliding("a"), slow(1)).await, (1, false));
+        assert_eq!(flights.run(Colliding("a"), slow(2)).await, (2, false));
+    }
+}
@@ -0,0 +1,160 @@
+use std::env;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderName, HeaderValue};
+use actix_web::Error;
+use chrono::NaiveDate;
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+
+/// the routes being retired
+const PREFIX: &str = "/api/v0";
+
+const WARNING: &str = "299 - \"/api/v0 is deprecated; use /api/v1 instead\"";
+
+/// the `API_V0_SUNSET` date, `YYYY-MM-DD`, as the http date `Sunset` wants
+pub fn sunset(value: &str) -> Option<String> {
+    let date = NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+/// mark every `/api/v0` response deprecated in favour of `/api/v1`: a
+/// `Deprecation: true` header, a `Warning` pointing at v1, and with
+/// `API_V0_SUNSET` set a `Sunset` header saying when v0 goes away. Other
+/// responses, v1 included, are left alone.
+#[derive(Debug, Clone, Default)]
+pub struct Deprecation {
+    sunset: Option<HeaderValue>,
+}
+
+impl Deprecation {
+    pub fn from_env() -> Self {
+        let sunset = env::var("API_V0_SUNSET")
+            .ok()
+            .and_then(|value| sunset(&value))
+            .and_then(|date| HeaderValue::from_str(&date).ok());
+        Deprecation { sunset }
+    }
+}
+
+fn deprecated(path: &str) -> bool {
+    path.strip_prefix(PREFIX).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl<S, B> Transform<S> for Deprecation
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = DeprecationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(DeprecationMiddleware { service, sunset: self.sunset.clone() })
+    }
+}
+
+pub struct DeprecationMiddleware<S> {
+    service: S,
+    sunset: Option<HeaderValue>,
+}
+
+impl<S, B> Service for DeprecationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, LocalBoxFuture<'static, Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !deprecated(req.path()) {
+            return Either::Left(self.service.call(req));
+        }
+        let sunset = self.sunset.clone();
+        let fut = self.service.call(req);
+        Either::Right(Box::pin(async move {
+            let mut res = fut.await?;
+            let headers = res.headers_mut();
+            headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+            headers.insert(header::WARNING, HeaderValue::from_static(WARNING));
+            if let Some(sunset) = sunset {
+                headers.insert(HeaderName::from_static("sunset"), sunset);
+            }
+            Ok(res)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App};
+
+    use super::*;
+    use crate::teams::{self, AreaRegexes};
+    use crate::test_support;
+
+    #[test]
+    fn sunset_dates_are_written_as_http_dates() {
+        assert_eq!(sunset("2027-03-31").as_deref(), Some("Wed, 31 Mar 2027 00:00:00 GMT"));
+        assert_eq!(sunset(" 2027-03-31 ").as_deref(), Some("Wed, 31 Mar 2027 00:00:00 GMT"));
+        assert_eq!(sunset("2027-02-30"), None);
+        assert_eq!(sunset("31/03/2027"), None);
+    }
+
+    #[actix_rt::test]
+    async fn only_v0_responses_are_marked_deprecated() {
+        let config = test_support::config();
+        let sunset = sunset("2027-03-31").and_then(|date| HeaderValue::from_str(&date).ok());
+        let app = App::new()
+            .wrap(Deprecation { sunset })
+            .app_data(web::Data::new(config.clone()))
+            .app_data(test_support::seeded_store(&config))
+            .app_data(web::Data::new(AreaRegexes::new(64, Duration::from_secs(3600))))
+            .route("/api/v0/teams", web::get().to(teams::all_teams))
+            .route("/api/v1/teams", web::get().to(crate::v1::all_teams));
+        let mut app = test::init_service(app).await;
+
+        let res = test::call_service(&mut app, TestRequest::get().uri("/api/v0/teams").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(headers.get("deprecation").unwrap(), "true");
+        assert_eq!(headers.get("sunset").unwrap(), "Wed, 31 Mar 2027 00:00:00 GMT");
+        assert_eq!(headers.get(header::WARNING).unwrap(), WARNING);
+        let v0 = test_support::json(&test::read_body(res).await);
+        assert_eq!(v0[0]["active_area"], "茨城県");
+
+        let res = test::call_service(&mut app, TestRequest::get().uri("/api/v1/teams").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        for name in ["deprecation", "sunset", "warning"] {
+            assert!(!res.headers().contains_key(name), "v1 sent {}", name);
+        }
+        let v1 = test_support::json(&test::read_body(res).await);
+        assert_eq!(v1[0]["area"]["name"], "茨城県");
+    }
+
+    #[test]
+    fn deprecated_paths_are_v0_and_below() {
+        assert!(deprecated("/api/v0"));
+        assert!(deprecated("/api/v0/teams"));
+        assert!(!deprecated("/api/v0teams"));
+        assert!(!deprecated("/api/v1/teams"));
+        assert!(!deprecated("/shami_momo"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::future::Future;
+
+use actix_web::dev::{AppService, Factory, HttpServiceFactory};
+use actix_web::http::{header, Method};
+use actix_web::{web, Error, FromRequest, HttpResponse, Resource, Responder};
+use futures::future::ready;
+use serde::Serialize;
+
+/// a resource that keeps track of the methods it has routes for, so that
+/// `OPTIONS` and the `405` for any other method can answer with an accurate
+/// `Allow` header. A `GET` route answers `HEAD` too.
+pub struct Endpoint {
+    path: String,
+    resource: Resource,
+    allow: Vec<Method>,
+}
+
+/// one mounted path and the methods it answers
+#[derive(Debug, Serialize)]
+pub struct RouteInfo {
+    pub path: String,
+    pub methods: Vec<String>,
+}
+
+pub type RouteTable = Vec<RouteInfo>;
+
+impl Endpoint {
+    pub fn new(path: &str) -> Self {
+        Endpoint { path: path.to_string(), resource: web::resource(path), allow: Vec::new() }
+    }
+
+    /// serve `method` with `handler`
+    pub fn route<F, T, R, U>(mut self, method: Method, handler: F) -> Self
+    where
+        F: Factory<T, R, U>,
+        T: FromRequest + 'static,
+        R: Future<Output = U> + 'static,
+        U: Responder + 'static,
+    {
+        if method == Method::GET {
+            self = self.route(Method::HEAD, handler.clone());
+        }
+        self.resource = self.resource.route(web::method(method.clone()).to(handler));
+        self.allow.push(method);
+        self
+    }
+
+    /// the methods `Allow` lists, `OPTIONS` last
+    fn methods(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.allow.iter().map(|m| m.as_str().to_string()).collect();
+        names.sort_unstable();
+        names.push("OPTIONS".to_string());
+        names
+    }
+
+    fn allow_header(&self) -> String {
+        self.methods().join(", ")
+    }
+
+    pub fn describe(&self) -> RouteInfo {
+        RouteInfo { path: self.path.clone(), methods: self.methods() }
+    }
+}
+
+impl HttpServiceFactory for Endpoint {
+    fn register(self, config: &mut AppService) {
+        let allow = self.allow_header();
+        let options = allow.clone();
+        self.resource
+            .route(web::method(Method::OPTIONS).to(move || {
+                ready(HttpResponse::NoContent().header(header::ALLOW, options.as_str()).finish())
+            }))
+            .default_service(web::route().to(move || {
+                ready(HttpResponse::MethodNotAllowed().header(header::ALLOW, allow.as_str()).finish())
+            }))
+            .register(config)
+    }
+}
+
+/// `GET /api/v0/routes`: every mounted path with the methods it answers
+pub async fn list_routes(table: web::Data<RouteTable>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().content_type("application/json").body(serde_json::to_string(table.get_ref())?))
+}
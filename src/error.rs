@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use actix_web::error::JsonPayloadError;
+use actix_web::{http::header, http::StatusCode, Error, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::Value;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::env_or;
+use crate::locale::{self, Lang};
+
+/// the status validation failures are answered with, `VALIDATION_ERROR_STATUS`
+/// (400 by default; 422 is the usual alternative). Read once, the first time
+/// it's needed; `validate_env` has already checked it's a 4xx.
+fn validation_status() -> StatusCode {
+    static STATUS: OnceLock<StatusCode> = OnceLock::new();
+    *STATUS.get_or_init(|| {
+        StatusCode::from_u16(env_or("VALIDATION_ERROR_STATUS", 400))
+            .ok()
+            .filter(StatusCode::is_client_error)
+            .unwrap_or(StatusCode::BAD_REQUEST)
+    })
+}
+
+/// errors returned by the api handlers, rendered as a json body
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    /// the submitted data failed validation; reported per field, with
+    /// `VALIDATION_ERROR_STATUS`
+    Validation(ValidationErrors),
+    /// the request lacks valid credentials
+    Unauthorized(String),
+    /// the requested resource doesn't exist
+    NotFound(String),
+    /// the requested resource existed but was deleted; carries when, which
+    /// is sent as `deleted_at`
+    Gone(String, String),
+    /// the client accepts none of the media types the api produces
+    NotAcceptable(String),
+    /// the resource being created already exists
+    Conflict(String),
+    /// a conditional request's precondition (e.g. `If-None-Match: *`) didn't
+    /// hold
+    PreconditionFailed(String),
+    /// the store already holds as many items as it is allowed to
+    StoreFull(String),
+    /// the request was malformed in a way not tied to a single field
+    BadRequest(String),
+    /// the request was well-formed but the data it names can't be used for it
+    Unprocessable(String),
+    /// the request uri exceeded a configured limit
+    UriTooLong(String),
+    /// the request body, or the response it asks for, exceeded a configured
+    /// limit
+    PayloadTooLarge(String),
+    /// the endpoint can't serve requests right now
+    Unavailable(String),
+    /// something went wrong on our side
+    Internal(String),
+    /// the upstream (httpbin) call failed or returned something unusable
+    Upstream(String),
+    /// the upstream answered `429 Too Many Requests`; carries its
+    /// `Retry-After`, which is passed on to the client
+    TooManyRequests(String, Option<String>),
+    /// the handler didn't respond within its deadline
+    Timeout(String),
+    /// the client didn't finish sending the request in time
+    RequestTimeout(String),
+}
+
+impl ApiError {
+    /// stable, machine-readable code; also the key into the message catalog
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Gone(..) => "gone",
+            ApiError::NotAcceptable(_) => "not_acceptable",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::StoreFull(_) => "store_full",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unprocessable(_) => "unprocessable",
+            ApiError::UriTooLong(_) => "uri_too_long",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::TooManyRequests(..) => "too_many_requests",
+            ApiError::Timeout(_) => "timeout",
+            ApiError::RequestTimeout(_) => "request_timeout",
+        }
+    }
+
+    /// the json error response with messages in `lang`
+    pub fn render(&self, lang: Lang) -> HttpResponse {
+        let message = match locale::message(self.code(), lang) {
+            Some(m) => m.to_string(),
+            None => self.to_string(),
+        };
+        let errors = match self {
+            ApiError::Validation(e) => Some(field_errors(e, lang)),
+            _ => None,
+        };
+
+        let mut res = HttpResponse::build(self.status_code());
+        if let ApiError::TooManyRequests(_, Some(retry_after)) = self {
+            res.header(header::RETRY_AFTER, retry_after.as_str());
+        }
+        let deleted_at = match self {
+            ApiError::Gone(_, deleted_at) => Some(deleted_at.as_str()),
+            _ => None,
+        };
+        res.json(ErrorBody {
+            code: self.code(),
+            error: &message,
+            errors,
+            deleted_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    error: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<HashMap<&'static str, Vec<FieldError>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<&'a str>,
+}
+
+/// one way a field failed validation: a stable `code` to branch on and a
+/// message in the requested language
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub code: Cow<'static, str>,
+    pub message: String,
+}
+
+/// how many characters or items a `length` check measured: its `length`
+/// param when it reports one (`SomeData`'s, which may count grapheme
+/// clusters), otherwise the `char`s or items of its `value` as `validator`
+/// counts them
+fn measured_length(e: &ValidationError) -> Option<f64> {
+    if let Some(length) = e.params.get("length").and_then(Value::as_f64) {
+        return Some(length);
+    }
+    match e.params.get("value")? {
+        Value::String(s) => Some(s.chars().count() as f64),
+        Value::Array(items) => Some(items.len() as f64),
+        _ => None,
+    }
+}
+
+/// the code a client sees for `e`: `length` and `range` failures say which
+/// bound was crossed (`too_short`/`too_long`, `too_small`/`too_large`), other
+/// `validator` codes pass through
+fn field_code(e: &ValidationError) -> Cow<'static, str> {
+    let bound = |name: &str| e.params.get(name).and_then(Value::as_f64);
+    let (low, high, measured) = match &*e.code {
+        "length" => ("too_short", "too_long", measured_length(e)),
+        "range" => ("too_small", "too_large", e.params.get("value").and_then(Value::as_f64)),
+        _ => return e.code.clone(),
+    };
+    match measured {
+        Some(n) if bound("min").is_some_and(|min| n < min) => Cow::from(low),
+        Some(n) if bound("max").is_some_and(|max| n > max) => Cow::from(high),
+        _ => e.code.clone(),
+    }
+}
+
+fn field_error(e: ValidationError, lang: Lang) -> FieldError {
+    let code = field_code(&e);
+    let message = match locale::message(&code, lang).or_else(|| locale::message(&e.code, lang)) {
+        Some(m) => m.to_string(),
+        None => e.message.unwrap_or(e.code).into_owned(),
+    };
+    FieldError { code, message }
+}
+
+/// validation errors per field, with messages in `lang`
+pub fn field_errors(errors: &ValidationErrors, lang: Lang) -> HashMap<&'static str, Vec<FieldError>> {
+    errors
+        .clone()
+        .inner()
+        .into_iter()
+        .map(|(field, errs)| {
+            let errors = errs.into_iter().map(|e| field_error(e, lang)).collect();
+            (field, errors)
+        })
+        .collect()
+}
+
+/// the `JsonConfig` error handler: a json body that doesn't deserialize is a
+/// `400` saying why, rather than one with an empty body
+pub fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> Error {
+    match err {
+        JsonPayloadError::Deserialize(e) => ApiError::BadRequest(e.to_string()).into(),
+        err => err.into(),
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Validation(_) => write!(f, "validation failed"),
+            ApiError::Unauthorized(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::Gone(msg, _)
+            | ApiError::NotAcceptable(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::PreconditionFailed(msg)
+            | ApiError::StoreFull(msg)
+            | ApiError::BadRequest(msg)
+            | ApiError::Unprocessable(msg)
+            | ApiError::UriTooLong(msg)
+            | ApiError::PayloadTooLarge(msg)
+            | ApiError::Unavailable(msg)
+            | ApiError::Internal(msg)
+            | ApiError::Upstream(msg)
+            | ApiError::TooManyRequests(msg, _)
+            | ApiError::Timeout(msg)
+            | ApiError::RequestTimeout(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => validation_status(),
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Gone(..) => StatusCode::GONE,
+            ApiError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::StoreFull(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::UriTooLong(_) => StatusCode::URI_TOO_LONG,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::RequestTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.render(Lang::En)
+    }
+}
@@ -0,0 +1,409 @@
+//! `GET /api/v0/export` writes the whole dataset as one json document,
+//! `{"teams":[...],"news":[...]}`. The stores are snapshotted up front, then
+//! the document is serialized and sent an item at a time, and with
+//! `?compress=gzip` compressed on the way out, so neither the json nor the
+//! gzip output is ever held in memory in full. A `Range` request is the
+//! exception: the document is built in full so the byte range can be cut out
+//! of it, and as the same data always serializes to the same bytes, ranges
+//! stay stable for as long as the data doesn't change. Its `ETag` can be sent
+//! back in `If-Range` to make sure of that.
+
+use std::io::Write;
+use std::iter;
+use std::mem;
+
+use actix_web::http::header;
+use actix_web::web::{self, Bytes};
+use actix_web::{Error, HttpRequest, HttpResponse};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{stream, Stream};
+use openssl::sha::Sha256;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+use crate::news::{News, NewsStore};
+use crate::teams::{Team, TeamStore};
+
+/// `prefix`, then `items` as the comma-separated body of a json array, each
+/// serialized only when its turn comes
+pub fn array<T: Serialize>(
+    prefix: &'static str,
+    items: Vec<T>,
+) -> impl Iterator<Item = Result<Vec<u8>, Error>> {
+    let items = items.into_iter().enumerate().map(|(i, item)| {
+        let mut chunk = if i == 0 { Vec::new() } else { b",".to_vec() };
+        serde_json::to_writer(&mut chunk, &item)?;
+        Ok(chunk)
+    });
+    iter::once(Ok(prefix.as_bytes().to_vec())).chain(items)
+}
+
+/// `items` as a complete json array, a piece at a time, for a streaming body
+pub fn json_array<T: Serialize>(items: Vec<T>) -> impl Stream<Item = Result<Bytes, Error>> {
+    let chunks = array("[", items).chain(iter::once(Ok(b"]".to_vec())));
+    stream::iter(chunks.map(|c| c.map(Bytes::from)))
+}
+
+/// the export document, a piece at a time
+fn export_chunks(teams: Vec<Team>, news: Vec<News>) -> impl Iterator<Item = Result<Vec<u8>, Error>> {
+    array("{\"teams\":[", teams)
+        .chain(array("],\"news\":[", news))
+        .chain(iter::once(Ok(b"]}".to_vec())))
+}
+
+/// `chunks` gzip-compressed, yielding compressed output as soon as the
+/// encoder produces some
+struct Gzip<I> {
+    chunks: I,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl<I: Iterator<Item = Result<Vec<u8>, Error>>> Iterator for Gzip<I> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let encoder = self.encoder.as_mut()?;
+            match self.chunks.next() {
+                Some(Ok(chunk)) => {
+                    if let Err(e) = encoder.write_all(&chunk) {
+                        return Some(Err(e.into()));
+                    }
+                    let out = mem::take(encoder.get_mut());
+                    if !out.is_empty() {
+                        return Some(Ok(out));
+                    }
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let encoder = self.encoder.take()?;
+                    return Some(encoder.finish().map_err(Error::from));
+                }
+            }
+        }
+    }
+}
+
+/// the first and last byte of what a `Range: bytes=` header asks for out of
+/// `len` bytes; `None` for a header that is ignored (malformed, or asking for
+/// several ranges) and the whole document sent, `Some(Err)` for a range that
+/// lies entirely past the end
+fn byte_range(range: &str, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        let suffix: usize = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len - 1)));
+    }
+    let start: usize = start.parse().ok()?;
+    let end = if end.is_empty() { None } else { Some(end.parse::<usize>().ok()?) };
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end.map_or(len - 1, |end| end.min(len - 1)))))
+}
+
+/// answer a `Range` request for the export: `206` with the bytes asked for,
+/// `416` for a range past the end, and the whole document when the range is
+/// ignored or `If-Range` names a different version
+fn partial_export(
+    req: &HttpRequest,
+    range: &str,
+    chunks: impl Iterator<Item = Result<Vec<u8>, Error>>,
+) -> Result<HttpResponse, Error> {
+    let mut body = Vec::new();
+    for chunk in chunks {
+        body.extend_from_slice(&chunk?);
+    }
+    let hex: String = openssl::sha::sha256(&body).iter().map(|b| format!("{:02x}", b)).collect();
+    let etag = format!("\"{}\"", hex);
+    let current = req
+        .headers()
+        .get(header::IF_RANGE)
+        .is_none_or(|tag| tag.to_str().is_ok_and(|tag| tag.trim() == etag));
+
+    let mut res = match byte_range(range, body.len()).filter(|_| current) {
+        None => HttpResponse::Ok(),
+        Some(Err(())) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::CONTENT_RANGE, format!("bytes */{}", body.len()))
+                .header(header::ETAG, etag)
+                .finish());
+        }
+        Some(Ok((start, end))) => {
+            let len = body.len();
+            body = body[start..=end].to_vec();
+            let mut res = HttpResponse::PartialContent();
+            res.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len));
+            res
+        }
+    };
+    Ok(res
+        .content_type("application/json")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"export.json\"")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag)
+        .body(body))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// `gzip` to compress the export
+    compress: Option<String>,
+}
+
+/// every team and news item as one json download. The uncompressed download
+/// can be fetched in parts with `Range: bytes=...`.
+pub async fn export(
+    req: HttpRequest,
+    query: web::Query<ExportQuery>,
+    teams: web::Data<TeamStore>,
+    news: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    let chunks = export_chunks(teams.all()?, news.all()?);
+    let range = req.headers().get(header::RANGE).and_then(|v| v.to_str().ok());
+    if let (None, Some(range)) = (&query.compress, range) {
+        return partial_export(&req, range, chunks);
+    }
+    let mut res = HttpResponse::Ok();
+    res.content_type("application/json")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"export.json\"");
+
+    match query.compress.as_deref() {
+        None => Ok(res
+            .header(header::ACCEPT_RANGES, "bytes")
+            .streaming(stream::iter(chunks.map(|c| c.map(Bytes::from))))),
+        Some("gzip") => {
+            let encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let gzip = Gzip { chunks, encoder: Some(encoder) };
+            Ok(res
+                .header(header::CONTENT_ENCODING, "gzip")
+                .streaming(stream::iter(gzip.map(|c| c.map(Bytes::from)))))
+        }
+        Some(other) => Err(ApiError::BadRequest(format!(
+            "unknown compression {:?}, expected gzip",
+            other
+        ))
+        .into()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Fingerprint {
+    fingerprint: String,
+    teams: usize,
+    news: usize,
+}
+
+/// the sha-256 over `items`, each serialized and then sorted, so the order
+/// they were stored in doesn't matter
+fn digest_sorted<T: Serialize>(hasher: &mut Sha256, name: &str, items: &[T]) -> Result<(), Error> {
+    let mut lines = items.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+    lines.sort_unstable();
+    hasher.update(name.as_bytes());
+    for line in lines {
+        hasher.update(b"\n");
+        hasher.update(line.as_bytes());
+    }
+    hasher.update(b"\n\n");
+    Ok(())
+}
+
+/// `GET /api/v0/fingerprint`: a sha-256 of the teams and news, the same for
+/// the same data whatever order it was added in, for telling cheaply whether
+/// anything changed. Also sent as the `ETag`, so `If-None-Match` gets a `304`.
+pub async fn fingerprint(
+    req: HttpRequest,
+    teams: web::Data<TeamStore>,
+    news: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    let (teams, news) = (teams.all()?, news.all()?);
+    let mut hasher = Sha256::new();
+    digest_sorted(&mut hasher, "teams", &teams)?;
+    digest_sorted(&mut hasher, "news", &news)?;
+    let hex: String = hasher.finish().iter().map(|b| format!("{:02x}", b)).collect();
+    let etag = format!("\"{}\"", hex);
+
+    let unchanged = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if unchanged {
+        return Ok(HttpResponse::NotModified().header(header::ETAG, etag).finish());
+    }
+
+    let res = Fingerprint { fingerprint: format!("sha256:{}", hex), teams: teams.len(), news: news.len() };
+    Ok(HttpResponse::Ok()
+        .header(header::ETAG, etag)
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::test_support::{self, call};
+
+    fn gunzip(bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        GzDecoder::new(bytes).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[actix_rt::test]
+    async fn the_gzip_export_decompresses_to_the_plain_export() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+
+        let req = TestRequest::get().uri("/api/v0/export").to_request();
+        let (status, plain) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let doc = test_support::json(&plain);
+        assert_eq!(doc["teams"], serde_json::to_value(crate::teams::seed_teams()).unwrap());
+
+        let req = TestRequest::get().uri("/api/v0/export?compress=gzip").to_request();
+        let res = actix_web::dev::Service::call(&mut app, req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        let disposition = res.headers().get(header::CONTENT_DISPOSITION).unwrap();
+        assert_eq!(disposition, "attachment; filename=\"export.json\"");
+        let gzip = actix_web::test::read_body(res).await;
+        assert_eq!(gunzip(&gzip), plain);
+
+        let req = TestRequest::get().uri("/api/v0/export?compress=brotli").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        test_support::assert_error_code(&body, "bad_request");
+    }
+
+    #[test]
+    fn gzip_output_comes_out_as_the_input_goes_in() {
+        let chunks = (0..20_000u64).map(|i| Ok(format!("{{\"n\":{},\"s\":\"{:x}\"}}", i, i * 7919).into()));
+        let plain = chunks.clone().map(Result::unwrap).collect::<Vec<_>>().concat();
+        let encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let gzip = Gzip { chunks, encoder: Some(encoder) }.map(Result::unwrap).collect::<Vec<_>>();
+        assert!(gzip.len() > 2, "all {} bytes came out at the end", plain.len());
+        assert_eq!(gunzip(&gzip.concat()), plain);
+    }
+
+    #[test]
+    fn byte_ranges_are_read_as_rfc_7233_says() {
+        assert_eq!(byte_range("bytes=0-9", 100), Some(Ok((0, 9))));
+        assert_eq!(byte_range(" bytes= 10 - 19 ", 100), Some(Ok((10, 19))));
+        // an end past the last byte stops at it, and no end means to the end
+        assert_eq!(byte_range("bytes=90-200", 100), Some(Ok((90, 99))));
+        assert_eq!(byte_range("bytes=95-", 100), Some(Ok((95, 99))));
+        // a suffix is the last N bytes, or all of them when N is more
+        assert_eq!(byte_range("bytes=-10", 100), Some(Ok((90, 99))));
+        assert_eq!(byte_range("bytes=-500", 100), Some(Ok((0, 99))));
+        // past the end, or an empty suffix
+        assert_eq!(byte_range("bytes=100-", 100), Some(Err(())));
+        assert_eq!(byte_range("bytes=100-110", 100), Some(Err(())));
+        assert_eq!(byte_range("bytes=-0", 100), Some(Err(())));
+        assert_eq!(byte_range("bytes=-5", 0), Some(Err(())));
+        // ignored: several ranges, other units, backwards or unreadable ones
+        for ignored in ["bytes=0-1,5-6", "items=0-1", "bytes=9-0", "bytes=a-b", "bytes=5", "0-9"] {
+            assert_eq!(byte_range(ignored, 100), None, "{}", ignored);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn two_ranges_put_back_together_are_the_plain_export() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let (_, plain) = call(&mut app, TestRequest::get().uri("/api/v0/export").to_request()).await;
+        let len = plain.len();
+        let ranged = |range: String| TestRequest::get().uri("/api/v0/export").header(header::RANGE, range);
+
+        let req = ranged("bytes=0-99".to_string()).to_request();
+        let res = actix_web::dev::Service::call(&mut app, req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let content_range = res.headers().get(header::CONTENT_RANGE).unwrap().to_str().unwrap();
+        assert_eq!(content_range, format!("bytes 0-99/{}", len));
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        let head = actix_web::test::read_body(res).await;
+
+        let req = ranged("bytes=100-".to_string()).header(header::IF_RANGE, etag).to_request();
+        let (status, tail) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!([&head[..], &tail[..]].concat(), &plain[..]);
+
+        let (status, suffix) = call(&mut app, ranged("bytes=-10".to_string()).to_request()).await;
+        assert_eq!((status, &suffix[..]), (StatusCode::PARTIAL_CONTENT, &plain[len - 10..]));
+
+        let req = ranged(format!("bytes={}-", len)).to_request();
+        let res = actix_web::dev::Service::call(&mut app, req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        let content_range = res.headers().get(header::CONTENT_RANGE).unwrap().to_str().unwrap();
+        assert_eq!(content_range, format!("bytes */{}", len));
+
+        // several ranges, or an If-Range for another version, get the whole export
+        let (status, body) = call(&mut app, ranged("bytes=0-1,5-6".to_string()).to_request()).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &plain[..]));
+        let req = ranged("bytes=0-99".to_string()).header(header::IF_RANGE, "\"stale\"").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &plain[..]));
+    }
+
+    /// `GET /api/v0/fingerprint` of `teams` and the seeded news, with
+    /// `If-None-Match` when given
+    async fn fingerprint_of(teams: Vec<Team>, if_none_match: Option<&str>) -> HttpResponse {
+        let req = match if_none_match {
+            Some(tags) => TestRequest::get().header(header::IF_NONE_MATCH, tags),
+            None => TestRequest::get(),
+        };
+        let teams = web::Data::new(TeamStore::new(teams, 1000, false));
+        fingerprint(req.to_http_request(), teams, web::Data::new(NewsStore::seeded())).await.unwrap()
+    }
+
+    fn etag(res: &HttpResponse) -> String {
+        res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn the_fingerprint_does_not_depend_on_insert_order() {
+        let seed = crate::teams::seed_teams();
+        let mut reversed = seed.clone();
+        reversed.reverse();
+        let (a, b) = (fingerprint_of(seed.clone(), None).await, fingerprint_of(reversed, None).await);
+        assert_eq!((a.status(), b.status()), (StatusCode::OK, StatusCode::OK));
+        assert_eq!(etag(&a), etag(&b));
+
+        let fewer = fingerprint_of(seed[1..].to_vec(), None).await;
+        assert_ne!(etag(&fewer), etag(&a));
+    }
+
+    #[actix_rt::test]
+    async fn a_matching_if_none_match_is_304() {
+        let seed = crate::teams::seed_teams;
+        let current = etag(&fingerprint_of(seed(), None).await);
+        for tags in [current.clone(), format!("\"stale\", {}", current), "*".to_string()] {
+            let res = fingerprint_of(seed(), Some(&tags)).await;
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED, "{}", tags);
+            assert_eq!(etag(&res), current);
+        }
+
+        let res = fingerprint_of(seed(), Some("\"stale\"")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(etag(&res), current);
+    }
+}
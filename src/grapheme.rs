@@ -0,0 +1,184 @@
+//! With `GRAPHEME_LENGTH` set, `SomeData`'s length limits count
+//! extended grapheme clusters, what a reader sees as one character, rather
+//! than `char`s, so `が` written as `か` plus a combining dakuten or a family
+//! emoji joined with ZWJs counts once. Without it they count `char`s as
+//! before.
+//!
+//! The segmentation is a close approximation of UAX #29 rather than all of
+//! it: combining marks from the common blocks, variation selectors, emoji
+//! modifiers and tag characters extend the cluster before them, a ZWJ joins
+//! the emoji after it, regional indicators pair up into flags and CRLF is one
+//! cluster, and Hangul jamo join into syllables as they do in UAX #29. The
+//! spacing marks of Indic scripts are still counted a `char` at a time.
+
+/// characters that never start a cluster of their own
+fn extends(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x0300..=0x036F
+            | 0x1AB0..=0x1AFF
+            | 0x1DC0..=0x1DFF
+            | 0x200D
+            | 0x20D0..=0x20FF
+            | 0x3099..=0x309A
+            | 0xFE00..=0xFE0F
+            | 0xFE20..=0xFE2F
+            | 0x1F3FB..=0x1F3FF
+            | 0xE0020..=0xE007F
+            | 0xE0100..=0xE01EF
+    )
+}
+
+fn pictographic(c: char) -> bool {
+    matches!(c as u32, 0x2600..=0x27BF | 0x1F000..=0x1FAFF)
+}
+
+fn regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+/// the Hangul syllable types UAX #29 joins on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hangul {
+    /// a leading consonant jamo
+    L,
+    /// a vowel jamo
+    V,
+    /// a trailing consonant jamo
+    T,
+    /// a precomposed syllable without a trailing consonant
+    Lv,
+    /// a precomposed syllable with one
+    Lvt,
+}
+
+fn hangul(c: char) -> Option<Hangul> {
+    match c as u32 {
+        0x1100..=0x115F | 0xA960..=0xA97C => Some(Hangul::L),
+        0x1160..=0x11A7 | 0xD7B0..=0xD7C6 => Some(Hangul::V),
+        0x11A8..=0x11FF | 0xD7CB..=0xD7FB => Some(Hangul::T),
+        c @ 0xAC00..=0xD7A3 if (c - 0xAC00) % 28 == 0 => Some(Hangul::Lv),
+        0xAC00..=0xD7A3 => Some(Hangul::Lvt),
+        _ => None,
+    }
+}
+
+/// whether `c` continues the syllable `p` ends
+fn joins_syllable(p: char, c: char) -> bool {
+    use Hangul::*;
+    matches!(
+        (hangul(p), hangul(c)),
+        (Some(L), Some(L | V | Lv | Lvt)) | (Some(Lv | V), Some(V | T)) | (Some(Lvt | T), Some(T))
+    )
+}
+
+/// the byte offsets at which `s`'s clusters start
+fn cluster_starts(s: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut prev: Option<char> = None;
+    // regional indicators in a row just before `c`, to pair them off
+    let mut indicators = 0;
+    for (i, c) in s.char_indices() {
+        let joined = match prev {
+            None => false,
+            Some('\r') => c == '\n',
+            Some('\n') => false,
+            Some(p) => {
+                extends(c)
+                    || joins_syllable(p, c)
+                    || (p == '\u{200D}' && pictographic(c))
+                    || (regional_indicator(c) && indicators % 2 == 1)
+            }
+        };
+        indicators = if regional_indicator(c) { indicators + 1 } else { 0 };
+        if !joined {
+            starts.push(i);
+        }
+        prev = Some(c);
+    }
+    starts
+}
+
+/// how long `s` is for a length check: its clusters with `graphemes`
+/// (`GRAPHEME_LENGTH`), its `char`s without
+pub fn length(s: &str, graphemes: bool) -> usize {
+    if graphemes {
+        cluster_starts(s).len()
+    } else {
+        s.chars().count()
+    }
+}
+
+/// the byte offset `s` would have to be cut at to be `max` long in the same
+/// units as `length`, or `None` when it already fits
+pub fn cut_at(s: &str, max: usize, graphemes: bool) -> Option<usize> {
+    if graphemes {
+        cluster_starts(s).get(max).copied()
+    } else {
+        s.char_indices().nth(max).map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clusters(s: &str) -> usize {
+        length(s, true)
+    }
+
+    #[test]
+    fn combining_marks_join_the_character_before_them() {
+        // か with a combining dakuten, and e with a combining acute
+        assert_eq!(clusters("か\u{3099}"), 1);
+        assert_eq!(clusters("e\u{301}e\u{301}"), 2);
+        assert_eq!(length("か\u{3099}", false), 2);
+    }
+
+    #[test]
+    fn zwj_sequences_and_modifiers_are_one_emoji() {
+        // man, woman, girl joined by ZWJs
+        assert_eq!(clusters("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"), 1);
+        // thumbs up with a skin tone
+        assert_eq!(clusters("\u{1F44D}\u{1F3FD}"), 1);
+        // a heart with the emoji variation selector
+        assert_eq!(clusters("\u{2764}\u{FE0F}"), 1);
+    }
+
+    #[test]
+    fn regional_indicators_pair_up_into_flags() {
+        // 🇯🇵🇺🇸
+        assert_eq!(clusters("\u{1F1EF}\u{1F1F5}\u{1F1FA}\u{1F1F8}"), 2);
+        // a lone third indicator is a cluster of its own
+        assert_eq!(clusters("\u{1F1EF}\u{1F1F5}\u{1F1FA}"), 2);
+    }
+
+    #[test]
+    fn hangul_jamo_join_into_syllables() {
+        // 한국 precomposed
+        assert_eq!(clusters("\u{D55C}\u{AD6D}"), 2);
+        // 각 as L V T jamo
+        assert_eq!(clusters("\u{1100}\u{1161}\u{11A8}"), 1);
+        // 가 (an LV syllable) followed by a trailing jamo
+        assert_eq!(clusters("\u{AC00}\u{11A8}"), 1);
+        // an LVT syllable takes no vowel after it
+        assert_eq!(clusters("\u{AC01}\u{1161}"), 2);
+        // two leading jamo and a vowel
+        assert_eq!(clusters("\u{1100}\u{1100}\u{1161}"), 1);
+    }
+
+    #[test]
+    fn crlf_is_one_cluster() {
+        assert_eq!(clusters("a\r\nb"), 3);
+        assert_eq!(clusters("\n\r"), 2);
+    }
+
+    #[test]
+    fn cuts_fall_between_clusters() {
+        let s = "e\u{301}x";
+        assert_eq!(cut_at(s, 1, true), Some(3));
+        assert_eq!(cut_at(s, 1, false), Some(1));
+        assert_eq!(cut_at(s, 2, true), None);
+        assert_eq!(cut_at("\u{1F468}\u{200D}\u{1F469}", 1, true), None);
+    }
+}
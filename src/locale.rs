@@ -0,0 +1,238 @@
+use std::fmt;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderMap};
+use actix_web::{http::StatusCode, Error, HttpResponse, ResponseError};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::error::ApiError;
+
+/// languages error messages can be rendered in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    En,
+    Ja,
+}
+
+impl Lang {
+    /// the preferred supported language from `Accept-Language`, by q-value
+    /// then by order; English when nothing supported is listed. `q=0` marks a
+    /// language as not acceptable, so such an entry is never picked.
+    pub fn from_headers(headers: &HeaderMap) -> Lang {
+        let accept = match headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Lang::En,
+        };
+
+        let mut best: Option<(f32, Lang)> = None;
+        for entry in accept.split(',') {
+            let mut parts = entry.trim().split(';');
+            let tag = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q=").and_then(|q| q.parse().ok()))
+                .unwrap_or(1.0);
+            if q <= 0.0 {
+                continue;
+            }
+            let lang = match tag.split('-').next() {
+                Some("ja") => Lang::Ja,
+                Some("en") => Lang::En,
+                _ => continue,
+            };
+            if best.is_none_or(|(best_q, _)| q > best_q) {
+                best = Some((q, lang));
+            }
+        }
+
+        best.map_or(Lang::En, |(_, lang)| lang)
+    }
+}
+
+/// the catalog message for an error code. English has no entries: the
+/// specific messages carried by each error are already English.
+pub fn message(code: &str, lang: Lang) -> Option<&'static str> {
+    match (lang, code) {
+        (Lang::Ja, "validation_failed") => Some("入力内容が正しくありません"),
+        (Lang::Ja, "unauthorized") => Some("認証が必要です"),
+        (Lang::Ja, "not_found") => Some("見つかりません"),
+        (Lang::Ja, "gone") => Some("削除されています"),
+        (Lang::Ja, "not_acceptable") => Some("対応できる形式がAcceptヘッダーにありません"),
+        (Lang::Ja, "conflict") => Some("既に存在します"),
+        (Lang::Ja, "precondition_failed") => Some("リクエストの前提条件を満たしていません"),
+        (Lang::Ja, "store_full") => Some("登録できる上限に達しています"),
+        (Lang::Ja, "bad_request") => Some("リクエストが正しくありません"),
+        (Lang::Ja, "unprocessable") => Some("指定されたデータでは処理できません"),
+        (Lang::Ja, "uri_too_long") => Some("URIが長すぎます"),
+        (Lang::Ja, "payload_too_large") => Some("リクエストボディが大きすぎます"),
+        (Lang::Ja, "unavailable") => Some("現在このエンドポイントは利用できません"),
+        (Lang::Ja, "internal_error") => Some("サーバー内部でエラーが発生しました"),
+        (Lang::Ja, "upstream_error") => Some("上流サービスでエラーが発生しました"),
+        (Lang::Ja, "too_many_requests") => Some("上流サービスへのリクエストが多すぎます。しばらくしてから再試行してください"),
+        (Lang::Ja, "timeout") => Some("処理が時間内に終わりませんでした"),
+        (Lang::Ja, "request_timeout") => Some("リクエストの受信が時間内に終わりませんでした"),
+        // field-level codes reported by `validator`
+        (Lang::Ja, "length") => Some("文字数が範囲外です"),
+        (Lang::Ja, "too_short") => Some("文字数が足りません"),
+        (Lang::Ja, "too_long") => Some("文字数が多すぎます"),
+        (Lang::Ja, "join_year_in_future") => Some("join_yearに未来の年は指定できません"),
+        (Lang::Ja, "unknown_division") => Some("不明なディビジョンです"),
+        (Lang::Ja, "invalid_label") => Some("ラベルは空白のみにできず、長さに上限があります"),
+        (Lang::Ja, "blank") => Some("空白のみの値は指定できません"),
+        (Lang::Ja, "control_characters") => Some("制御文字は使用できません"),
+        _ => None,
+    }
+}
+
+/// an `ApiError` raised by a middleware, to be rendered in `lang` once the
+/// server turns it into a response
+#[derive(Debug)]
+struct LocalizedError {
+    error: ApiError,
+    lang: Lang,
+}
+
+impl fmt::Display for LocalizedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl ResponseError for LocalizedError {
+    fn status_code(&self) -> StatusCode {
+        self.error.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.error.render(self.lang)
+    }
+}
+
+/// re-render `ApiError` responses in the language asked for by `Accept-Language`.
+/// A re-rendered response keeps the headers middleware further in set on the
+/// original, such as CORS's, apart from those describing the body.
+pub struct LocalizeErrors;
+
+impl<S, B> Transform<S> for LocalizeErrors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocalizeErrorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LocalizeErrorsMiddleware { service })
+    }
+}
+
+pub struct LocalizeErrorsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for LocalizeErrorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let lang = Lang::from_headers(req.headers());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            if lang == Lang::En {
+                return fut.await;
+            }
+
+            match fut.await {
+                Ok(res) => {
+                    let localized = res
+                        .response()
+                        .error()
+                        .and_then(|e| e.as_error::<ApiError>())
+                        .map(|e| e.render(lang));
+                    match localized {
+                        Some(mut localized) => {
+                            for (name, value) in res.headers() {
+                                let body = name == header::CONTENT_TYPE || name == header::CONTENT_LENGTH;
+                                if !body && !localized.headers().contains_key(name) {
+                                    localized.headers_mut().append(name.clone(), value.clone());
+                                }
+                            }
+                            Ok(res.into_response(localized.into_body()))
+                        }
+                        None => Ok(res),
+                    }
+                }
+                // errors raised by middleware never became a response
+                Err(e) => match e.as_error::<ApiError>() {
+                    Some(api) => Err(LocalizedError { error: api.clone(), lang }.into()),
+                    None => Err(e),
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service;
+    use actix_web::http::HeaderValue;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App};
+
+    use super::*;
+
+    fn lang(accept: &str) -> Lang {
+        let req = TestRequest::default().header(header::ACCEPT_LANGUAGE, accept).to_http_request();
+        Lang::from_headers(req.headers())
+    }
+
+    #[test]
+    fn a_language_with_q_0_is_never_picked() {
+        assert_eq!(lang("ja;q=0.5, en;q=0.8"), Lang::En);
+        assert_eq!(lang("ja-JP, en;q=0.8"), Lang::Ja);
+        assert_eq!(lang("ja;q=0, en;q=0.1"), Lang::En);
+        assert_eq!(lang("en;q=0, ja;q=0.1"), Lang::Ja);
+        assert_eq!(lang("ja;q=0"), Lang::En);
+        assert_eq!(lang("ja;q=0.000"), Lang::En);
+    }
+
+    #[actix_rt::test]
+    async fn a_localized_error_keeps_the_headers_set_further_in() {
+        let app = App::new()
+            .wrap(LocalizeErrors)
+            .wrap_fn(|req, srv| {
+                let fut = srv.call(req);
+                async {
+                    let mut res = fut.await?;
+                    let origin = HeaderValue::from_static("*");
+                    res.headers_mut().insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+                    Ok(res)
+                }
+            })
+            .route("/", web::get().to(|| async { Err::<HttpResponse, _>(ApiError::NotFound("x".into())) }));
+        let mut app = test::init_service(app).await;
+        let req = TestRequest::get().uri("/").header(header::ACCEPT_LANGUAGE, "ja").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(res).await).unwrap();
+        assert_eq!(body["error"], "見つかりません");
+    }
+}
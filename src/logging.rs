@@ -0,0 +1,22 @@
+use std::io::Write;
+
+use env_logger::Builder;
+
+/// set up `env_logger` from `RUST_LOG`. With `LOG_FORMAT=json` every record is
+/// written as one json object per line (`timestamp`, `level`, `target`,
+/// `message`) for log aggregators; otherwise the usual human-readable format.
+pub fn init() {
+    let mut builder = Builder::from_default_env();
+    if std::env::var("LOG_FORMAT").map(|v| v == "json").unwrap_or(false) {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+}
@@ -11,182 +11,932 @@
 //     2. actix-web client features:
 //           - POSTing json body
 //     3. chaining futures into a single response used by an async endpoint
+//     4. streaming per-step progress as newline-delimited json (`?stream=true`)
+
+// without `upstream` the helpers only its endpoints use (the error variants,
+// config fields and spans for httpbin calls) are still built but never called
+#![cfg_attr(not(feature = "upstream"), allow(dead_code))]
 
 use serde::{Deserialize, Serialize};
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io;
-use std::env; 
+use std::time::{Duration, Instant};
 
 use actix_web::{
-    client::Client,
-    error::ErrorBadRequest,
-    web::{self, BytesMut},
-    App, Error, HttpResponse, HttpServer,
+    http::Method,
+    web::{self},
+    App, Error, HttpRequest, HttpResponse, HttpServer,
 };
-use futures::StreamExt;
-use validator::Validate;
-use validator_derive::Validate;
+use validator::{ValidationError, ValidationErrors};
+
+mod admin;
+mod assets;
+mod audit;
+mod auth;
+mod body_log;
+mod body_timeout;
+mod cache;
+mod clock;
+mod config;
+mod cors;
+mod debug;
+mod deprecation;
+#[cfg(feature = "upstream")]
+mod dedup;
+mod endpoint;
+mod error;
+mod export;
+mod geo;
+mod grapheme;
+mod locale;
+mod logging;
+mod maintenance;
+mod metrics;
+mod news;
+#[cfg(feature = "upstream")]
+mod outbound;
+mod protobuf;
+mod query_limit;
+mod queue;
+mod recent;
+mod request_id;
+mod retry;
+mod signature;
+mod slow_log;
+mod store;
+mod strict_accept;
+mod teams;
+#[cfg(test)]
+mod test_support;
+mod timeout;
+mod tls;
+mod trace;
+#[cfg(feature = "upstream")]
+mod upstream;
+mod v1;
+#[cfg(feature = "upstream")]
+mod utf8_json;
+mod warmup;
+#[cfg(feature = "upstream")]
+mod ws;
 
-#[derive(Debug, Validate, Deserialize, Serialize)]
+use admin::StopHandle;
+use body_log::BodyLog;
+use body_timeout::BodyTimeout;
+use config::Config;
+use cors::Cors;
+use deprecation::Deprecation;
+use endpoint::{Endpoint, RouteTable};
+use error::{ApiError, FieldError};
+use locale::{Lang, LocalizeErrors};
+use maintenance::Maintenance;
+use metrics::RequestCounts;
+#[cfg(feature = "upstream")]
+use metrics::UpstreamStats;
+use news::{News, NewsStore};
+#[cfg(feature = "upstream")]
+use outbound::OutboundLimit;
+use query_limit::QueryLimit;
+use queue::RequestQueue;
+use recent::RecentRequests;
+use request_id::RequestIds;
+use signature::Signatures;
+use slow_log::SlowRequestLog;
+use strict_accept::StrictAccept;
+use teams::{Team, TeamStore};
+use timeout::RequestTimeout;
+use trace::Tracer;
+#[cfg(feature = "upstream")]
+use upstream::{ChainFlights, StepCache};
+use warmup::Warmup;
+
+/// a field left out deserializes as empty, which `ValidationMode::Create`
+/// rejects and `ValidationMode::Search` skips
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
 struct SomeData {
-    #[validate(length(min = "1", max = "1000000"))]
     id: String,
-    #[validate(length(min = "1", max = "100"))]
     name: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct HttpBinResponse {
-    args: HashMap<String, String>,
-    data: String,
-    files: HashMap<String, String>,
-    form: HashMap<String, String>,
-    headers: HashMap<String, String>,
-    json: SomeData,
-    origin: String,
-    url: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct News {
-    day: String,
-    content: String,
+impl SomeData {
+    /// `validate_with` against the fixed limits in `ValidationMode::Create`,
+    /// as every endpoint taking a `SomeData` to work on checks it
+    fn validate(&self, graphemes: bool) -> Result<(), ValidationErrors> {
+        validate_with(self, NAME_LENGTH, ValidationMode::Create, graphemes)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Team {
-    team_abbreviation: String,
-    active_area: String,
-    join_year: u32,
+/// `value` against `limits`, failing the way `validator`'s `length` check
+/// would, but measured with `grapheme::length`, so in grapheme clusters with
+/// `graphemes`. The measured length goes along as the `length` param.
+fn check_length(value: &str, limits: Length, graphemes: bool) -> Result<(), ValidationError> {
+    let length = grapheme::length(value, graphemes);
+    if (limits.min..=limits.max).contains(&length) {
+        return Ok(());
+    }
+    let mut err = ValidationError::new("length");
+    err.add_param(Cow::from("min"), &limits.min);
+    err.add_param(Cow::from("max"), &limits.max);
+    err.add_param(Cow::from("length"), &length);
+    err.add_param(Cow::from("value"), &value);
+    Err(err)
 }
 
-/// validate data, post json to httpbin, get it back in the response body, return deserialized
-async fn step_x(data: SomeData, client: &Client) -> Result<SomeData, Error> {
-    // validate data
-    data.validate().map_err(ErrorBadRequest)?;
+const CHAIN_STEPS: usize = 3;
 
-    let mut res = client
-        .post("https://httpbin.org/post")
-        .send_json(&data)
-        .await
-        .map_err(Error::from)?; // <- convert SendRequestError to an Error
-
-    let mut body = BytesMut::new();
-    while let Some(chunk) = res.next().await {
-        body.extend_from_slice(&chunk?);
-    }
+/// what a step's result goes through before it is sent on as the next step's
+/// input; nothing, for now
+fn between_steps(data: SomeData) -> SomeData {
+    data
+}
 
-    let body: HttpBinResponse = serde_json::from_slice(&body).unwrap();
-    Ok(body.json)
+#[derive(Debug, Serialize)]
+struct StepPreview {
+    step: usize,
+    /// the json exactly as it would be posted to httpbin
+    body: String,
 }
 
-async fn create_something(
+/// what `POST /something` would send httpbin at each step, worked out without
+/// any network I/O: httpbin echoing a step's input back unchanged, the next
+/// step's is that through `between_steps`
+async fn steps_preview(
     some_data: web::Json<SomeData>,
-    client: web::Data<Client>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let some_data_2 = step_x(some_data.into_inner(), &client).await?;
-    let some_data_3 = step_x(some_data_2, &client).await?;
-    let d = step_x(some_data_3, &client).await?;
+    let mut data = some_data.into_inner();
+    let mut res = Vec::with_capacity(CHAIN_STEPS);
+    for step in 1..=CHAIN_STEPS {
+        data.validate(config.grapheme_length).map_err(ApiError::Validation)?;
+        res.push(StepPreview { step, body: serde_json::to_string(&data)? });
+        data = between_steps(data);
+    }
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body(serde_json::to_string(&d).unwrap()))
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the nearest-rank `p`th percentile of `sorted`, in milliseconds
+fn percentile_ms(sorted: &[Duration], p: usize) -> f64 {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1].as_secs_f64() * 1000.0
+}
+
+/// the most iterations `/debug/bench/response` runs
+const BENCH_RESPONSE_MAX: usize = 100_000;
+
+#[derive(Debug, Deserialize)]
+struct ResponseBenchQuery {
+    iterations: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct PathLatency {
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    total_ms: f64,
+}
+
+impl PathLatency {
+    fn of(mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+        PathLatency {
+            p50_ms: percentile_ms(&latencies, 50),
+            p95_ms: percentile_ms(&latencies, 95),
+            p99_ms: percentile_ms(&latencies, 99),
+            total_ms: latencies.iter().sum::<Duration>().as_secs_f64() * 1000.0,
+        }
+    }
 }
 
-async fn todays_shami_momo(
-    _client: web::Data<Client>,
+#[derive(Debug, Serialize)]
+struct ResponseBench {
+    iterations: usize,
+    /// `create_something`'s `serde_json::to_string` into `.body()`
+    to_string: PathLatency,
+    /// `HttpResponse::Ok().json()`
+    json: PathLatency,
+}
+
+/// compare the two ways of turning `create_something`'s result into a
+/// response, `?iterations=` times each (10000 by default, at most
+/// `BENCH_RESPONSE_MAX`), on the same fixed result. Each iteration times
+/// building one response either way, from the result to a finished
+/// `HttpResponse` with its body serialized, alternating which goes first so
+/// neither always runs on a warm cache; no httpbin call or socket write is
+/// involved, so what's left is the serialization and response building. Only
+/// exists with `DEBUG_ENDPOINTS`.
+async fn bench_response(
+    query: web::Query<ResponseBenchQuery>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let news = News { day: "today".to_string(), content: "Shamiko is going to go on date with Momo.".to_string() };
+    if !config.debug_endpoints {
+        return Err(ApiError::NotFound("debug endpoints are disabled".to_string()).into());
+    }
+    let iterations = query.iterations.unwrap_or(10_000);
+    if iterations == 0 || iterations > BENCH_RESPONSE_MAX {
+        let message = format!("iterations must be between 1 and {}", BENCH_RESPONSE_MAX);
+        return Err(ApiError::BadRequest(message).into());
+    }
+    let d = Some(SomeData { id: "bench".to_string(), name: "bench".to_string() });
+
+    let to_string = || -> Result<HttpResponse, Error> {
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&d)?))
+    };
+    let json = || HttpResponse::Ok().json(&d);
+    let (mut to_string_latencies, mut json_latencies) =
+        (Vec::with_capacity(iterations), Vec::with_capacity(iterations));
+    for i in 0..iterations {
+        let started = Instant::now();
+        if i % 2 == 0 {
+            std::hint::black_box(to_string()?);
+            to_string_latencies.push(started.elapsed());
+            let started = Instant::now();
+            std::hint::black_box(json());
+            json_latencies.push(started.elapsed());
+        } else {
+            std::hint::black_box(json());
+            json_latencies.push(started.elapsed());
+            let started = Instant::now();
+            std::hint::black_box(to_string()?);
+            to_string_latencies.push(started.elapsed());
+        }
+    }
+    let res = ResponseBench {
+        iterations,
+        to_string: PathLatency::of(to_string_latencies),
+        json: PathLatency::of(json_latencies),
+    };
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body(serde_json::to_string(&news)?))
+        .body(serde_json::to_string(&res)?))
 }
 
-async fn all_teams(
-    _client: web::Data<Client>,
+#[derive(Debug, Serialize)]
+struct ItemValidation {
+    index: usize,
+    valid: bool,
+    errors: HashMap<&'static str, Vec<FieldError>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<Vec<&'static str>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TruncateQuery {
+    #[serde(default)]
+    truncate: bool,
+}
+
+/// which of `SomeData`'s fields validation insists on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ValidationMode {
+    /// every field is required and checked, as for creating something
+    #[default]
+    Create,
+    /// every field is optional, and only checked when it's given (not empty)
+    Search,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateBatchQuery {
+    #[serde(default)]
+    truncate: bool,
+    #[serde(default)]
+    mode: ValidationMode,
+}
+
+/// validate every item of the batch, reporting each one rather than stopping
+/// at the first failure, in the `?mode=` given (`create` by default). With
+/// `?truncate=true` values over their max length are cut down to it first,
+/// and each item says which were.
+async fn validate_batch(
+    req: HttpRequest,
+    items: web::Json<Vec<SomeData>>,
+    query: web::Query<ValidateBatchQuery>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let mut res: Vec<Team> = Vec::new();
-
-    let t1 = Team { team_abbreviation: "鹿島".to_string(),
-                    active_area: "茨城県".to_string(),
-                    join_year: 1991 };
-    let t2 = Team { team_abbreviation: "浦和".to_string(),
-                    active_area: "埼玉県".to_string(),
-                    join_year: 1991 };
-    let t3 = Team { team_abbreviation: "水戸".to_string(),
-                    active_area: "茨城県".to_string(),
-                    join_year: 2000 };
-
-    res.push(t1);
-    res.push(t2);
-    res.push(t3);
+    let graphemes = config.grapheme_length;
+    let lang = Lang::from_headers(req.headers());
+    let res: Vec<ItemValidation> = items
+        .into_inner()
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut item)| {
+            let truncated = query.truncate.then(|| truncate(&mut item, NAME_LENGTH, graphemes));
+            match validate_with(&item, NAME_LENGTH, query.mode, graphemes) {
+                Ok(()) => ItemValidation { index, valid: true, errors: HashMap::new(), truncated },
+                Err(e) => ItemValidation { index, valid: false, errors: error::field_errors(&e, lang), truncated },
+            }
+        })
+        .collect();
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .body(serde_json::to_string(&res)?))
 }
 
-async fn teams_j1(
-    _client: web::Data<Client>,
+/// `SomeData`'s length limits as fixed by its `#[validate]` attributes
+const ID_LENGTH: Length = Length { min: 1, max: 1_000_000 };
+const NAME_LENGTH: Length = Length { min: 1, max: 100 };
+
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Length {
+    min: usize,
+    max: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Constraints {
+    id: Length,
+    name: Length,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateQuery {
+    /// lower `name`'s max length for this request; capped at `NAME_LENGTH.max`
+    max_name: Option<usize>,
+    /// cut values over their max length down to it instead of failing them
+    #[serde(default)]
+    truncate: bool,
+    #[serde(default)]
+    mode: ValidationMode,
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationReport {
+    valid: bool,
+    errors: HashMap<&'static str, Vec<FieldError>>,
+    constraints: Constraints,
+    /// with `?truncate=true`, the fields that were cut down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<Vec<&'static str>>,
+}
+
+/// `value` cut down to at most `max` characters (not bytes, so never inside
+/// one, and with `graphemes` never inside a grapheme cluster either);
+/// whether anything was cut
+fn truncate_chars(value: &mut String, max: usize, graphemes: bool) -> bool {
+    match grapheme::cut_at(value, max, graphemes) {
+        Some(end) => {
+            value.truncate(end);
+            true
+        }
+        None => false,
+    }
+}
+
+/// `data` with `id` and `name` cut down to `ID_LENGTH.max` and
+/// `name_length.max`, returning the fields that were
+fn truncate(data: &mut SomeData, name_length: Length, graphemes: bool) -> Vec<&'static str> {
+    let mut truncated = Vec::new();
+    if truncate_chars(&mut data.id, ID_LENGTH.max, graphemes) {
+        truncated.push("id");
+    }
+    if truncate_chars(&mut data.name, name_length.max, graphemes) {
+        truncated.push("name");
+    }
+    truncated
+}
+
+/// `id` and `name` checked against `ID_LENGTH` and `NAME_LENGTH`, and `name`
+/// against `name_length` too, whose max can be lowered at runtime, lengths
+/// counted in grapheme clusters with `graphemes` (`GRAPHEME_LENGTH`). In
+/// `ValidationMode::Search` the fields left empty aren't checked at all.
+fn validate_with(
+    data: &SomeData,
+    name_length: Length,
+    mode: ValidationMode,
+    graphemes: bool,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    let mut name_failed = false;
+    let fields = [("id", &data.id, ID_LENGTH), ("name", &data.name, NAME_LENGTH)];
+    for (field, value, limits) in fields {
+        if mode == ValidationMode::Search && value.is_empty() {
+            continue;
+        }
+        if let Err(err) = check_length(value, limits, graphemes) {
+            name_failed |= field == "name";
+            errors.add(field, err);
+        }
+    }
+    let name_len = grapheme::length(&data.name, graphemes);
+    if !name_failed && name_len > name_length.max {
+        let mut err = ValidationError::new("length");
+        err.message = Some(Cow::from(format!(
+            "name must be at most {} characters",
+            name_length.max
+        )));
+        err.add_param(Cow::from("min"), &name_length.min);
+        err.add_param(Cow::from("max"), &name_length.max);
+        err.add_param(Cow::from("length"), &name_len);
+        err.add_param(Cow::from("value"), &data.name);
+        errors.add("name", err);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// validate one `SomeData` in the `?mode=` given (`create` by default), with
+/// `name`'s max length optionally lowered by `?max_name=`, reporting the constraints that were applied. With
+/// `?truncate=true` values over their max length are cut down to it rather
+/// than failed, and `truncated` lists those fields.
+async fn validate_something(
+    req: HttpRequest,
+    some_data: web::Json<SomeData>,
+    query: web::Query<ValidateQuery>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let mut res: Vec<Team> = Vec::new();
+    let name_max = query.max_name.unwrap_or(NAME_LENGTH.max).min(NAME_LENGTH.max);
+    if name_max < NAME_LENGTH.min {
+        return Err(ApiError::BadRequest(format!(
+            "max_name must be at least {}",
+            NAME_LENGTH.min
+        ))
+        .into());
+    }
+    let name_length = Length { max: name_max, ..NAME_LENGTH };
 
-    let t1 = Team { team_abbreviation: "鹿島".to_string(),
-                    active_area: "茨城県".to_string(),
-                    join_year: 1991 };
-    let t2 = Team { team_abbreviation: "浦和".to_string(),
-                    active_area: "埼玉県".to_string(),
-                    join_year: 1991 };
+    let mut data = some_data.into_inner();
+    let graphemes = config.grapheme_length;
+    let truncated = if query.truncate { Some(truncate(&mut data, name_length, graphemes)) } else { None };
 
-    res.push(t1);
-    res.push(t2);
+    let lang = Lang::from_headers(req.headers());
+    let errors = match validate_with(&data, name_length, query.mode, graphemes) {
+        Ok(()) => HashMap::new(),
+        Err(e) => error::field_errors(&e, lang),
+    };
+    let res = ValidationReport {
+        valid: errors.is_empty(),
+        errors,
+        constraints: Constraints { id: ID_LENGTH, name: name_length },
+        truncated,
+    };
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .body(serde_json::to_string(&res)?))
 }
 
-async fn teams_j2(
-    _client: web::Data<Client>,
+fn id_error(code: &'static str, message: &'static str) -> ApiError {
+    let mut err = ValidationError::new(code);
+    err.message = Some(Cow::from(message));
+    let mut errors = ValidationErrors::new();
+    errors.add("id", err);
+    ApiError::Validation(errors)
+}
+
+/// trim surrounding whitespace from `id`, rejecting any control characters
+/// left inside it and ids that end up empty
+fn normalize_id(id: &str) -> Result<String, ApiError> {
+    let id = id.trim();
+    if id.is_empty() {
+        return Err(id_error("blank", "id must not be blank"));
+    }
+    if id.chars().any(char::is_control) {
+        return Err(id_error("control_characters", "id must not contain control characters"));
+    }
+    Ok(id.to_string())
+}
+
+#[derive(Debug, Serialize)]
+struct Truncated {
+    #[serde(flatten)]
+    data: SomeData,
+    truncated: Vec<&'static str>,
+}
+
+/// normalize and validate a `SomeData`, returning the normalized value. With
+/// `?truncate=true` values over their max length are cut down to it rather
+/// than rejected, and the value comes with the `truncated` fields.
+async fn normalize_something(
+    some_data: web::Json<SomeData>,
+    query: web::Query<TruncateQuery>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let mut res: Vec<Team> = Vec::new();
+    let graphemes = config.grapheme_length;
+    let mut data = some_data.into_inner();
+    data.id = normalize_id(&data.id)?;
+    let truncated = if query.truncate { Some(truncate(&mut data, NAME_LENGTH, graphemes)) } else { None };
+    data.validate(graphemes).map_err(ApiError::Validation)?;
 
-    let t3 = Team { team_abbreviation: "水戸".to_string(),
-                    active_area: "茨城県".to_string(),
-                    join_year: 2000 };
+    let body = match truncated {
+        Some(truncated) => serde_json::to_string(&Truncated { data, truncated })?,
+        None => serde_json::to_string(&data)?,
+    };
+    Ok(HttpResponse::Ok().content_type("application/json").body(body))
+}
+
+#[derive(Debug, Serialize)]
+struct Home {
+    news: Option<News>,
+    teams: Vec<Team>,
+}
 
-    res.push(t3);
+/// today's news and all teams in one payload
+async fn home(
+    news: web::Data<NewsStore>,
+    teams: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let res = Home { news: news.latest()?, teams: teams.all()? };
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .body(serde_json::to_string(&res)?))
 }
 
+/// every endpoint the server mounts; both the app and `GET /api/v0/routes`
+/// are built from this one list, so the route table can't fall out of date.
+/// The httpbin-backed ones come from `upstream` when that feature is on.
+fn routes() -> Vec<Endpoint> {
+    #[allow(unused_mut)]
+    let mut routes = vec![
+        Endpoint::new("/something/steps-preview").route(Method::POST, steps_preview),
+        Endpoint::new("/normalize").route(Method::POST, normalize_something),
+        Endpoint::new("/validate").route(Method::POST, validate_something),
+        Endpoint::new("/validate/batch").route(Method::POST, validate_batch),
+        Endpoint::new(warmup::HEALTH_PATH).route(Method::GET, warmup::healthz),
+        Endpoint::new("/favicon.ico").route(Method::GET, assets::favicon),
+        Endpoint::new("/echo/raw").route(Method::POST, debug::echo_raw),
+        Endpoint::new("/debug/bench/serialize").route(Method::GET, debug::bench_serialize),
+        Endpoint::new("/debug/bench/response").route(Method::GET, bench_response),
+        Endpoint::new("/debug/flaky").route(Method::GET, debug::flaky),
+        Endpoint::new("/debug/headers").route(Method::GET, debug::echo_headers),
+        Endpoint::new("/admin/shutdown").route(Method::POST, admin::shutdown),
+        Endpoint::new("/admin/maintenance").route(Method::POST, maintenance::toggle),
+        Endpoint::new("/admin/reset").route(Method::POST, admin::reset),
+        Endpoint::new("/admin/validate-store").route(Method::GET, admin::validate_store),
+        Endpoint::new("/admin/validate-coords").route(Method::GET, admin::validate_coords),
+        Endpoint::new("/admin/request-counts").route(Method::GET, metrics::request_counts),
+        Endpoint::new("/admin/recent-requests").route(Method::GET, recent::recent_requests),
+        Endpoint::new("/admin/replay/{id}").route(Method::POST, recent::replay),
+        Endpoint::new("/config").route(Method::GET, config::show_config),
+        Endpoint::new("/now").route(Method::GET, clock::now),
+        Endpoint::new("/shami_momo")
+            .route(Method::GET, news::todays_shami_momo)
+            .route(Method::POST, news::post_shami_momo),
+        Endpoint::new("/shami_momo/poll").route(Method::GET, news::poll_shami_momo),
+        Endpoint::new("/shami_momo/range").route(Method::GET, news::shami_momo_range),
+        Endpoint::new("/api/v0/fingerprint").route(Method::GET, export::fingerprint),
+        Endpoint::new("/api/v0/export").route(Method::GET, export::export),
+        Endpoint::new("/api/v0/home").route(Method::GET, home),
+        Endpoint::new("/api/v0/routes").route(Method::GET, endpoint::list_routes),
+        Endpoint::new("/api/v0/teams").route(Method::GET, teams::all_teams)
+            .route(Method::POST, teams::create_team)
+            .route(Method::DELETE, teams::delete_teams),
+        Endpoint::new("/api/v0/teams.html").route(Method::GET, teams::teams_html),
+        Endpoint::new("/api/v0/teams.csv").route(Method::GET, teams::teams_csv),
+        Endpoint::new("/api/v0/teams/import").route(Method::POST, teams::import_teams),
+        Endpoint::new("/api/v0/teams/import/ndjson").route(Method::POST, teams::import_ndjson),
+        Endpoint::new("/api/v0/import/csv").route(Method::POST, teams::import_csv),
+        Endpoint::new("/api/v0/teams/merge").route(Method::POST, teams::merge_teams),
+        Endpoint::new("/api/v0/teams/j1").route(Method::GET, teams::teams_j1),
+        Endpoint::new("/api/v0/teams/j2").route(Method::GET, teams::teams_j2),
+        Endpoint::new("/api/v0/teams/division-diff").route(Method::GET, teams::teams_division_diff),
+        Endpoint::new("/api/v0/teams/validate-abbr").route(Method::POST, teams::validate_abbreviation),
+        Endpoint::new("/api/v0/teams/filter").route(Method::GET, teams::teams_filter),
+        Endpoint::new("/api/v0/teams/random").route(Method::GET, teams::random_team),
+        Endpoint::new("/api/v0/teams/tree").route(Method::GET, teams::teams_tree),
+        Endpoint::new("/api/v0/teams/age-histogram").route(Method::GET, teams::teams_age_histogram),
+        Endpoint::new("/api/v0/teams/by-decade").route(Method::GET, teams::teams_by_decade),
+        Endpoint::new("/api/v0/teams/top-area").route(Method::GET, teams::teams_top_area),
+        Endpoint::new("/api/v0/teams/dashboard").route(Method::GET, teams::teams_dashboard),
+        Endpoint::new("/api/v0/teams/fuzzy").route(Method::GET, teams::teams_fuzzy),
+        Endpoint::new("/api/v0/teams/near").route(Method::GET, teams::teams_near),
+        Endpoint::new("/api/v0/teams/by-area-initial")
+            .route(Method::GET, teams::teams_by_area_initial),
+        Endpoint::new("/api/v0/teams/founding").route(Method::GET, teams::teams_founding),
+        Endpoint::new("/api/v0/teams/year/{year}").route(Method::GET, teams::teams_by_year),
+        Endpoint::new("/api/v0/teams/{abbr1}/distance/{abbr2}")
+            .route(Method::GET, teams::team_distance),
+        Endpoint::new("/api/v0/teams/{abbr}/restore").route(Method::POST, teams::restore_team),
+        Endpoint::new("/api/v0/teams/{abbr}/labels").route(Method::POST, teams::label_team),
+        Endpoint::new("/api/v0/teams/{abbr}/cohort").route(Method::GET, teams::team_cohort),
+        Endpoint::new("/api/v0/teams/{abbr}/division")
+            .route(Method::GET, teams::team_division)
+            .route(Method::POST, teams::move_team_division),
+        Endpoint::new("/api/v0/teams/{abbr}/divisions").route(Method::GET, teams::team_divisions),
+        Endpoint::new("/api/v0/divisions/rename").route(Method::POST, teams::rename_division),
+        Endpoint::new("/api/v0/teams/{abbr}/tenure").route(Method::GET, teams::team_tenure),
+        Endpoint::new("/api/v0/teams/{abbr}/exists").route(Method::GET, teams::team_exists),
+        Endpoint::new("/api/v0/teams/{abbr}")
+            .route(Method::GET, teams::team)
+            .route(Method::PUT, teams::upsert_team)
+            .route(Method::PATCH, teams::patch_team)
+            .route(Method::DELETE, teams::delete_team),
+        Endpoint::new("/api/v1/teams").route(Method::GET, v1::all_teams),
+        Endpoint::new("/api/v1/teams/{abbr}").route(Method::GET, v1::team),
+    ];
+    #[cfg(feature = "upstream")]
+    routes.extend(upstream::routes());
+    routes
+}
+
 #[actix_rt::main]
 async fn main() -> io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
-    env_logger::init();
+    std::env::set_var("RUST_LOG", format!("actix_web=info,awc_examples=info,{}=info", audit::TARGET));
+    logging::init();
+    config::validate_env()?;
     // let endpoint = "127.0.0.1:8080";
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse()
-        .expect("PORT must be a number");
+    // `HttpServer::new` runs the closure below once per worker thread, so
+    // anything constructed inside it (like the `Client`) is per worker. State
+    // that must look the same from every worker is built once out here and each
+    // worker's `App` gets a clone of the same `web::Data`, which is an `Arc`:
+    // a team POSTed through one worker is listed by all of them.
+    let config = web::Data::new(Config::from_env());
+    let initial_teams = teams::initial_teams(config.current_year)?;
+    let store = web::Data::new(TeamStore::new(initial_teams, config.max_teams, config.soft_delete));
+    let news = web::Data::new(NewsStore::seeded());
+    let stop = web::Data::new(StopHandle::default());
+    #[cfg(feature = "upstream")]
+    let step_cache = web::Data::new(StepCache::from_env());
+    let area_regexes = web::Data::new(teams::AreaRegexes::new(64, Duration::from_secs(3600)));
+    let flaky = web::Data::new(debug::Flaky::default());
+    #[cfg(feature = "upstream")]
+    let (chain_flights, outbound, upstream_stats) = (
+        web::Data::new(ChainFlights::from_env()),
+        web::Data::new(OutboundLimit::from_env()),
+        web::Data::new(UpstreamStats::default()),
+    );
+    let stop_handle = stop.clone();
+    #[cfg(feature = "upstream")]
+    let user_agent = upstream::user_agent();
+    let query_limit = QueryLimit::from_env();
+    let strict_accept = StrictAccept::from_env();
+    let body_log = BodyLog::from_env();
+    let signatures = Signatures::new(config.webhook_secret.clone(), config.max_body_bytes);
+    let body_timeout = BodyTimeout::from_env();
+    let slow_log = SlowRequestLog::from_env();
+    let request_timeout = RequestTimeout::from_env();
+    let queue = RequestQueue::from_env();
+    let warmup = Warmup::start();
+    let cors = Cors::from_env();
+    let deprecation = Deprecation::from_env();
+    let tracer = web::Data::new(Tracer::start());
+    let maintenance = web::Data::new(Maintenance::default());
+    let tls = tls::acceptor(&config)?;
+    let addr = (config.host.clone(), config.port);
+    let route_table = web::Data::new(routes().iter().map(Endpoint::describe).collect::<RouteTable>());
+    for route in route_table.iter() {
+        log::info!("route {} {}", route.methods.join(","), route.path);
+    }
+    let request_counts = web::Data::new(RequestCounts::new(&route_table));
+    let recent_requests = web::Data::new(RecentRequests::from_env());
+    let request_ids = RequestIds::new(config.request_id_header.clone());
+    let workers = config.workers;
+    let h2c = config.h2c;
+    log::info!(
+        "listening on {}:{} ({}, {} workers)",
+        addr.0,
+        addr.1,
+        tls::protocols(tls.is_some(), &config),
+        workers
+    );
 
     // println!("Starting server at: {:?}", endpoint);
-    HttpServer::new(|| {
-        App::new()
-            .data(Client::default())
-            .service(web::resource("/something").route(web::post().to(create_something)))
-            .service(web::resource("/shami_momo").route(web::get().to(todays_shami_momo)))
-
-            .service(web::resource("/api/v0/teams").route(web::get().to(all_teams)))
-            .service(web::resource("/api/v0/teams/j1").route(web::get().to(teams_j1)))
-            .service(web::resource("/api/v0/teams/j2").route(web::get().to(teams_j2)))
-    })
+    let app = move || {
+        let app = App::new()
+            .wrap(body_log)
+            .wrap(signatures.clone())
+            .wrap(body_timeout)
+            .wrap(strict_accept)
+            .wrap(request_timeout.clone())
+            .wrap(queue.clone())
+            // outside the queue and the timeout, so a request over the query
+            // or path limits is turned away before it waits for a permit
+            .wrap(query_limit)
+            .wrap(slow_log)
+            .wrap(maintenance.get_ref().clone())
+            .wrap(warmup.clone())
+            .wrap(deprecation.clone())
+            .wrap(cors.clone())
+            .wrap(LocalizeErrors)
+            .wrap(request_counts.get_ref().clone())
+            .wrap(recent_requests.get_ref().clone())
+            .wrap(tracer.get_ref().clone())
+            .wrap(request_ids.clone())
+            .app_data(web::JsonConfig::default().error_handler(error::json_error))
+            .app_data(store.clone())
+            .app_data(news.clone())
+            .app_data(config.clone())
+            .app_data(stop.clone())
+            .app_data(flaky.clone())
+            .app_data(route_table.clone())
+            .app_data(request_counts.clone())
+            .app_data(recent_requests.clone())
+            .app_data(maintenance.clone())
+            .app_data(tracer.clone())
+            .app_data(area_regexes.clone());
+        #[cfg(feature = "upstream")]
+        let app = app
+            .data(upstream::client(&user_agent, config.client_timeout))
+            .app_data(step_cache.clone())
+            .app_data(chain_flights.clone())
+            .app_data(outbound.clone())
+            .app_data(upstream_stats.clone());
+        routes().into_iter().fold(app, |app, endpoint| app.service(endpoint))
+    };
+
     //.bind(endpoint)?
-    .bind(("0.0.0.0", port))? 
-    .run()
-    .await
+    let server = match tls {
+        Some(tls) => HttpServer::new(app).workers(workers).bind_openssl(addr, tls)?.run(),
+        None if h2c => tls::h2c_server(app, std::net::TcpListener::bind(addr)?, workers)?,
+        None => HttpServer::new(app).workers(workers).bind(addr)?.run(),
+    };
+    stop_handle.register(server.clone()).map_err(|e| io::Error::other(e.to_string()))?;
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, call};
+
+    fn data(id_len: usize, name_len: usize) -> SomeData {
+        SomeData { id: "i".repeat(id_len), name: "名".repeat(name_len) }
+    }
+
+    fn failing_fields(data: &SomeData) -> Vec<&'static str> {
+        let mut fields: Vec<&'static str> =
+            data.validate(false).err().map(|e| e.inner().into_keys().collect()).unwrap_or_default();
+        fields.sort_unstable();
+        fields
+    }
+
+    #[test]
+    fn id_length_is_checked_at_both_ends() {
+        assert_eq!(failing_fields(&data(ID_LENGTH.min - 1, 1)), ["id"]);
+        assert!(failing_fields(&data(ID_LENGTH.min, 1)).is_empty());
+        assert!(failing_fields(&data(ID_LENGTH.min + 1, 1)).is_empty());
+        assert!(failing_fields(&data(ID_LENGTH.max - 1, 1)).is_empty());
+        assert!(failing_fields(&data(ID_LENGTH.max, 1)).is_empty());
+        assert_eq!(failing_fields(&data(ID_LENGTH.max + 1, 1)), ["id"]);
+    }
+
+    #[test]
+    fn name_length_is_checked_at_both_ends() {
+        assert_eq!(failing_fields(&data(1, NAME_LENGTH.min - 1)), ["name"]);
+        assert!(failing_fields(&data(1, NAME_LENGTH.min)).is_empty());
+        assert!(failing_fields(&data(1, NAME_LENGTH.min + 1)).is_empty());
+        assert!(failing_fields(&data(1, NAME_LENGTH.max - 1)).is_empty());
+        assert!(failing_fields(&data(1, NAME_LENGTH.max)).is_empty());
+        assert_eq!(failing_fields(&data(1, NAME_LENGTH.max + 1)), ["name"]);
+    }
+
+    #[test]
+    fn lengths_count_characters_not_bytes() {
+        // 100 three-byte characters is 300 bytes, still within the limit
+        assert!(data(1, NAME_LENGTH.max).name.len() > NAME_LENGTH.max);
+        assert!(failing_fields(&data(1, NAME_LENGTH.max)).is_empty());
+    }
+
+    fn name_errors(data: &SomeData, max: usize) -> usize {
+        let limits = Length { max, ..NAME_LENGTH };
+        validate_with(data, limits, ValidationMode::Create, false)
+            .err()
+            .and_then(|e| e.inner().get("name").map(Vec::len))
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn max_name_lowers_the_cutoff() {
+        assert_eq!(name_errors(&data(1, 9), 10), 0);
+        assert_eq!(name_errors(&data(1, 10), 10), 0);
+        assert_eq!(name_errors(&data(1, 11), 10), 1);
+        // a name over the fixed limit is only reported once
+        assert_eq!(name_errors(&data(1, NAME_LENGTH.max + 1), 10), 1);
+        assert_eq!(name_errors(&data(1, NAME_LENGTH.max), NAME_LENGTH.max), 0);
+    }
+
+    #[test]
+    fn truncation_cuts_at_the_limit() {
+        let limits = Length { max: 10, ..NAME_LENGTH };
+        let mut at_limit = data(1, 10);
+        assert!(truncate(&mut at_limit, limits, false).is_empty());
+        assert_eq!(at_limit.name.chars().count(), 10);
+
+        let mut over = data(1, 11);
+        assert_eq!(truncate(&mut over, limits, false), ["name"]);
+        assert_eq!(over.name, "名".repeat(10));
+        assert_eq!(name_errors(&over, 10), 0);
+
+        let mut long_id = data(ID_LENGTH.max + 1, 1);
+        assert_eq!(truncate(&mut long_id, limits, false), ["id"]);
+        assert_eq!(long_id.id.len(), ID_LENGTH.max);
+    }
+
+    fn failing_fields_in(data: &SomeData, mode: ValidationMode) -> Vec<&'static str> {
+        let mut fields: Vec<&'static str> = validate_with(data, NAME_LENGTH, mode, false)
+            .err()
+            .map(|e| e.inner().into_keys().collect())
+            .unwrap_or_default();
+        fields.sort_unstable();
+        fields
+    }
+
+    #[test]
+    fn search_mode_skips_the_fields_left_out() {
+        let name_only = SomeData { id: String::new(), name: "鹿島".to_string() };
+        assert!(failing_fields_in(&name_only, ValidationMode::Search).is_empty());
+        assert_eq!(failing_fields_in(&name_only, ValidationMode::Create), ["id"]);
+
+        let empty = SomeData::default();
+        assert!(failing_fields_in(&empty, ValidationMode::Search).is_empty());
+        assert_eq!(failing_fields_in(&empty, ValidationMode::Create), ["id", "name"]);
+    }
+
+    #[test]
+    fn search_mode_still_checks_the_fields_given() {
+        let too_long = SomeData { id: String::new(), name: "名".repeat(NAME_LENGTH.max + 1) };
+        assert_eq!(failing_fields_in(&too_long, ValidationMode::Search), ["name"]);
+        assert_eq!(failing_fields_in(&too_long, ValidationMode::Create), ["id", "name"]);
+    }
+
+    #[actix_rt::test]
+    async fn a_payload_passes_search_mode_and_fails_create_mode() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let payload = json!({"name": "鹿島"});
+        let validate = |uri: &str| TestRequest::post().uri(uri).set_json(&payload).to_request();
+
+        let (status, body) = call(&mut app, validate("/validate?mode=search")).await;
+        assert_eq!(status, StatusCode::OK);
+        let report = test_support::json(&body);
+        assert_eq!((&report["valid"], &report["errors"]), (&json!(true), &json!({})));
+
+        for uri in ["/validate", "/validate?mode=create"] {
+            let (status, body) = call(&mut app, validate(uri)).await;
+            assert_eq!(status, StatusCode::OK);
+            let report = test_support::json(&body);
+            assert_eq!(report["valid"], false, "{}", uri);
+            assert!(report["errors"]["id"].is_array() && report["errors"]["name"].is_null(), "{}", report);
+        }
+
+        let items = json!([{"name": "鹿島"}, {"id": "1"}]);
+        for (mode, valid) in [("search", [true, true]), ("create", [false, false])] {
+            let uri = format!("/validate/batch?mode={}", mode);
+            let req = TestRequest::post().uri(&uri).set_json(&items).to_request();
+            let (status, body) = call(&mut app, req).await;
+            assert_eq!(status, StatusCode::OK);
+            let report = test_support::json(&body);
+            let valids = report.as_array().unwrap().iter().map(|item| item["valid"].as_bool().unwrap());
+            assert_eq!(valids.collect::<Vec<_>>(), valid, "{}", mode);
+        }
+
+        let (status, _) = call(&mut app, validate("/validate?mode=lenient")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// the paths of every route `routes` mounts
+    fn route_paths() -> Vec<String> {
+        routes().iter().map(|endpoint| endpoint.describe().path).collect()
+    }
+
+    #[test]
+    fn httpbin_routes_are_mounted_only_with_the_upstream_feature() {
+        let paths = route_paths();
+        for path in ["/something", "/something/batch", "/proxy", "/ws/something"] {
+            assert_eq!(paths.iter().any(|p| p == path), cfg!(feature = "upstream"), "{}", path);
+        }
+        for path in ["/api/v0/teams", "/validate", "/healthz"] {
+            assert!(paths.iter().any(|p| p == path), "{}", path);
+        }
+    }
+
+    #[cfg(not(feature = "upstream"))]
+    #[actix_rt::test]
+    async fn without_the_upstream_feature_httpbin_routes_are_404_and_local_ones_answer() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let data = json!({"id": "1", "name": "鹿島"});
+        let req = TestRequest::post().uri("/something").set_json(&data).to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::NOT_FOUND);
+        let req = TestRequest::get().uri("/api/v0/teams").to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::OK);
+    }
+
+    #[test]
+    fn graphemes_count_combined_characters_once() {
+        let name = "か\u{3099}".repeat(NAME_LENGTH.max);
+        let data = SomeData { id: "i".to_string(), name };
+        assert!(data.validate(true).is_ok());
+        assert!(data.validate(false).is_err());
+    }
 }
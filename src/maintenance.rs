@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures::future::{err, ok, Either, Ready};
+use serde::{Deserialize, Serialize};
+
+use crate::admin::require_admin;
+use crate::config::Config;
+use crate::warmup::HEALTH_PATH;
+
+#[derive(Debug, Default)]
+struct State {
+    on: AtomicBool,
+    retry_after: AtomicU64,
+}
+
+/// while maintenance mode is on, answer everything but `/healthz` and the
+/// admin endpoints with `503 {"status":"maintenance","retry_after":N}` and a
+/// `Retry-After` header. Toggled at runtime through `POST /admin/maintenance`;
+/// every worker shares the same switch.
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance {
+    state: Arc<State>,
+}
+
+#[derive(Debug, Serialize)]
+struct Unavailable {
+    status: &'static str,
+    retry_after: u64,
+}
+
+fn exempt(path: &str) -> bool {
+    path == HEALTH_PATH || path.starts_with("/admin/")
+}
+
+impl<S, B> Transform<S> for Maintenance
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MaintenanceMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MaintenanceMiddleware { service, state: self.state.clone() })
+    }
+}
+
+pub struct MaintenanceMiddleware<S> {
+    service: S,
+    state: Arc<State>,
+}
+
+impl<S, B> Service for MaintenanceMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !self.state.on.load(Ordering::SeqCst) || exempt(req.path()) {
+            return Either::Left(self.service.call(req));
+        }
+        let retry_after = self.state.retry_after.load(Ordering::SeqCst);
+        let res = HttpResponse::ServiceUnavailable()
+            .header(header::RETRY_AFTER, retry_after.to_string())
+            .json(Unavailable { status: "maintenance", retry_after });
+        Either::Right(err(res.into()))
+    }
+}
+
+fn default_retry_after() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Toggle {
+    enabled: bool,
+    /// the `Retry-After` seconds to answer with while it's on
+    #[serde(default = "default_retry_after")]
+    retry_after: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Mode {
+    maintenance: bool,
+    retry_after: u64,
+}
+
+/// `POST /admin/maintenance` with `{"enabled":true,"retry_after":N}` turns
+/// maintenance mode on, `{"enabled":false}` off again; either takes effect
+/// with the next request
+pub async fn toggle(
+    req: HttpRequest,
+    toggle: web::Json<Toggle>,
+    config: web::Data<Config>,
+    maintenance: web::Data<Maintenance>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+
+    let state = &maintenance.state;
+    state.retry_after.store(toggle.retry_after, Ordering::SeqCst);
+    let was_on = state.on.swap(toggle.enabled, Ordering::SeqCst);
+    if was_on != toggle.enabled {
+        log::warn!("maintenance mode turned {}", if toggle.enabled { "on" } else { "off" });
+    }
+
+    Ok(HttpResponse::Ok().json(Mode { maintenance: toggle.enabled, retry_after: toggle.retry_after }))
+}
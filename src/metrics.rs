@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures::future::{ok, Ready};
+use serde::{Deserialize, Serialize};
+
+use crate::admin::require_admin;
+use crate::config::Config;
+use crate::endpoint::RouteTable;
+
+/// what a request that matches none of the mounted paths is counted under
+pub const UNMATCHED: &str = "(unmatched)";
+
+/// a request counter per mounted path, shared by every worker. The set of
+/// paths is fixed when the server starts, so counting is a single atomic
+/// increment and never takes a lock. A request is counted under the route
+/// pattern it matches (`/api/v0/teams/{abbr}/tenure`), not its raw path, so
+/// the map can't grow with the requests.
+#[derive(Debug, Clone)]
+pub struct RequestCounts {
+    counts: Arc<Vec<(Vec<String>, AtomicU64)>>,
+    unmatched: Arc<AtomicU64>,
+}
+
+impl RequestCounts {
+    pub fn new(routes: &RouteTable) -> Self {
+        let counts = routes.iter().map(|route| (segments(&route.path), AtomicU64::new(0))).collect();
+        RequestCounts { counts: Arc::new(counts), unmatched: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// count one request for `path`, under the first pattern it matches as
+    /// actix does
+    fn record(&self, path: &str) {
+        let path: Vec<&str> = path.split('/').collect();
+        let counter = self
+            .counts
+            .iter()
+            .find(|(pattern, _)| matches(pattern, &path))
+            .map_or(&*self.unmatched, |(_, count)| count);
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// the totals since startup, by pattern
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        let mut totals: BTreeMap<String, u64> = self
+            .counts
+            .iter()
+            .map(|(pattern, count)| (pattern.join("/"), count.load(Ordering::Relaxed)))
+            .collect();
+        totals.insert(UNMATCHED.to_string(), self.unmatched.load(Ordering::Relaxed));
+        totals
+    }
+}
+
+fn segments(pattern: &str) -> Vec<String> {
+    pattern.split('/').map(str::to_string).collect()
+}
+
+/// a `{name}` segment matches any non-empty segment, anything else only itself
+fn matches(pattern: &[String], path: &[&str]) -> bool {
+    pattern.len() == path.len()
+        && pattern.iter().zip(path).all(|(want, got)| {
+            if want.starts_with('{') && want.ends_with('}') {
+                !got.is_empty()
+            } else {
+                want == got
+            }
+        })
+}
+
+impl<S, B> Transform<S> for RequestCounts
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestCountsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestCountsMiddleware { service, counts: self.clone() })
+    }
+}
+
+pub struct RequestCountsMiddleware<S> {
+    service: S,
+    counts: RequestCounts,
+}
+
+impl<S, B> Service for RequestCountsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        self.counts.record(req.path());
+        self.service.call(req)
+    }
+}
+
+/// `GET /admin/request-counts`: `{path pattern: total requests}` since startup
+pub async fn request_counts(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    counts: web::Data<RequestCounts>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    Ok(HttpResponse::Ok().json(counts.snapshot()))
+}
+
+/// why an httpbin call failed
+#[derive(Debug, Clone, Copy)]
+pub enum UpstreamFailure {
+    /// no answer within the client timeout
+    Timeout,
+    /// couldn't connect, or the connection broke
+    Network,
+    /// answered, but not with a 2xx
+    BadStatus,
+    /// answered 2xx with a body that isn't the expected json
+    Parse,
+}
+
+#[derive(Debug, Default)]
+struct UpstreamCounters {
+    succeeded: AtomicU64,
+    timeout: AtomicU64,
+    network: AtomicU64,
+    bad_status: AtomicU64,
+    parse: AtomicU64,
+}
+
+/// how the `step_x` calls to httpbin have gone, each attempt counted once,
+/// shared by every worker; cache hits make no call and aren't counted
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamStats {
+    counters: Arc<UpstreamCounters>,
+}
+
+impl UpstreamStats {
+    /// count one call, `None` for a success
+    pub fn record(&self, failure: Option<UpstreamFailure>) {
+        let c = &self.counters;
+        let counter = match failure {
+            None => &c.succeeded,
+            Some(UpstreamFailure::Timeout) => &c.timeout,
+            Some(UpstreamFailure::Network) => &c.network,
+            Some(UpstreamFailure::BadStatus) => &c.bad_status,
+            Some(UpstreamFailure::Parse) => &c.parse,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// the counts so far, set back to 0 with `reset`
+    fn snapshot(&self, reset: bool) -> UpstreamReport {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        let c = &self.counters;
+        let failures = FailureCounts {
+            timeout: read(&c.timeout),
+            network: read(&c.network),
+            bad_status: read(&c.bad_status),
+            parse: read(&c.parse),
+        };
+        UpstreamReport {
+            succeeded: read(&c.succeeded),
+            failed: failures.timeout + failures.network + failures.bad_status + failures.parse,
+            failures,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FailureCounts {
+    timeout: u64,
+    network: u64,
+    bad_status: u64,
+    parse: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamReport {
+    succeeded: u64,
+    failed: u64,
+    failures: FailureCounts,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpstreamStatsQuery {
+    #[serde(default)]
+    reset: bool,
+}
+
+/// `GET /admin/upstream-stats`: the httpbin calls that succeeded and failed,
+/// by failure kind, since startup or the last `?reset=true`
+pub async fn upstream_stats(
+    req: HttpRequest,
+    query: web::Query<UpstreamStatsQuery>,
+    config: web::Data<Config>,
+    stats: web::Data<UpstreamStats>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    Ok(HttpResponse::Ok().json(stats.snapshot(query.reset)))
+}
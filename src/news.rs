@@ -0,0 +1,542 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_rt::time::timeout;
+use actix_web::{
+    http::header::{IfModifiedSince, LastModified},
+    web, Error, HttpMessage, HttpRequest, HttpResponse,
+};
+use chrono::{NaiveDate, Utc};
+use futures::channel::oneshot;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use validator::{Validate, ValidationError};
+use validator_derive::Validate;
+
+use crate::audit;
+use crate::config::env_or;
+use crate::error::ApiError;
+use crate::store;
+
+/// a calendar date, serialized as ISO-8601 `YYYY-MM-DD`. On input the special
+/// value "today" is accepted too and resolved to the current UTC date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Day(NaiveDate);
+
+impl Day {
+    pub fn today() -> Self {
+        Day(Utc::now().naive_utc().date())
+    }
+}
+
+impl FromStr for Day {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "today" {
+            return Ok(Day::today());
+        }
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map(Day)
+            .map_err(|_| format!("day must be a YYYY-MM-DD date or \"today\", got {:?}", s))
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%d"))
+    }
+}
+
+impl Serialize for Day {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Day {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Validate, Serialize, Deserialize)]
+pub struct News {
+    day: Day,
+    #[validate(custom = "validate_content")]
+    content: String,
+    /// when the item was stored, sent as `Last-Modified`
+    #[serde(skip, default = "SystemTime::now")]
+    last_modified: SystemTime,
+}
+
+fn validate_content(content: &str) -> Result<(), ValidationError> {
+    if !content.trim().is_empty() {
+        return Ok(());
+    }
+    let mut err = ValidationError::new("blank");
+    err.message = Some(Cow::from("content must not be blank"));
+    Err(err)
+}
+
+impl News {
+    pub fn day(&self) -> Day {
+        self.day
+    }
+}
+
+/// http dates have one-second resolution, so drop the sub-second part
+/// before comparing against `If-Modified-Since`
+fn whole_seconds(t: SystemTime) -> SystemTime {
+    let secs = t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// the news items shared by all workers, and the long polls waiting for the
+/// next one
+pub struct NewsStore {
+    news: Mutex<Vec<News>>,
+    waiters: Mutex<Vec<oneshot::Sender<()>>>,
+}
+
+/// a `NewsStore` locked by `lock_for_reset` until dropped; nothing changes until
+/// `apply`
+pub struct NewsReset<'a> {
+    store: &'a NewsStore,
+    news: MutexGuard<'a, Vec<News>>,
+}
+
+impl NewsReset<'_> {
+    /// put the seed news back in place of whatever is stored, returning how
+    /// many items that is
+    pub fn apply(&mut self) -> usize {
+        *self.news = seed_news();
+        self.store.notify();
+        self.news.len()
+    }
+}
+
+/// what a long poll finds: news newer than it asked about, or nothing yet and
+/// a wakeup for when something arrives
+enum Update {
+    Ready(Vec<News>),
+    Wait(oneshot::Receiver<()>),
+}
+
+/// seconds since the epoch, with the sub-second part
+fn unix_secs(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn seed_news() -> Vec<News> {
+    vec![News {
+        day: Day::today(),
+        content: "Shamiko is going to go on date with Momo.".to_string(),
+        last_modified: SystemTime::now(),
+    }]
+}
+
+impl NewsStore {
+    pub fn seeded() -> Self {
+        NewsStore { news: Mutex::new(seed_news()), waiters: Mutex::new(Vec::new()) }
+    }
+
+    /// wake every waiting long poll; called with the news lock held
+    fn notify(&self) {
+        let waiters = match store::lock(&self.waiters, "news waiters") {
+            Ok(mut waiters) => std::mem::take(&mut *waiters),
+            Err(_) => return,
+        };
+        for waiter in waiters {
+            let _ = waiter.send(()); // <- the poll may have timed out already
+        }
+    }
+
+    /// store `item` and wake the long polls
+    pub fn insert(&self, item: News) -> Result<(), ApiError> {
+        let mut news = store::lock(&self.news, "news")?;
+        news.push(item);
+        self.notify();
+        Ok(())
+    }
+
+    /// the items stored after `since` (unix seconds), oldest first, or a
+    /// wakeup for the next insert when there are none. The check and the
+    /// registration happen under the news lock, so no insert falls in between.
+    fn newer_or_wait(&self, since: f64) -> Result<Update, ApiError> {
+        let news = store::lock(&self.news, "news")?;
+        let mut newer: Vec<News> =
+            news.iter().filter(|n| unix_secs(n.last_modified) > since).cloned().collect();
+        if !newer.is_empty() {
+            newer.sort_by_key(|n| n.last_modified);
+            return Ok(Update::Ready(newer));
+        }
+        let (tx, rx) = oneshot::channel();
+        let mut waiters = store::lock(&self.waiters, "news waiters")?;
+        waiters.retain(|waiter| !waiter.is_canceled());
+        waiters.push(tx);
+        Ok(Update::Wait(rx))
+    }
+
+    /// the store locked for a reset, so that it can be reset together with
+    /// other stores once every one of them is locked
+    pub fn lock_for_reset(&self) -> Result<NewsReset<'_>, ApiError> {
+        Ok(NewsReset { store: self, news: store::lock(&self.news, "news")? })
+    }
+
+    pub fn all(&self) -> Result<Vec<News>, ApiError> {
+        Ok(store::lock(&self.news, "news")?.clone())
+    }
+
+    pub fn latest(&self) -> Result<Option<News>, ApiError> {
+        Ok(store::lock(&self.news, "news")?.last().cloned())
+    }
+}
+
+/// `news` as a sentence, `[today] ...` or `[YYYY-MM-DD] ...` for another day
+fn render_text(news: &News, today: Day) -> String {
+    let day = if news.day == today { "today".to_string() } else { news.day.to_string() };
+    format!("[{}] {}", day, news.content)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormatQuery {
+    /// `json` (the default) or `text`
+    format: Option<String>,
+}
+
+/// today's news, honoring `If-Modified-Since` with a `304 Not Modified`; as
+/// json, or with `?format=text` as a `text/plain` sentence
+pub async fn todays_shami_momo(
+    req: HttpRequest,
+    query: web::Query<FormatQuery>,
+    store: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    let text = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("text") => true,
+        Some(other) => {
+            return Err(ApiError::BadRequest(format!("format must be json or text, got {:?}", other)).into())
+        }
+    };
+    let news = match store.latest()? {
+        Some(news) => news,
+        None => return Err(ApiError::NotFound("no news yet".to_string()).into()),
+    };
+    let last_modified = whole_seconds(news.last_modified);
+
+    if let Some(IfModifiedSince(since)) = req.get_header::<IfModifiedSince>() {
+        if last_modified <= SystemTime::from(since) {
+            return Ok(HttpResponse::NotModified()
+                .set(LastModified(last_modified.into()))
+                .finish());
+        }
+    }
+
+    let mut res = HttpResponse::Ok();
+    res.set(LastModified(last_modified.into()));
+    if text {
+        return Ok(res.content_type("text/plain; charset=utf-8").body(render_text(&news, Day::today())));
+    }
+    Ok(res.content_type("application/json").body(serde_json::to_string(&news)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RangeQuery {
+    from: String,
+    to: String,
+}
+
+fn parse_date(name: &str, value: &str) -> Result<Day, ApiError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Day).map_err(|_| {
+        ApiError::BadRequest(format!("{} must be a YYYY-MM-DD date, got {:?}", name, value))
+    })
+}
+
+/// the news items dated between `from` and `to`, both inclusive, oldest first
+pub async fn shami_momo_range(
+    query: web::Query<RangeQuery>,
+    store: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    let from = parse_date("from", &query.from)?;
+    let to = parse_date("to", &query.to)?;
+    if from > to {
+        let message = format!("from ({}) must not be after to ({})", from, to);
+        return Err(ApiError::BadRequest(message).into());
+    }
+
+    let mut res: Vec<News> = store
+        .all()?
+        .into_iter()
+        .filter(|news| from <= news.day && news.day <= to)
+        .collect();
+    res.sort_by_key(|news| news.day);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// a news item to add; `day` defaults to today
+#[derive(Debug, Deserialize)]
+pub struct NewNews {
+    day: Option<Day>,
+    content: String,
+}
+
+/// add a news item, answering `201` with it, and wake the long polls
+pub async fn post_shami_momo(
+    req: HttpRequest,
+    item: web::Json<NewNews>,
+    store: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    let item = item.into_inner();
+    let news = News {
+        day: item.day.unwrap_or_else(Day::today),
+        content: item.content,
+        last_modified: SystemTime::now(),
+    };
+    news.validate().map_err(ApiError::Validation)?;
+    store.insert(news.clone())?;
+    audit::record(&req, "create", &news.day.to_string(), None, audit::snapshot(&news));
+
+    Ok(HttpResponse::Created()
+        .content_type("application/json")
+        .body(serde_json::to_string(&news)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PollQuery {
+    /// unix seconds, fractions allowed; only news stored after it counts
+    since: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct PollResult {
+    news: Vec<News>,
+    /// when the newest item was stored, to pass as the next `since`
+    last_modified: f64,
+}
+
+/// long poll for news stored after `?since=`: answer at once if there is
+/// some, otherwise hold the request until some arrives or `NEWS_POLL_MAX_MS`
+/// (25000 by default) passes, then answer `204`
+pub async fn poll_shami_momo(
+    query: web::Query<PollQuery>,
+    store: web::Data<NewsStore>,
+) -> Result<HttpResponse, Error> {
+    let news = match store.newer_or_wait(query.since)? {
+        Update::Ready(news) => news,
+        Update::Wait(wakeup) => {
+            let max_wait = Duration::from_millis(env_or("NEWS_POLL_MAX_MS", 25_000));
+            if timeout(max_wait, wakeup).await.is_err() {
+                return Ok(HttpResponse::NoContent().finish());
+            }
+            match store.newer_or_wait(query.since)? {
+                Update::Ready(news) => news,
+                Update::Wait(_) => return Ok(HttpResponse::NoContent().finish()),
+            }
+        }
+    };
+    let last_modified = news.last().map_or(query.since, |n| unix_secs(n.last_modified));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&PollResult { news, last_modified })?))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::header::{self, HttpDate};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_support;
+
+    /// a news item for `day`, stored now
+    fn news(day: &str, content: &str) -> News {
+        News { day: day.parse().unwrap(), content: content.to_string(), last_modified: SystemTime::now() }
+    }
+
+    /// the status and, when there is one, the json body `res` answers with
+    async fn answer(res: HttpResponse) -> (StatusCode, Option<Value>) {
+        let res = ServiceResponse::new(TestRequest::default().to_http_request(), res);
+        let status = res.status();
+        let body = test::read_body(res).await;
+        (status, if body.is_empty() { None } else { Some(test_support::json(&body)) })
+    }
+
+    async fn poll(store: &web::Data<NewsStore>, since: f64) -> (StatusCode, Option<Value>) {
+        let query = web::Query::<PollQuery>::from_query(&format!("since={}", since)).unwrap();
+        answer(poll_shami_momo(query, store.clone()).await.unwrap()).await
+    }
+
+    #[actix_rt::test]
+    async fn a_poll_answers_at_once_when_there_is_newer_news() {
+        let store = web::Data::new(NewsStore::seeded());
+        let (status, body) = poll(&store, 0.0).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["news"].as_array().unwrap().len(), 1);
+        assert_eq!(body["news"][0]["content"], "Shamiko is going to go on date with Momo.");
+        assert!(body["last_modified"].as_f64().unwrap() > 0.0);
+    }
+
+    #[actix_rt::test]
+    async fn an_insert_wakes_a_waiting_poll() {
+        let store = web::Data::new(NewsStore::seeded());
+        let since = unix_secs(SystemTime::now());
+        let insert = async {
+            actix_rt::time::delay_for(Duration::from_millis(20)).await;
+            store.insert(news("2024-01-02", "シャミ子が桃とデートに行く")).unwrap();
+        };
+        let ((status, body), ()) = futures::join!(poll(&store, since), insert);
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["news"], json!([{"day": "2024-01-02", "content": "シャミ子が桃とデートに行く"}]));
+        assert!(body["last_modified"].as_f64().unwrap() > since);
+    }
+
+    #[actix_rt::test]
+    async fn a_poll_nothing_arrives_for_is_204_after_news_poll_max_ms() {
+        std::env::set_var("NEWS_POLL_MAX_MS", "30");
+        let store = web::Data::new(NewsStore::seeded());
+        let started = std::time::Instant::now();
+        assert_eq!(poll(&store, unix_secs(SystemTime::now())).await, (StatusCode::NO_CONTENT, None));
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    /// `GET /shami_momo` on `store`, with `If-Modified-Since` when given
+    async fn todays(store: &web::Data<NewsStore>, if_modified_since: Option<SystemTime>) -> HttpResponse {
+        let req = match if_modified_since {
+            Some(since) => TestRequest::get().set(IfModifiedSince(since.into())),
+            None => TestRequest::get(),
+        };
+        let query = web::Query::<FormatQuery>::from_query("").unwrap();
+        todays_shami_momo(req.to_http_request(), query, store.clone()).await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn todays_news_is_304_unless_modified_since() {
+        let store = web::Data::new(NewsStore::seeded());
+        let res = todays(&store, None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let last_modified = match res.headers().get(header::LAST_MODIFIED) {
+            Some(value) => value.to_str().unwrap().parse::<HttpDate>().unwrap(),
+            None => panic!("no Last-Modified"),
+        };
+        let last_modified = SystemTime::from(last_modified);
+
+        let res = todays(&store, Some(last_modified)).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(res.headers().contains_key(header::LAST_MODIFIED));
+        assert_eq!(answer(res).await, (StatusCode::NOT_MODIFIED, None));
+        let later = last_modified + Duration::from_secs(60);
+        assert_eq!(todays(&store, Some(later)).await.status(), StatusCode::NOT_MODIFIED);
+
+        // news stored since is sent in full
+        let earlier = last_modified - Duration::from_secs(1);
+        let (status, body) = answer(todays(&store, Some(earlier)).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.unwrap()["content"], "Shamiko is going to go on date with Momo.");
+    }
+
+    /// a store holding one item for each of `days`
+    fn dated(days: &[&str]) -> web::Data<NewsStore> {
+        let news = days.iter().map(|day| news(day, &format!("{}のニュース", day))).collect();
+        web::Data::new(NewsStore { news: Mutex::new(news), waiters: Mutex::new(Vec::new()) })
+    }
+
+    async fn range(store: &web::Data<NewsStore>, query: &str) -> Result<Vec<String>, ApiError> {
+        let query = web::Query::<RangeQuery>::from_query(query).unwrap();
+        let res = shami_momo_range(query, store.clone())
+            .await
+            .map_err(|e| e.as_error::<ApiError>().unwrap().clone())?;
+        let body = answer(res).await.1.unwrap();
+        Ok(body.as_array().unwrap().iter().map(|n| n["day"].as_str().unwrap().to_string()).collect())
+    }
+
+    #[actix_rt::test]
+    async fn a_range_includes_both_its_ends_oldest_first() {
+        let store = dated(&["2024-01-04", "2024-01-02", "2023-12-31", "2024-01-03", "2024-01-01"]);
+        let days = range(&store, "from=2024-01-02&to=2024-01-03").await.unwrap();
+        assert_eq!(days, ["2024-01-02", "2024-01-03"]);
+        let days = range(&store, "from=2023-12-31&to=2024-01-04").await.unwrap();
+        assert_eq!(days, ["2023-12-31", "2024-01-01", "2024-01-02", "2024-01-03", "2024-01-04"]);
+        assert_eq!(range(&store, "from=2024-01-01&to=2024-01-01").await.unwrap(), ["2024-01-01"]);
+        assert!(range(&store, "from=2025-01-01&to=2025-12-31").await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn a_backwards_or_malformed_range_is_400() {
+        let store = dated(&["2024-01-01"]);
+        let e = range(&store, "from=2024-01-02&to=2024-01-01").await.unwrap_err();
+        assert_eq!(e.code(), "bad_request");
+        assert!(e.to_string().contains("from (2024-01-02) must not be after to (2024-01-01)"), "{}", e);
+
+        let malformed =
+            ["from=2024-1-1x&to=2024-01-02", "from=2024-01-01&to=2024-02-30", "from=today&to=2024-01-02"];
+        for query in malformed {
+            let e = range(&store, query).await.unwrap_err();
+            assert_eq!(e.code(), "bad_request", "{}", query);
+            assert!(e.to_string().contains("must be a YYYY-MM-DD date"), "{}: {}", query, e);
+        }
+    }
+
+    #[test]
+    fn days_are_iso_dates_or_today() {
+        let day: Day = "2024-02-29".parse().unwrap();
+        assert_eq!(day.to_string(), "2024-02-29");
+        assert_eq!(serde_json::to_value(day).unwrap(), json!("2024-02-29"));
+        assert_eq!("today".parse::<Day>().unwrap(), Day::today());
+        assert_eq!(serde_json::from_value::<Day>(json!("today")).unwrap(), Day::today());
+        assert!("2024-01-01".parse::<Day>().unwrap() < day);
+
+        for bad in ["2023-02-29", "2024/01/01", "20240101", "Today", "tomorrow", ""] {
+            let e = bad.parse::<Day>().unwrap_err();
+            assert!(e.contains("YYYY-MM-DD date or \"today\""), "{:?}: {}", bad, e);
+        }
+        assert!(serde_json::from_value::<Day>(json!("yesterday")).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn news_posted_for_today_is_dated_today() {
+        let store = dated(&[]);
+        for item in [json!({"day": "today", "content": "a"}), json!({"content": "b"})] {
+            let req = TestRequest::post().to_http_request();
+            let item = web::Json(serde_json::from_value::<NewNews>(item).unwrap());
+            let (status, body) = answer(post_shami_momo(req, item, store.clone()).await.unwrap()).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(body.unwrap()["day"], Day::today().to_string());
+        }
+        assert!(store.all().unwrap().iter().all(|n| n.day() == Day::today()));
+    }
+
+    #[test]
+    fn news_renders_as_a_sentence_dated_today_or_by_day() {
+        let item = news("2024-01-02", "シャミ子が桃とデートに行く。");
+        assert_eq!(render_text(&item, "2024-01-02".parse().unwrap()), "[today] シャミ子が桃とデートに行く。");
+        assert_eq!(
+            render_text(&item, "2024-01-03".parse().unwrap()),
+            "[2024-01-02] シャミ子が桃とデートに行く。"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn todays_news_as_text_is_utf_8() {
+        let store = dated(&[]);
+        store.insert(news("today", "まぞくが街にいる")).unwrap();
+        let req = TestRequest::get().to_http_request();
+        let query = web::Query::<FormatQuery>::from_query("format=text").unwrap();
+        let res = todays_shami_momo(req, query, store).await.unwrap();
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/plain; charset=utf-8");
+        let res = ServiceResponse::new(TestRequest::default().to_http_request(), res);
+        assert_eq!(test::read_body(res).await, "[today] まぞくが街にいる".as_bytes());
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_rt::time::timeout;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::env_or;
+use crate::error::ApiError;
+
+/// keep at most `MAX_OUTBOUND_REQUESTS` httpbin requests in flight at once
+/// (0, unlimited, by default), across every handler and worker, so a burst of
+/// inbound requests doesn't open as many upstream connections. A request
+/// waits at most `OUTBOUND_WAIT_MS` (5000 by default) for its turn and fails
+/// with `503` after that.
+#[derive(Debug, Clone, Default)]
+pub struct OutboundLimit {
+    limit: Option<(Arc<Semaphore>, Duration)>,
+}
+
+impl OutboundLimit {
+    pub fn from_env() -> Self {
+        let max: usize = env_or("MAX_OUTBOUND_REQUESTS", 0);
+        let limit = if max == 0 {
+            None
+        } else {
+            Some((Arc::new(Semaphore::new(max)), Duration::from_millis(env_or("OUTBOUND_WAIT_MS", 5000))))
+        };
+        OutboundLimit { limit }
+    }
+
+    /// the go-ahead for one request. The permit, if there's a limit, goes
+    /// back when it is dropped, so however the request ends.
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, ApiError> {
+        let (permits, wait) = match &self.limit {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let permit = timeout(*wait, permits.clone().acquire_owned()).await.map_err(|_| {
+            ApiError::Unavailable(format!("no outbound connection freed up within {}ms", wait.as_millis()))
+        })?;
+        Ok(Some(permit))
+    }
+}
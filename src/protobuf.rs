@@ -0,0 +1,216 @@
+//! team lists in the protobuf encoding of `proto/teams.proto`'s `TeamList`,
+//! written by hand: the messages are small and flat enough that the wire
+//! format (varints and length-delimited fields) is all there is to it. As
+//! proto3 does, fields holding their default value are left out.
+
+use crate::teams::Team;
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// the wire type of `uint32` fields
+const VARINT: u8 = 0;
+/// the wire type of `string` and message fields
+const LENGTH_DELIMITED: u8 = 2;
+
+fn varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn key(out: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(out, u64::from(field << 3 | u32::from(wire_type)));
+}
+
+fn bytes_field(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    key(out, field, LENGTH_DELIMITED);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn string_field(out: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        bytes_field(out, field, value.as_bytes());
+    }
+}
+
+fn uint32_field(out: &mut Vec<u8>, field: u32, value: u32) {
+    if value != 0 {
+        key(out, field, VARINT);
+        varint(out, u64::from(value));
+    }
+}
+
+/// one `Team` message
+fn team(team: &Team) -> Vec<u8> {
+    let mut out = Vec::new();
+    string_field(&mut out, 1, team.abbreviation());
+    string_field(&mut out, 2, team.active_area());
+    uint32_field(&mut out, 3, team.join_year());
+    string_field(&mut out, 4, team.division());
+    for label in team.labels() {
+        // repeated strings are sent even when empty, to keep their place
+        bytes_field(&mut out, 5, label.as_bytes());
+    }
+    out
+}
+
+/// a `TeamList` message holding `teams`
+pub fn team_list(teams: &[Team]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for t in teams {
+        bytes_field(&mut out, 1, &team(t));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::teams;
+    use crate::test_support::{self, call};
+
+    const PROTO: &str = include_str!("../proto/teams.proto");
+
+    /// the field numbers of `message` in `PROTO`, by name
+    fn proto_fields(message: &str) -> HashMap<&'static str, u64> {
+        let start = PROTO.find(&format!("message {} {{", message)).expect("message in teams.proto");
+        let body = &PROTO[start..start + PROTO[start..].find('}').unwrap()];
+        body.lines()
+            .filter_map(|line| {
+                let (decl, number) = line.trim().trim_end_matches(';').split_once(" = ")?;
+                Some((decl.rsplit(' ').next()?, number.parse().ok()?))
+            })
+            .collect()
+    }
+
+    fn read_varint(bytes: &mut &[u8]) -> u64 {
+        let mut n = 0;
+        for shift in (0..64).step_by(7) {
+            let (b, rest) = bytes.split_first().expect("varint cut short");
+            *bytes = rest;
+            n |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        n
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Value<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    /// every field of a message, in order, as `(number, value)`
+    fn decode(mut bytes: &[u8]) -> Vec<(u64, Value<'_>)> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes);
+            let value = match (key & 7) as u8 {
+                VARINT => Value::Varint(read_varint(&mut bytes)),
+                LENGTH_DELIMITED => {
+                    let len = read_varint(&mut bytes) as usize;
+                    let (value, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    Value::Bytes(value)
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    /// a `Team` message read back as the json `Team` serializes to
+    fn decode_team(bytes: &[u8]) -> serde_json::Value {
+        let numbers = proto_fields("Team");
+        let name_of = |number| *numbers.iter().find(|(_, n)| **n == number).expect("field in teams.proto").0;
+        let mut team = json!({
+            "team_abbreviation": "", "active_area": "", "join_year": 0, "division": "", "labels": [],
+        });
+        for (number, value) in decode(bytes) {
+            let name = name_of(number);
+            match value {
+                Value::Varint(n) => team[name] = n.into(),
+                Value::Bytes(b) if name == "labels" => {
+                    team[name].as_array_mut().unwrap().push(String::from_utf8(b.to_vec()).unwrap().into())
+                }
+                Value::Bytes(b) => team[name] = String::from_utf8(b.to_vec()).unwrap().into(),
+            }
+        }
+        team
+    }
+
+    /// a `TeamList` message read back as json
+    fn decode_team_list(bytes: &[u8]) -> serde_json::Value {
+        let teams_field = proto_fields("TeamList")["teams"];
+        decode(bytes)
+            .into_iter()
+            .map(|(number, value)| match value {
+                Value::Bytes(team) if number == teams_field => decode_team(team),
+                other => panic!("unexpected field {} {:?}", number, other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_seed_team_encodes_to_known_bytes() {
+        let teams = teams::seed_teams();
+        let expected: &[u8] = &[
+            0x0a, 0x1a, 0x0a, 0x06, 0xe9, 0xb9, 0xbf, 0xe5, 0xb3, 0xb6, 0x12, 0x09, 0xe8, 0x8c, 0xa8, 0xe5,
+            0x9f, 0x8e, 0xe7, 0x9c, 0x8c, 0x18, 0xc7, 0x0f, 0x22, 0x02, 0x4a, 0x31,
+        ];
+        assert_eq!(team_list(&teams[..1]), expected);
+    }
+
+    #[test]
+    fn team_lists_decode_back_by_the_proto_field_numbers() {
+        let mut listed = serde_json::to_value(teams::seed_teams()).unwrap();
+        let labelled: teams::Team = serde_json::from_value(json!({
+            "team_abbreviation": "甲府",
+            "active_area": "山梨県",
+            "join_year": 1999,
+            "division": "J2",
+            "labels": ["甲斐", ""],
+        }))
+        .unwrap();
+        listed.as_array_mut().unwrap().push(serde_json::to_value(&labelled).unwrap());
+        let mut teams = teams::seed_teams();
+        teams.push(labelled);
+
+        assert_eq!(decode_team_list(&team_list(&teams)), listed);
+        assert!(team_list(&[]).is_empty());
+    }
+
+    #[test]
+    fn default_values_are_left_out() {
+        let team: teams::Team =
+            serde_json::from_value(json!({"active_area": "", "join_year": 0, "division": ""})).unwrap();
+        assert_eq!(team_list(&[team]), [0x0a, 0x00]);
+    }
+
+    #[actix_rt::test]
+    async fn team_lists_are_sent_as_protobuf_when_asked_for() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let req = TestRequest::get().uri("/api/v0/teams").header(header::ACCEPT, CONTENT_TYPE).to_request();
+        let res = actix_web::dev::Service::call(&mut app, req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), CONTENT_TYPE);
+        let body = actix_web::test::read_body(res).await;
+        assert_eq!(decode_team_list(&body), serde_json::to_value(teams::seed_teams()).unwrap());
+
+        let (_, body) = call(&mut app, TestRequest::get().uri("/api/v0/teams").to_request()).await;
+        test_support::assert_json(&body, serde_json::to_value(teams::seed_teams()).unwrap());
+    }
+}
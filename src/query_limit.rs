@@ -0,0 +1,185 @@
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{err, ok, Either, Ready};
+
+use crate::config::env_or;
+use crate::error::ApiError;
+
+/// reject requests whose raw query string is longer than `max_length` bytes
+/// (`414`) or carries more than `max_params` parameters (`400`), and likewise
+/// paths longer than `max_path_length` bytes or with more than
+/// `max_path_segments` segments
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimit {
+    max_length: usize,
+    max_params: usize,
+    max_path_length: usize,
+    max_path_segments: usize,
+}
+
+impl QueryLimit {
+    /// limits from `MAX_QUERY_LENGTH` (default 2048), `MAX_QUERY_PARAMS`
+    /// (default 32), `MAX_PATH_LENGTH` (default 1024) and `MAX_PATH_SEGMENTS`
+    /// (default 16)
+    pub fn from_env() -> Self {
+        QueryLimit {
+            max_length: env_or("MAX_QUERY_LENGTH", 2048),
+            max_params: env_or("MAX_QUERY_PARAMS", 32),
+            max_path_length: env_or("MAX_PATH_LENGTH", 1024),
+            max_path_segments: env_or("MAX_PATH_SEGMENTS", 16),
+        }
+    }
+
+    fn check_path(&self, path: &str) -> Result<(), ApiError> {
+        if path.len() > self.max_path_length {
+            return Err(ApiError::UriTooLong(format!(
+                "path must be at most {} bytes",
+                self.max_path_length
+            )));
+        }
+
+        let segments = path.split('/').filter(|s| !s.is_empty()).count();
+        if segments > self.max_path_segments {
+            return Err(ApiError::BadRequest(format!(
+                "path must have at most {} segments",
+                self.max_path_segments
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, query: &str) -> Result<(), ApiError> {
+        if query.len() > self.max_length {
+            return Err(ApiError::UriTooLong(format!(
+                "query string must be at most {} bytes",
+                self.max_length
+            )));
+        }
+
+        let params = query.split('&').filter(|p| !p.is_empty()).count();
+        if params > self.max_params {
+            return Err(ApiError::BadRequest(format!(
+                "query string must have at most {} parameters",
+                self.max_params
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl<S, B> Transform<S> for QueryLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = QueryLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(QueryLimitMiddleware { service, limit: *self })
+    }
+}
+
+pub struct QueryLimitMiddleware<S> {
+    service: S,
+    limit: QueryLimit,
+}
+
+impl<S, B> Service for QueryLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let checked = self
+            .limit
+            .check_path(req.path())
+            .and_then(|()| self.limit.check(req.query_string()));
+        match checked {
+            Ok(()) => Either::Left(self.service.call(req)),
+            Err(e) => Either::Right(err(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::test_support;
+
+    const LIMIT: QueryLimit =
+        QueryLimit { max_length: 12, max_params: 3, max_path_length: 10, max_path_segments: 3 };
+
+    async fn send(uri: &str) -> (StatusCode, web::Bytes) {
+        let mut app = test::init_service(
+            App::new().wrap(LIMIT).default_service(web::route().to(HttpResponse::Ok)),
+        )
+        .await;
+        test_support::call(&mut app, test::TestRequest::with_uri(uri).to_request()).await
+    }
+
+    #[test]
+    fn query_length_is_allowed_up_to_the_limit() {
+        assert!(LIMIT.check("a=123456789").is_ok());
+        assert!(LIMIT.check("a=1234567890").is_ok());
+        assert!(matches!(LIMIT.check("a=12345678901"), Err(ApiError::UriTooLong(_))));
+    }
+
+    #[test]
+    fn params_are_allowed_up_to_the_limit() {
+        assert!(LIMIT.check("a&b").is_ok());
+        assert!(LIMIT.check("a&b&c").is_ok());
+        assert!(matches!(LIMIT.check("a&b&c&d"), Err(ApiError::BadRequest(_))));
+        // empty pairs aren't parameters
+        assert!(LIMIT.check("a&&b&&c&").is_ok());
+    }
+
+    #[test]
+    fn path_length_is_allowed_up_to_the_limit() {
+        assert!(LIMIT.check_path("/123456789").is_ok());
+        assert!(matches!(LIMIT.check_path("/1234567890"), Err(ApiError::UriTooLong(_))));
+        assert!(LIMIT.check_path("/12345678").is_ok());
+    }
+
+    #[test]
+    fn segments_are_allowed_up_to_the_limit() {
+        assert!(LIMIT.check_path("/a/b").is_ok());
+        assert!(LIMIT.check_path("/a/b/c").is_ok());
+        assert!(matches!(LIMIT.check_path("/a/b/c/d"), Err(ApiError::BadRequest(_))));
+        // repeated and trailing slashes don't make segments
+        assert!(LIMIT.check_path("//a//b/c/").is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn requests_over_the_limits_are_rejected_before_the_handler() {
+        assert_eq!(send("/?a=1234567890").await.0, StatusCode::OK);
+        let (status, body) = send("/?a=12345678901").await;
+        assert_eq!(status, StatusCode::URI_TOO_LONG);
+        test_support::assert_error_code(&body, "uri_too_long");
+        assert_eq!(send("/?a&b&c").await.0, StatusCode::OK);
+        assert_eq!(send("/?a&b&c&d").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send("/a/b/c").await.0, StatusCode::OK);
+        assert_eq!(send("/a/b/c/d").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send("/1234567890").await.0, StatusCode::URI_TOO_LONG);
+    }
+}
@@ -0,0 +1,203 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_rt::time::timeout;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use tokio::sync::Semaphore;
+
+use crate::config::env_or;
+use crate::error::ApiError;
+use crate::warmup::HEALTH_PATH;
+
+/// serve at most `MAX_CONCURRENT_REQUESTS` requests at once (0, unlimited, by
+/// default). Excess requests wait in a queue up to `QUEUE_DEPTH` deep (0 by
+/// default) for at most `QUEUE_WAIT_MS` (1000 by default); they're answered
+/// `503` only when the queue is full or the wait runs out. Every worker
+/// shares the same permits.
+#[derive(Debug, Clone)]
+pub struct RequestQueue {
+    limit: Option<Arc<Limit>>,
+}
+
+#[derive(Debug)]
+struct Limit {
+    permits: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    depth: usize,
+    wait: Duration,
+}
+
+impl RequestQueue {
+    pub fn from_env() -> Self {
+        RequestQueue::new(
+            env_or("MAX_CONCURRENT_REQUESTS", 0),
+            env_or("QUEUE_DEPTH", 0),
+            Duration::from_millis(env_or("QUEUE_WAIT_MS", 1000)),
+        )
+    }
+
+    /// `concurrent` requests at once, 0 for no limit, with up to `depth` more
+    /// waiting for at most `wait`
+    pub fn new(concurrent: usize, depth: usize, wait: Duration) -> Self {
+        let limit = if concurrent == 0 {
+            None
+        } else {
+            Some(Arc::new(Limit {
+                permits: Arc::new(Semaphore::new(concurrent)),
+                waiting: AtomicUsize::new(0),
+                depth,
+                wait,
+            }))
+        };
+        RequestQueue { limit }
+    }
+}
+
+/// gives the queue slot back however the wait ends
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<S, B> Transform<S> for RequestQueue
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestQueueMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestQueueMiddleware { service: Rc::new(RefCell::new(service)), limit: self.limit.clone() })
+    }
+}
+
+pub struct RequestQueueMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    limit: Option<Arc<Limit>>,
+}
+
+impl<S, B> Service for RequestQueueMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let limit = match &self.limit {
+            Some(limit) if req.path() != HEALTH_PATH => limit.clone(),
+            _ => {
+                let fut = self.service.borrow_mut().call(req);
+                return Box::pin(fut);
+            }
+        };
+        let service = self.service.clone();
+        Box::pin(async move {
+            // the permit lives until the response is ready, on every path
+            let _permit = match limit.permits.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    if limit.waiting.fetch_add(1, Ordering::SeqCst) >= limit.depth {
+                        limit.waiting.fetch_sub(1, Ordering::SeqCst);
+                        return Err(ApiError::Unavailable("the request queue is full".to_string()).into());
+                    }
+                    let _slot = Waiting(&limit.waiting);
+                    timeout(limit.wait, limit.permits.clone().acquire_owned()).await.map_err(|_| {
+                        ApiError::Unavailable(format!(
+                            "no capacity freed up within {}ms",
+                            limit.wait.as_millis()
+                        ))
+                    })?
+                }
+            };
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use futures::future::join_all;
+
+    use super::*;
+
+    /// the status of each of `count` requests sent at once to a handler taking
+    /// `busy` to answer, behind `queue`, along with how long they all took
+    async fn burst(queue: RequestQueue, busy: Duration, count: usize) -> (Vec<StatusCode>, Duration) {
+        let handler = move || async move {
+            actix_rt::time::delay_for(busy).await;
+            Ok::<_, Error>(HttpResponse::Ok().finish())
+        };
+        let mut app = test::init_service(App::new().wrap(queue).route("/", web::get().to(handler))).await;
+        let started = Instant::now();
+        let calls = (0..count).map(|_| app.call(TestRequest::get().uri("/").to_request()));
+        let statuses = join_all(calls.collect::<Vec<_>>())
+            .await
+            .into_iter()
+            .map(|res| match res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            })
+            .collect();
+        (statuses, started.elapsed())
+    }
+
+    #[actix_rt::test]
+    async fn queued_requests_are_served_once_a_permit_frees_up() {
+        let queue = RequestQueue::new(1, 2, Duration::from_secs(5));
+        let (statuses, took) = burst(queue, Duration::from_millis(50), 3).await;
+        assert_eq!(statuses, [StatusCode::OK; 3]);
+        assert!(took >= Duration::from_millis(150), "served in {:?}, not one at a time", took);
+    }
+
+    #[actix_rt::test]
+    async fn requests_past_the_queue_depth_are_503() {
+        let queue = RequestQueue::new(1, 1, Duration::from_secs(5));
+        let (statuses, _) = burst(queue, Duration::from_millis(50), 3).await;
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+    }
+
+    #[actix_rt::test]
+    async fn requests_waiting_past_the_queue_wait_are_503() {
+        let queue = RequestQueue::new(1, 1, Duration::from_millis(20));
+        let (statuses, _) = burst(queue, Duration::from_millis(200), 2).await;
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+    }
+
+    #[actix_rt::test]
+    async fn no_limit_serves_everything_at_once() {
+        let queue = RequestQueue::new(0, 0, Duration::from_secs(5));
+        let (statuses, took) = burst(queue, Duration::from_millis(50), 5).await;
+        assert_eq!(statuses, [StatusCode::OK; 5]);
+        assert!(took < Duration::from_millis(250), "took {:?}", took);
+    }
+}
@@ -0,0 +1,473 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_web::client::{Client, Connector};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::http::{header, HeaderValue, Method};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::{Stream, StreamExt};
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use serde::{Deserialize, Serialize};
+
+use crate::admin::require_admin;
+use crate::config::{env_or, Config};
+use crate::error::ApiError;
+use crate::request_id::RequestId;
+use crate::store;
+
+/// the most of a replayed request's response `POST /admin/replay/{id}` reads
+const REPLAY_RESPONSE_MAX: usize = 256 * 1024;
+
+/// the header a replayed request carries the original's id in
+const REPLAY_OF: &str = "x-replay-of";
+
+/// what it takes to send a recorded request again
+#[derive(Debug, Clone)]
+struct Replay {
+    method: Method,
+    /// the path and query string
+    uri: String,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Entry {
+    timestamp: String,
+    method: String,
+    path: String,
+    status: u16,
+    duration_ms: f64,
+    /// the request's id, as sent back in `REQUEST_ID_HEADER`
+    request_id: Option<String>,
+    /// whether `POST /admin/replay/{id}` can send it again
+    replayable: bool,
+    #[serde(skip)]
+    replay: Option<Replay>,
+}
+
+/// the last `RECENT_REQUESTS` requests (100 by default, 0 to keep none),
+/// newest last, in one buffer shared by every worker. A request is recorded
+/// once its response starts, so a streamed one's duration doesn't include
+/// the streaming. Bodies declaring a `Content-Length` of at most
+/// `RECENT_REQUEST_BODY_MAX` bytes (4096 by default, 0 for bodiless requests
+/// only) are kept with the request for replaying. They aren't buffered ahead
+/// of the handler but copied aside as it reads them, so the body timeout
+/// still applies, and a request whose handler hadn't read all of its body by
+/// the time the response started is recorded without it. Chunked uploads and
+/// websocket upgrades are recorded without theirs.
+#[derive(Debug, Clone)]
+pub struct RecentRequests {
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+    capacity: usize,
+    body_max: usize,
+}
+
+impl RecentRequests {
+    /// a buffer of the last `capacity` requests, keeping bodies of up to
+    /// `body_max` bytes
+    pub fn new(capacity: usize, body_max: usize) -> Self {
+        let entries = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+        RecentRequests { entries, capacity, body_max }
+    }
+
+    pub fn from_env() -> Self {
+        RecentRequests::new(env_or("RECENT_REQUESTS", 100), env_or("RECENT_REQUEST_BODY_MAX", 4096))
+    }
+
+    /// the length of `req`'s body when it is to be kept for replaying
+    fn kept_body_len(&self, req: &ServiceRequest) -> Option<usize> {
+        let chunked = req.headers().contains_key(header::TRANSFER_ENCODING);
+        if self.capacity == 0 || req.head().upgrade() || chunked {
+            return None;
+        }
+        let len = match req.headers().get(header::CONTENT_LENGTH) {
+            Some(len) => len.to_str().ok()?.parse().ok()?,
+            None => 0,
+        };
+        Some(len).filter(|len| *len <= self.body_max)
+    }
+
+    fn record(&self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        // losing an entry is better than failing the request it describes
+        if let Ok(mut entries) = store::lock(&self.entries, "recent requests") {
+            if entries.len() == self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RecentRequests
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RecentRequestsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RecentRequestsMiddleware { service: Rc::new(RefCell::new(service)), recent: self.clone() })
+    }
+}
+
+pub struct RecentRequestsMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    recent: RecentRequests,
+}
+
+impl<S, B> Service for RecentRequestsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let recent = self.recent.clone();
+        let started = Instant::now();
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+        let kept = recent.kept_body_len(&req).map(|len| {
+            let kept = Rc::new(RefCell::new(BytesMut::with_capacity(len)));
+            let payload = KeptPayload { payload: req.take_payload(), kept: kept.clone(), max: len };
+            req.set_payload(Payload::Stream(Box::pin(payload)));
+            let replay = Replay {
+                method: req.method().clone(),
+                uri: req.uri().path_and_query().map_or_else(|| path.clone(), ToString::to_string),
+                content_type: req.headers().get(header::CONTENT_TYPE).cloned(),
+                body: Bytes::new(),
+            };
+            (replay, len, kept)
+        });
+        let fut = self.service.borrow_mut().call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            let replay = kept.and_then(|(replay, len, kept)| {
+                let body = kept.borrow_mut().split().freeze();
+                Some(Replay { body, ..replay }).filter(|replay| replay.body.len() == len)
+            });
+            recent.record(Entry {
+                timestamp,
+                method,
+                path,
+                status: status.as_u16(),
+                duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+                request_id,
+                replayable: replay.is_some(),
+                replay,
+            });
+            res
+        })
+    }
+}
+
+/// a request payload passed through unchanged, copying it into `kept` as it
+/// goes, up to one byte past `max` so a body longer than that shows
+struct KeptPayload {
+    payload: Payload,
+    kept: Rc<RefCell<BytesMut>>,
+    max: usize,
+}
+
+impl Stream for KeptPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let polled = self.payload.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            let mut kept = self.kept.borrow_mut();
+            let room = (self.max + 1).saturating_sub(kept.len());
+            kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        }
+        polled
+    }
+}
+
+fn default_n() -> usize {
+    20
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentQuery {
+    #[serde(default = "default_n")]
+    n: usize,
+}
+
+/// `GET /admin/recent-requests?n=20`: the last `n` requests recorded, newest
+/// first
+pub async fn recent_requests(
+    req: HttpRequest,
+    query: web::Query<RecentQuery>,
+    config: web::Data<Config>,
+    recent: web::Data<RecentRequests>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let res: Vec<Entry> =
+        store::lock(&recent.entries, "recent requests")?.iter().rev().take(query.n).cloned().collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct ReplayReport {
+    id: String,
+    method: String,
+    uri: String,
+    recorded_status: u16,
+    status: u16,
+    /// the replayed request's own id
+    replay_request_id: Option<String>,
+    /// the replayed response's body, lossily decoded as UTF-8 and cut off
+    /// after `REPLAY_RESPONSE_MAX` bytes
+    body: String,
+}
+
+/// where this server can be reached from itself: its own port on the
+/// loopback interface, unless it listens on one address only
+fn own_url(config: &Config) -> String {
+    let scheme = if config.tls_cert_file.is_some() { "https" } else { "http" };
+    let host = match config.host.as_str() {
+        "0.0.0.0" | "" => "127.0.0.1",
+        "::" => "[::1]",
+        host => host,
+    };
+    format!("{}://{}:{}", scheme, host, config.port)
+}
+
+/// a client for calls back into this server; its certificate is whatever
+/// `TLS_CERT_FILE` holds, often self-signed, so it isn't verified
+fn own_client(config: &Config) -> Result<Client, ApiError> {
+    let mut ssl = SslConnector::builder(SslMethod::tls()).map_err(|e| ApiError::Internal(e.to_string()))?;
+    ssl.set_verify(SslVerifyMode::NONE);
+    Ok(Client::build()
+        .connector(Connector::new().ssl(ssl.build()).finish())
+        .timeout(config.client_timeout)
+        .finish())
+}
+
+/// `POST /admin/replay/{id}`: send the recent request with id `{id}` to this
+/// server again, through every middleware like any other request, and answer
+/// with its fresh status and body next to the status recorded the first
+/// time. Only the method, path, query string, `Content-Type` and body are
+/// replayed; the recorded request's other headers aren't kept, so the replay
+/// carries this call's own `X-API-Key` or `Authorization` instead, and
+/// `X-Replay-Of: {id}`. Requests recorded without their body (see
+/// `RecentRequests`) and replays of replays are a `409`. The replay changes
+/// whatever the original did, again.
+pub async fn replay(
+    req: HttpRequest,
+    id: web::Path<String>,
+    config: web::Data<Config>,
+    recent: web::Data<RecentRequests>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let entry = store::lock(&recent.entries, "recent requests")?
+        .iter()
+        .rev()
+        .find(|e| e.request_id.as_deref() == Some(id.as_str()))
+        .cloned()
+        .ok_or_else(|| ApiError::NotFound(format!("no recent request has id {}", id)))?;
+    let replay = entry.replay.ok_or_else(|| {
+        ApiError::Conflict(format!("request {} was recorded without its body, so it can't be replayed", id))
+    })?;
+    if replay.uri.starts_with("/admin/replay/") {
+        return Err(ApiError::Conflict(format!("request {} is itself a replay", id)).into());
+    }
+
+    let mut outbound = own_client(&config)?
+        .request(replay.method.clone(), format!("{}{}", own_url(&config), replay.uri))
+        .header(REPLAY_OF, id.as_str());
+    if let Some(content_type) = &replay.content_type {
+        outbound = outbound.header(header::CONTENT_TYPE, content_type.clone());
+    }
+    for name in &[header::AUTHORIZATION, header::HeaderName::from_static("x-api-key")] {
+        if let Some(value) = req.headers().get(name) {
+            outbound = outbound.header(name.clone(), value.clone());
+        }
+    }
+    let mut res = outbound
+        .send_body(replay.body)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("couldn't replay request {}: {}", id, e)))?;
+    // read no more than `REPLAY_RESPONSE_MAX` and leave the rest unread
+    let mut body = BytesMut::new();
+    while body.len() < REPLAY_RESPONSE_MAX {
+        let chunk = match res.next().await {
+            Some(chunk) => chunk,
+            None => break,
+        };
+        let chunk =
+            chunk.map_err(|e| ApiError::Upstream(format!("couldn't read the replay of {}: {}", id, e)))?;
+        let room = REPLAY_RESPONSE_MAX - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+    let report = ReplayReport {
+        id: id.into_inner(),
+        method: replay.method.to_string(),
+        uri: replay.uri,
+        recorded_status: entry.status,
+        status: res.status().as_u16(),
+        replay_request_id: res
+            .headers()
+            .get(&config.request_id_header)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&report)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+
+    use super::*;
+    use crate::config::Secret;
+    use crate::test_support::{self, call};
+
+    fn admin_config() -> Config {
+        let mut config = test_support::config();
+        config.enable_admin = true;
+        config.api_key = Some(Secret::new("admin key".to_string()));
+        config
+    }
+
+    /// the app with `recent` recording in front of an echo route, a route
+    /// that ignores its body, and the admin endpoints of this module
+    async fn app(
+        recent: RecentRequests,
+    ) -> impl Service<Request = actix_http::Request, Response = ServiceResponse, Error = Error> {
+        let app = App::new()
+            .wrap(recent.clone())
+            .app_data(web::Data::new(admin_config()))
+            .app_data(web::Data::new(recent))
+            .route("/echo", web::post().to(|body: Bytes| async move { body }))
+            .route("/ignore", web::post().to(HttpResponse::NoContent))
+            .route("/admin/recent-requests", web::get().to(recent_requests))
+            .route("/admin/replay/{id}", web::post().to(replay));
+        actix_web::test::init_service(app).await
+    }
+
+    fn admin(req: TestRequest) -> actix_http::Request {
+        req.header("x-api-key", "admin key").to_request()
+    }
+
+    fn entry(request_id: &str, replay: Option<Replay>) -> Entry {
+        Entry {
+            timestamp: String::new(),
+            method: "POST".to_string(),
+            path: "/echo".to_string(),
+            status: 200,
+            duration_ms: 0.0,
+            request_id: Some(request_id.to_string()),
+            replayable: replay.is_some(),
+            replay,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn a_body_is_kept_only_once_the_handler_has_read_it_all() {
+        let recent = RecentRequests::new(10, 16);
+        let mut app = app(recent.clone()).await;
+        for (path, body) in [("/echo", "ping"), ("/ignore", "ping"), ("/echo", "more than sixteen bytes")] {
+            let req = TestRequest::post().uri(path).header(header::CONTENT_LENGTH, body.len());
+            let req = req.set_payload(body).to_request();
+            assert!(call(&mut app, req).await.0.is_success());
+        }
+        let entries = recent.entries.lock().unwrap();
+        let kept: Vec<_> = entries.iter().map(|e| e.replay.as_ref().map(|r| r.body.clone())).collect();
+        assert_eq!(kept, [Some(Bytes::from("ping")), None, None]);
+    }
+
+    #[actix_rt::test]
+    async fn replaying_an_unknown_bodiless_or_replayed_request_is_refused() {
+        let recent = RecentRequests::new(10, 16);
+        let uri = "/admin/replay/a".to_string();
+        let replayed = Replay { method: Method::POST, uri, content_type: None, body: Bytes::new() };
+        recent.record(entry("bodiless", None));
+        recent.record(entry("replay", Some(replayed)));
+        let mut app = app(recent).await;
+
+        let (status, body) = call(&mut app, admin(TestRequest::post().uri("/admin/replay/unknown"))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        test_support::assert_error_code(&body, "not_found");
+        for id in ["bodiless", "replay"] {
+            let uri = format!("/admin/replay/{}", id);
+            let (status, body) = call(&mut app, admin(TestRequest::post().uri(&uri))).await;
+            assert_eq!(status, StatusCode::CONFLICT, "{}", id);
+            test_support::assert_error_code(&body, "conflict");
+        }
+    }
+
+    /// `GET /admin/recent-requests` with `query`, `config` and `key`
+    async fn list(
+        recent: &RecentRequests,
+        query: &str,
+        config: Config,
+        key: &str,
+    ) -> Result<Vec<String>, Error> {
+        let req = TestRequest::get().header("x-api-key", key).to_http_request();
+        let query = web::Query::<RecentQuery>::from_query(query).unwrap();
+        let res = recent_requests(req, query, web::Data::new(config), web::Data::new(recent.clone())).await?;
+        let res = ServiceResponse::new(TestRequest::default().to_http_request(), res);
+        let entries = test_support::json(&actix_web::test::read_body(res).await);
+        let ids = entries.as_array().unwrap().iter().map(|e| e["request_id"].as_str().unwrap().to_string());
+        Ok(ids.collect())
+    }
+
+    #[actix_rt::test]
+    async fn the_buffer_keeps_the_last_capacity_requests_newest_first() {
+        let recent = RecentRequests::new(3, 0);
+        for id in ["a", "b", "c", "d", "e"] {
+            recent.record(entry(id, None));
+        }
+        assert_eq!(list(&recent, "", admin_config(), "admin key").await.unwrap(), ["e", "d", "c"]);
+        assert_eq!(list(&recent, "n=2", admin_config(), "admin key").await.unwrap(), ["e", "d"]);
+
+        let status = |e: Error| e.as_response_error().status_code();
+        let e = list(&recent, "", admin_config(), "wrong").await.unwrap_err();
+        assert_eq!(status(e), StatusCode::UNAUTHORIZED);
+        let e = list(&recent, "", test_support::config(), "admin key").await.unwrap_err();
+        assert_eq!(status(e), StatusCode::NOT_FOUND);
+    }
+}
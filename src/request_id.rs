@@ -0,0 +1,97 @@
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+/// the id of the request being handled, kept in its extensions: the inbound
+/// one, or a fresh one when the caller sent none
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// the id `req` was given by [`RequestIds`]
+pub fn of(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<RequestId>().map(|id| id.0.clone())
+}
+
+/// give every request an id, read from the `REQUEST_ID_HEADER` header
+/// (`X-Request-Id` by default) or made up when it's missing or unusable, and
+/// send it back in the same header. The httpbin calls made for the request
+/// carry it on, and the audit log and recent requests record it.
+#[derive(Debug, Clone)]
+pub struct RequestIds {
+    header: HeaderName,
+}
+
+impl RequestIds {
+    pub fn new(header: HeaderName) -> Self {
+        RequestIds { header }
+    }
+}
+
+fn fresh() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+impl<S, B> Transform<S> for RequestIds
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdsMiddleware { service, header: self.header.clone() })
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+    header: HeaderName,
+}
+
+impl<S, B> Service for RequestIdsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let id = req
+            .headers()
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(fresh);
+        // whatever made it through `to_str` is a valid header value again
+        let value = HeaderValue::from_str(&id).ok();
+        req.extensions_mut().insert(RequestId(id));
+
+        let header = self.header.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(value) = value {
+                res.headers_mut().insert(header, value);
+            }
+            Ok(res)
+        })
+    }
+}
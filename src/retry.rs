@@ -0,0 +1,90 @@
+use std::future::Future;
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+
+use crate::config::env_or;
+use crate::error::ApiError;
+
+/// how a failed httpbin call is retried: up to `retries` more attempts,
+/// waiting `base`, then twice that, and so on in between. Only failures that
+/// may pass on their own are retried, see [`retryable`].
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub retries: u32,
+    pub base: Duration,
+}
+
+impl Backoff {
+    /// `STEP_RETRIES` (0, no retries, by default) and `STEP_RETRY_BASE_MS`
+    /// (100 by default)
+    pub fn from_env() -> Self {
+        Backoff {
+            retries: env_or("STEP_RETRIES", 0),
+            base: Duration::from_millis(env_or("STEP_RETRY_BASE_MS", 100)),
+        }
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        self.base * 2u32.saturating_pow(retry)
+    }
+
+    /// run `attempt` until it succeeds, fails for good or runs out of
+    /// retries, along with how many attempts that took
+    pub async fn run<F, Fut, T>(&self, mut attempt: F) -> (Result<T, ApiError>, u32)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match attempt().await {
+                Err(e) if retryable(&e) && attempts <= self.retries => {
+                    let delay = self.delay(attempts - 1);
+                    log::debug!("attempt {} failed ({}), retrying in {}ms", attempts, e, delay.as_millis());
+                    delay_for(delay).await;
+                }
+                result => return (result, attempts),
+            }
+        }
+    }
+}
+
+/// upstream failures and timeouts may pass on their own; bad input won't, and
+/// neither will being unavailable here (offline mode, a full queue), which
+/// retrying would only wait out the backoff for
+fn retryable(e: &ApiError) -> bool {
+    matches!(e, ApiError::Upstream(_) | ApiError::Timeout(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::future::ready;
+
+    use super::*;
+
+    async fn attempts_for(e: ApiError) -> u32 {
+        let backoff = Backoff { retries: 2, base: Duration::from_millis(1) };
+        let calls = Cell::new(0);
+        let (result, attempts) = backoff
+            .run(|| {
+                calls.set(calls.get() + 1);
+                ready(Err::<(), _>(e.clone()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, calls.get());
+        attempts
+    }
+
+    #[actix_rt::test]
+    async fn only_upstream_failures_and_timeouts_are_retried() {
+        assert_eq!(attempts_for(ApiError::Upstream("502".to_string())).await, 3);
+        assert_eq!(attempts_for(ApiError::Timeout("slow".to_string())).await, 3);
+        assert_eq!(attempts_for(ApiError::Unavailable("offline".to_string())).await, 1);
+        assert_eq!(attempts_for(ApiError::BadRequest("bad".to_string())).await, 1);
+    }
+}
@@ -0,0 +1,400 @@
+//! With `WEBHOOK_SECRET` set, requests that can change the teams or the news
+//! (anything but `GET`, `HEAD` and `OPTIONS` under `/api/` and
+//! `/shami_momo`) must carry `X-Signature: sha256=<hex>`, the HMAC-SHA256 of
+//! the raw request body under the secret, and are answered `401` when it's
+//! missing or doesn't match. Unlike `API_KEY` this authenticates the body as
+//! well as the sender. The signature is compared in constant time; a bare hex
+//! digest without the `sha256=` prefix is taken too. Without the secret
+//! nothing is checked or buffered.
+//!
+//! The body is buffered, up to `MAX_BODY_BYTES`, and checked before the
+//! handler runs, except for the imports in `STREAMED_PATHS`, which would lose
+//! their bounded memory that way. Those are checked as they stream: the
+//! handler gets each chunk once the next one has arrived, and the last only
+//! when the whole body has matched, so a bad signature fails the request with
+//! `401` and the lines in its last chunk are never imported. The lines
+//! before it may already have been, the same as with a body cut off halfway
+//! through; send imports that mustn't be applied partially as a single chunk,
+//! or through `POST /api/v0/teams/import`.
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Payload, PayloadStream, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::http::Method;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+use futures::{stream, Stream, StreamExt};
+use openssl::memcmp;
+use openssl::sha::{sha256, Sha256};
+
+use crate::config::Secret;
+use crate::error::ApiError;
+
+/// the header the signature comes in
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// the endpoints that consume their body as it arrives, and so are checked
+/// as it does rather than buffered first
+const STREAMED_PATHS: &[&str] = &["/api/v0/teams/import/ndjson"];
+
+/// the signature check, for every worker; inert without a secret
+#[derive(Debug, Clone)]
+pub struct Signatures {
+    secret: Option<Arc<Secret>>,
+    /// the most body buffered to check, `MAX_BODY_BYTES`
+    max_body: usize,
+}
+
+impl Signatures {
+    pub fn new(secret: Option<Secret>, max_body: usize) -> Self {
+        Signatures { secret: secret.map(Arc::new), max_body }
+    }
+}
+
+/// whether `req` is one that has to be signed
+fn needs_signature(req: &ServiceRequest) -> bool {
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let path = req.path();
+    !safe && (path.starts_with("/api/") || path == "/shami_momo" || path.starts_with("/shami_momo/"))
+}
+
+/// HMAC-SHA256 (RFC 2104), fed the body a chunk at a time
+struct Hmac {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl Hmac {
+    fn new(secret: &Secret) -> Self {
+        let secret = secret.expose().as_bytes();
+        let mut key = [0; 64];
+        if secret.len() > key.len() {
+            key[..32].copy_from_slice(&sha256(secret));
+        } else {
+            key[..secret.len()].copy_from_slice(secret);
+        }
+        let (mut inner, mut outer) = (Sha256::new(), Sha256::new());
+        inner.update(&key.map(|b| b ^ 0x36));
+        outer.update(&key.map(|b| b ^ 0x5c));
+        Hmac { inner, outer }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.inner.update(bytes);
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        self.outer.update(&self.inner.finish());
+        self.outer.finish()
+    }
+}
+
+/// the digest in an `X-Signature` value, or `None` when it isn't hex
+fn presented(value: &str) -> Option<Vec<u8>> {
+    let hex = value.trim();
+    let hex = hex.strip_prefix("sha256=").unwrap_or(hex);
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// the digest `req` presents, or `401` when it presents none
+fn signature_of(req: &ServiceRequest) -> Result<Vec<u8>, ApiError> {
+    let value = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .ok_or_else(|| ApiError::Unauthorized("missing X-Signature".to_string()))?;
+    value.to_str().ok().and_then(presented).ok_or_else(invalid)
+}
+
+fn invalid() -> ApiError {
+    ApiError::Unauthorized("invalid X-Signature".to_string())
+}
+
+/// whether `hmac`, having been fed the whole body, is `signature`
+fn matches(hmac: Hmac, signature: &[u8]) -> bool {
+    let expected = hmac.finish();
+    signature.len() == expected.len() && memcmp::eq(signature, &expected)
+}
+
+/// how far `Verified` has got with the body it checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Check {
+    Reading,
+    Matched,
+    Failed,
+}
+
+/// a request body handed on as it is checked: each chunk once the next one
+/// has arrived, and the last only when the whole body matched the signature,
+/// or a payload error in place of it when it didn't
+struct Verified {
+    payload: Payload,
+    hmac: Option<Hmac>,
+    signature: Vec<u8>,
+    /// the chunk read but not yet handed on
+    held: Option<Bytes>,
+    check: Rc<Cell<Check>>,
+}
+
+impl Stream for Verified {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let hmac = match &mut this.hmac {
+                Some(hmac) => hmac,
+                None => return Poll::Ready(this.held.take().map(Ok)),
+            };
+            match Pin::new(&mut this.payload).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(chunk))) => {
+                    hmac.update(&chunk);
+                    if let Some(previous) = this.held.replace(chunk) {
+                        return Poll::Ready(Some(Ok(previous)));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    if matches(this.hmac.take().expect("checked above"), &this.signature) {
+                        this.check.set(Check::Matched);
+                    } else {
+                        this.check.set(Check::Failed);
+                        this.held = None;
+                        let e = io::Error::new(io::ErrorKind::PermissionDenied, "invalid X-Signature");
+                        return Poll::Ready(Some(Err(PayloadError::Io(e))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Signatures
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SignaturesMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SignaturesMiddleware { service: Rc::new(RefCell::new(service)), signatures: self.clone() })
+    }
+}
+
+pub struct SignaturesMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    signatures: Signatures,
+}
+
+impl<S, B> Service for SignaturesMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, LocalBoxFuture<'static, Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let secret = match &self.signatures.secret {
+            Some(secret) if needs_signature(&req) => secret.clone(),
+            _ => return Either::Left(self.service.borrow_mut().call(req)),
+        };
+        let max_body = self.signatures.max_body;
+        let service = self.service.clone();
+        Either::Right(Box::pin(async move {
+            let signature = signature_of(&req)?;
+            let mut hmac = Hmac::new(&secret);
+
+            if STREAMED_PATHS.contains(&req.path()) {
+                let check = Rc::new(Cell::new(Check::Reading));
+                let verified = Verified {
+                    payload: req.take_payload(),
+                    hmac: Some(hmac),
+                    signature,
+                    held: None,
+                    check: check.clone(),
+                };
+                req.set_payload(Payload::from(Box::pin(verified) as PayloadStream));
+                let fut = service.borrow_mut().call(req);
+                let res = fut.await?;
+                // a handler that answered without reading the whole body
+                // acted on one that was never checked
+                let unchecked = check.get() == Check::Reading && res.status().is_success();
+                if check.get() == Check::Failed || unchecked {
+                    return Err(invalid().into());
+                }
+                return Ok(res);
+            }
+
+            let mut body = BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                body.extend_from_slice(&chunk?);
+                if body.len() > max_body {
+                    let message = format!("request body is over the {} byte limit", max_body);
+                    return Err(ApiError::PayloadTooLarge(message).into());
+                }
+            }
+            let body = body.freeze();
+            hmac.update(&body);
+            if !matches(hmac, &signature) {
+                return Err(invalid().into());
+            }
+
+            let replay: PayloadStream = Box::pin(stream::once(async { Ok::<_, PayloadError>(body) }));
+            req.set_payload(Payload::from(replay));
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support;
+
+    const SECRET: &str = "s3cret";
+    const BODY: &str = r#"{"team_abbreviation":"甲府"}"#;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn signature(body: &str) -> String {
+        let mut hmac = Hmac::new(&Secret::new(SECRET.to_string()));
+        hmac.update(body.as_bytes());
+        hex(&hmac.finish())
+    }
+
+    async fn echo(body: web::Bytes) -> HttpResponse {
+        HttpResponse::Ok().body(body)
+    }
+
+    async fn send(req: test::TestRequest) -> (StatusCode, web::Bytes) {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Signatures::new(Some(Secret::new(SECRET.to_string())), 1024))
+                .route("/api/v0/teams", web::get().to(HttpResponse::Ok))
+                .route("/api/v0/teams", web::post().to(echo))
+                .route("/api/v0/teams/import/ndjson", web::post().to(echo)),
+        )
+        .await;
+        test_support::call(&mut app, req.to_request()).await
+    }
+
+    fn post(path: &str, signature: Option<String>) -> test::TestRequest {
+        let req = test::TestRequest::post().uri(path).set_payload(BODY);
+        match signature {
+            Some(signature) => req.header(SIGNATURE_HEADER, signature),
+            None => req,
+        }
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let mut hmac = Hmac::new(&Secret::new("Jefe".to_string()));
+        hmac.update(b"what do ya want for nothing?");
+        assert_eq!(hex(&hmac.finish()), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[actix_rt::test]
+    async fn correct_signature_passes_the_body_on() {
+        let signed = post("/api/v0/teams", Some(format!("sha256={}", signature(BODY))));
+        assert_eq!(send(signed).await, (StatusCode::OK, web::Bytes::from(BODY)));
+    }
+
+    #[actix_rt::test]
+    async fn bare_hex_digest_is_accepted() {
+        let signed = post("/api/v0/teams", Some(signature(BODY)));
+        assert_eq!(send(signed).await.0, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn wrong_signature_is_401() {
+        let signed = post("/api/v0/teams", Some(format!("sha256={}", signature("something else"))));
+        let (status, body) = send(signed).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        test_support::assert_json(&body, json!({"code": "unauthorized", "error": "invalid X-Signature"}));
+        let garbled = post("/api/v0/teams", Some("sha256=not hex".to_string()));
+        assert_eq!(send(garbled).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn missing_signature_is_401() {
+        let (status, body) = send(post("/api/v0/teams", None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        test_support::assert_json(&body, json!({"code": "unauthorized", "error": "missing X-Signature"}));
+    }
+
+    #[actix_rt::test]
+    async fn get_is_exempt() {
+        let req = test::TestRequest::get().uri("/api/v0/teams");
+        assert_eq!(send(req).await.0, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn streamed_import_is_checked_as_it_is_read() {
+        let path = "/api/v0/teams/import/ndjson";
+        let signed = post(path, Some(signature(BODY)));
+        assert_eq!(send(signed).await, (StatusCode::OK, web::Bytes::from(BODY)));
+        let wrong = post(path, Some(signature("something else")));
+        assert_eq!(send(wrong).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    fn verified(chunks: &[&'static str], signature: &str) -> (Verified, Rc<Cell<Check>>) {
+        let chunks: Vec<Result<Bytes, PayloadError>> = chunks.iter().map(|c| Ok(Bytes::from(*c))).collect();
+        let check = Rc::new(Cell::new(Check::Reading));
+        let verified = Verified {
+            payload: Payload::from(Box::pin(stream::iter(chunks)) as PayloadStream),
+            hmac: Some(Hmac::new(&Secret::new(SECRET.to_string()))),
+            signature: presented(signature).unwrap(),
+            held: None,
+            check: check.clone(),
+        };
+        (verified, check)
+    }
+
+    #[actix_rt::test]
+    async fn streamed_body_holds_its_last_chunk_back_until_checked() {
+        let (body, check) = verified(&["line 1\n", "line 2\n"], &signature("line 1\nline 2\n"));
+        let chunks: Vec<_> = body.collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(Result::is_ok));
+        assert_eq!(check.get(), Check::Matched);
+
+        let (body, check) = verified(&["line 1\n", "line 2\n"], &signature("line 1\n"));
+        let chunks: Vec<_> = body.collect().await;
+        assert_eq!(chunks[0].as_ref().ok(), Some(&Bytes::from("line 1\n")));
+        assert!(chunks[1].is_err());
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(check.get(), Check::Failed);
+    }
+}
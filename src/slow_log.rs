@@ -0,0 +1,78 @@
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::config::env_or;
+
+/// log a warning for every request whose handler takes longer than the
+/// threshold to produce a response. For streaming responses that is the time
+/// until the response starts, not until the last chunk is sent.
+#[derive(Debug, Clone, Copy)]
+pub struct SlowRequestLog {
+    threshold: Duration,
+}
+
+impl SlowRequestLog {
+    /// threshold from `SLOW_REQUEST_MS`, 1000 by default
+    pub fn from_env() -> Self {
+        SlowRequestLog { threshold: Duration::from_millis(env_or("SLOW_REQUEST_MS", 1000)) }
+    }
+}
+
+impl<S, B> Transform<S> for SlowRequestLog
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SlowRequestLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SlowRequestLogMiddleware { service, threshold: self.threshold })
+    }
+}
+
+pub struct SlowRequestLogMiddleware<S> {
+    service: S,
+    threshold: Duration,
+}
+
+impl<S, B> Service for SlowRequestLogMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let threshold = self.threshold;
+        let label = format!("{} {}", req.method(), req.path());
+        let started = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let elapsed = started.elapsed();
+            if elapsed > threshold {
+                log::warn!("slow request: {} took {}ms", label, elapsed.as_millis());
+            }
+            res
+        })
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::{Mutex, MutexGuard};
+
+use crate::error::ApiError;
+
+/// lock a store shared between workers. A handler that panicked while holding
+/// the lock leaves it poisoned; rather than letting every later `lock()` panic
+/// too, log it, recover the data, clear the poison so following requests go
+/// through, and fail just this request with a `500`.
+pub fn lock<'a, T>(mutex: &'a Mutex<T>, name: &str) -> Result<MutexGuard<'a, T>, ApiError> {
+    match mutex.lock() {
+        Ok(guard) => Ok(guard),
+        Err(poisoned) => {
+            log::error!("{} store lock was poisoned by a panicking handler; recovering", name);
+            drop(poisoned.into_inner());
+            mutex.clear_poison();
+            Err(ApiError::Internal(format!("the {} store was left in an inconsistent state", name)))
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderMap};
+use actix_web::Error;
+use futures::future::{err, ok, Either, Ready};
+
+use crate::config::env_flag;
+use crate::error::ApiError;
+
+/// the media types some endpoint can answer with
+const PRODUCES: &[&str] = &[
+    "application/json",
+    "application/vnd.api+json",
+    "application/x-ndjson",
+    "application/xml",
+    "application/x-protobuf",
+    "text/xml",
+    "text/event-stream",
+    "text/html",
+    "text/plain",
+    "text/csv",
+    "image/x-icon",
+];
+
+/// whether `range` (from an `Accept` header, parameters stripped) covers a
+/// type in `PRODUCES`
+fn covers(range: &str) -> bool {
+    match range {
+        "*/*" => true,
+        _ if range.ends_with("/*") => {
+            let kind = &range[..range.len() - 1];
+            PRODUCES.iter().any(|p| p.starts_with(kind))
+        }
+        _ => PRODUCES.contains(&range),
+    }
+}
+
+fn acceptable(headers: &HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT).filter_map(|v| v.to_str().ok()).any(|accept| {
+        accept.split(',').any(|m| {
+            let mut parts = m.split(';');
+            let range = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let refused = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .any(|q| q.trim().parse() == Ok(0.0));
+            !refused && covers(&range)
+        })
+    })
+}
+
+/// with `STRICT_ACCEPT=true`, answer `406` to requests whose `Accept` header is
+/// missing or names nothing the api produces, instead of assuming json.
+/// Off by default.
+#[derive(Debug, Clone, Copy)]
+pub struct StrictAccept {
+    enabled: bool,
+}
+
+impl StrictAccept {
+    pub fn from_env() -> Self {
+        StrictAccept { enabled: env_flag("STRICT_ACCEPT") }
+    }
+}
+
+impl<S, B> Transform<S> for StrictAccept
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = StrictAcceptMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(StrictAcceptMiddleware { service, enabled: self.enabled })
+    }
+}
+
+pub struct StrictAcceptMiddleware<S> {
+    service: S,
+    enabled: bool,
+}
+
+impl<S, B> Service for StrictAcceptMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !self.enabled || acceptable(req.headers()) {
+            return Either::Left(self.service.call(req));
+        }
+        let e = ApiError::NotAcceptable(format!(
+            "Accept must allow one of {}",
+            PRODUCES.join(", ")
+        ));
+        Either::Right(err(e.into()))
+    }
+}
@@ -0,0 +1,3719 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+use actix_web::dev::Payload;
+use actix_web::{
+    error::ErrorInternalServerError, http::header, web, web::BytesMut, Error, FromRequest, HttpRequest, HttpResponse,
+};
+use futures::future::{ready, Ready};
+use futures::StreamExt;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use openssl::sha::sha256;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::{Rng, SeedableRng};
+use regex::{Regex, RegexBuilder};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors};
+use validator_derive::Validate;
+
+use crate::audit;
+use crate::cache::Lru;
+use crate::config::Config;
+use crate::error::{field_errors, ApiError, FieldError};
+use crate::export;
+use crate::geo;
+use crate::locale::Lang;
+use crate::protobuf;
+use crate::store;
+
+#[derive(Debug, Clone, PartialEq, Validate, Serialize, Deserialize)]
+pub struct Team {
+    /// may be left out of `PUT /api/v0/teams/{abbr}`, which takes it from the
+    /// path
+    #[serde(default)]
+    #[validate(length(min = "1", message = "team_abbreviation must not be empty"))]
+    team_abbreviation: String,
+    active_area: String,
+    /// a number, or a numeric string from loosely typed clients; always sent
+    /// back as a number
+    /// no later than `CURRENT_YEAR`, which `validate_at` checks
+    #[serde(deserialize_with = "number_or_numeric_string")]
+    join_year: u32,
+    /// missing on `POST /api/v0/teams` means `DEFAULT_DIVISION`
+    #[serde(default)]
+    #[validate(custom = "validate_division")]
+    division: String,
+    /// free-form tags, at most `LABEL_MAX` characters each
+    #[serde(default)]
+    #[validate(custom = "validate_labels")]
+    labels: Vec<String>,
+    /// only sent with `?include_source=true`
+    #[serde(skip)]
+    source: Source,
+    /// set on the teams `SOFT_DELETE` keeps around after a delete, and only
+    /// sent for those
+    #[serde(skip_deserializing, skip_serializing_if = "std::ops::Not::not")]
+    deleted: bool,
+    /// when a deleted team was deleted, as RFC 3339
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<String>,
+}
+
+/// where a stored team came from, for `?source=` and `?include_source=true`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// the teams the store started with (the built-in ones or `TEAMS_FILE`),
+    /// or that a reset put back
+    Seed,
+    /// added since, by a `POST`, `PUT`, import or merge
+    #[default]
+    Runtime,
+}
+
+impl Team {
+    pub fn abbreviation(&self) -> &str {
+        &self.team_abbreviation
+    }
+
+    pub fn active_area(&self) -> &str {
+        &self.active_area
+    }
+
+    pub fn join_year(&self) -> u32 {
+        self.join_year
+    }
+
+    pub fn division(&self) -> &str {
+        &self.division
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// `validate`, and a `join_year` no later than `current_year`, which the
+    /// derived checks can't be handed
+    pub fn validate_at(&self, current_year: u32) -> Result<(), ValidationErrors> {
+        let mut errors = self.validate().err().unwrap_or_else(ValidationErrors::new);
+        if let Err(e) = join_year_not_after(self.join_year, current_year) {
+            errors.add("join_year", e);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// a made-up but valid team, numbered `i`, for load and benchmark data
+    pub fn synthetic(i: usize) -> Self {
+        Team {
+            team_abbreviation: format!("T{}", i),
+            active_area: format!("Area {}", i % 47),
+            join_year: 1991 + (i % 30) as u32,
+            division: DIVISIONS[i % DIVISIONS.len()].to_string(),
+            labels: Vec::new(),
+            source: Source::Runtime,
+            deleted: false,
+            deleted_at: None,
+        }
+    }
+}
+
+/// the longest label a team can carry, in characters
+const LABEL_MAX: usize = 32;
+
+// validator hands custom validators a `&Vec<String>` for `Vec<String>` fields
+#[allow(clippy::ptr_arg)]
+fn validate_labels(labels: &Vec<String>) -> Result<(), ValidationError> {
+    let bad = labels.iter().find(|l| l.trim().is_empty() || l.chars().count() > LABEL_MAX);
+    match bad {
+        None => Ok(()),
+        Some(_) => {
+            let mut err = ValidationError::new("invalid_label");
+            err.message = Some(Cow::from(format!(
+                "labels must be non-blank and at most {} characters",
+                LABEL_MAX
+            )));
+            Err(err)
+        }
+    }
+}
+
+/// `labels` added to `existing`, skipping ones already there
+fn add_labels(existing: &mut Vec<String>, labels: Vec<String>) {
+    for label in labels {
+        if !existing.contains(&label) {
+            existing.push(label);
+        }
+    }
+}
+
+/// the divisions a team can be registered in
+pub const DIVISIONS: &[&str] = &["J1", "J2"];
+
+fn validate_division(division: &str) -> Result<(), ValidationError> {
+    if DIVISIONS.contains(&division) {
+        return Ok(());
+    }
+
+    let mut err = ValidationError::new("unknown_division");
+    err.message = Some(Cow::from(format!("division must be one of {}", DIVISIONS.join(", "))));
+    Err(err)
+}
+
+/// the longest name a division can be renamed to, in characters
+const DIVISION_NAME_MAX: usize = 32;
+
+/// a division name a rename can use: any non-blank name of at most
+/// `DIVISION_NAME_MAX` characters, not only one of `DIVISIONS`
+fn validate_division_name(division: &str) -> Result<(), ValidationError> {
+    if !division.trim().is_empty() && division.chars().count() <= DIVISION_NAME_MAX {
+        return Ok(());
+    }
+
+    let mut err = ValidationError::new("invalid_division_name");
+    err.message = Some(Cow::from(format!(
+        "division names must be non-blank and at most {} characters",
+        DIVISION_NAME_MAX
+    )));
+    Err(err)
+}
+
+/// a `TeamStore` locked by `lock_for_reset` until dropped; nothing changes until
+/// `apply`
+pub struct TeamReset<'a> {
+    teams: MutexGuard<'a, Vec<Team>>,
+    deleted: MutexGuard<'a, Vec<Team>>,
+}
+
+impl TeamReset<'_> {
+    /// put the built-in teams back in place of whatever is stored, returning
+    /// how many teams that is; deleted teams are gone for good
+    pub fn apply(&mut self) -> usize {
+        *self.teams = seed_teams();
+        self.deleted.clear();
+        self.teams.len()
+    }
+}
+
+/// the character classes `ABBR_CHARSET` can allow in an abbreviation;
+/// `kana` is short for both `hiragana` and `katakana`
+pub const CHAR_CLASSES: &[&str] = &["kanji", "hiragana", "katakana", "kana", "latin", "digit"];
+
+fn in_class(class: &str, c: char) -> bool {
+    match class {
+        // CJK unified ideographs and extension A, plus the 々 repetition mark
+        "kanji" => ('\u{4E00}'..='\u{9FFF}').contains(&c) || ('\u{3400}'..='\u{4DBF}').contains(&c) || c == '々',
+        "hiragana" => ('\u{3041}'..='\u{309F}').contains(&c),
+        // full width (with ー) and half width
+        "katakana" => ('\u{30A0}'..='\u{30FF}').contains(&c) || ('\u{FF66}'..='\u{FF9F}').contains(&c),
+        "kana" => in_class("hiragana", c) || in_class("katakana", c),
+        "latin" => c.is_ascii_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+/// why `abbr` isn't made of the `allowed` character classes, if it isn't:
+/// the first character that falls outside them
+pub fn check_abbreviation(abbr: &str, allowed: &[String]) -> Result<(), String> {
+    if abbr.is_empty() {
+        return Err("the abbreviation is empty".to_string());
+    }
+    match abbr.chars().find(|&c| !allowed.iter().any(|class| in_class(class, c))) {
+        Some(c) => Err(format!(
+            "{:?} (U+{:04X}) is not one of the allowed character classes: {}",
+            c,
+            c as u32,
+            allowed.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+struct NumberOrNumericString;
+
+impl<'de> de::Visitor<'de> for NumberOrNumericString {
+    type Value = u32;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a year as a number or a numeric string")
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<u32, E> {
+        u32::try_from(n).map_err(|_| E::custom(format!("join_year {} is out of range", n)))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<u32, E> {
+        u32::try_from(n).map_err(|_| E::custom(format!("join_year {} is out of range", n)))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<u32, E> {
+        s.trim()
+            .parse()
+            .map_err(|_| E::custom(format!("join_year must be a number or a numeric string, got {:?}", s)))
+    }
+}
+
+fn number_or_numeric_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    deserializer.deserialize_any(NumberOrNumericString)
+}
+
+/// reject a `join_year` later than `current_year`
+pub fn join_year_not_after(join_year: u32, current_year: u32) -> Result<(), ValidationError> {
+    if join_year <= current_year {
+        return Ok(());
+    }
+
+    let mut err = ValidationError::new("join_year_in_future");
+    err.message = Some(Cow::from(format!(
+        "join_year must not be later than {}",
+        current_year
+    )));
+    err.add_param(Cow::from("current_year"), &current_year);
+    Err(err)
+}
+
+pub fn seed_teams() -> Vec<Team> {
+    let t1 = Team { team_abbreviation: "鹿島".to_string(),
+                    active_area: "茨城県".to_string(),
+                    join_year: 1991,
+                    division: "J1".to_string(),
+                    labels: Vec::new(),
+                    source: Source::Seed,
+                    deleted: false,
+                    deleted_at: None };
+    let t2 = Team { team_abbreviation: "浦和".to_string(),
+                    active_area: "埼玉県".to_string(),
+                    join_year: 1991,
+                    division: "J1".to_string(),
+                    labels: Vec::new(),
+                    source: Source::Seed,
+                    deleted: false,
+                    deleted_at: None };
+    let t3 = Team { team_abbreviation: "水戸".to_string(),
+                    active_area: "茨城県".to_string(),
+                    join_year: 2000,
+                    division: "J2".to_string(),
+                    labels: Vec::new(),
+                    source: Source::Seed,
+                    deleted: false,
+                    deleted_at: None };
+
+    vec![t1, t2, t3]
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// the initial team list: read from the json array in the file named by
+/// `TEAMS_FILE`, or the built-in seed when the variable is unset or the file
+/// doesn't exist. A file that exists but doesn't parse, or holds an invalid or
+/// duplicated team, is an error rather than a silent fallback.
+pub fn initial_teams(current_year: u32) -> io::Result<Vec<Team>> {
+    let path = match env::var("TEAMS_FILE") {
+        Ok(path) => path,
+        Err(_) => return Ok(seed_teams()),
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            log::warn!("TEAMS_FILE {} not found, using the built-in teams", path);
+            return Ok(seed_teams());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut teams: Vec<Team> = serde_json::from_str(&contents)
+        .map_err(|e| invalid_data(format!("TEAMS_FILE {}: {}", path, e)))?;
+    for team in &mut teams {
+        team.source = Source::Seed;
+    }
+    let mut seen = HashSet::new();
+    for (i, team) in teams.iter().enumerate() {
+        team.validate_at(current_year).map_err(|e| {
+            let fields = field_errors(&e, Lang::En);
+            invalid_data(format!("TEAMS_FILE {}: team #{} ({}): {:?}", path, i, team.team_abbreviation, fields))
+        })?;
+        if !seen.insert(team.team_abbreviation.as_str()) {
+            return Err(invalid_data(format!(
+                "TEAMS_FILE {}: duplicate team {}",
+                path, team.team_abbreviation
+            )));
+        }
+    }
+
+    Ok(teams)
+}
+
+/// the team list shared by all workers; construct it once outside the
+/// `HttpServer::new` closure and hand out clones of the `web::Data`. Built
+/// inside the closure instead, every worker would get its own copy and the
+/// lists would drift apart as teams are added through different workers.
+pub struct TeamStore {
+    teams: Mutex<Vec<Team>>,
+    /// the teams deleted with `soft_delete` on, out of every read but
+    /// `?include_deleted=true` until restored; only ever locked after `teams`
+    deleted: Mutex<Vec<Team>>,
+    max_teams: usize,
+    soft_delete: bool,
+}
+
+impl TeamStore {
+    /// a store refusing inserts beyond `max_teams`, and with `soft_delete`
+    /// keeping deleted teams for a restore instead of dropping them
+    pub fn new(teams: Vec<Team>, max_teams: usize, soft_delete: bool) -> Self {
+        TeamStore { teams: Mutex::new(teams), deleted: Mutex::new(Vec::new()), max_teams, soft_delete }
+    }
+
+    fn lock_deleted(&self) -> Result<MutexGuard<'_, Vec<Team>>, ApiError> {
+        store::lock(&self.deleted, "deleted team")
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Vec<Team>>, ApiError> {
+        store::lock(&self.teams, "team")
+    }
+
+    /// the store locked for a reset, so that it can be reset together with
+    /// other stores once every one of them is locked
+    pub fn lock_for_reset(&self) -> Result<TeamReset<'_>, ApiError> {
+        Ok(TeamReset { teams: self.lock()?, deleted: self.lock_deleted()? })
+    }
+
+    /// a separate store starting out with the same teams, deleted ones and
+    /// limits
+    fn scratch_copy(&self) -> Result<Self, ApiError> {
+        let teams = self.lock()?;
+        let scratch = TeamStore::new(teams.clone(), self.max_teams, self.soft_delete);
+        *scratch.lock_deleted()? = self.lock_deleted()?.clone();
+        Ok(scratch)
+    }
+
+    /// the teams deleted and kept for a restore
+    pub fn deleted(&self) -> Result<Vec<Team>, ApiError> {
+        Ok(self.lock_deleted()?.clone())
+    }
+
+    pub fn all(&self) -> Result<Vec<Team>, ApiError> {
+        Ok(self.lock()?.clone())
+    }
+
+    fn contains(&self, abbr: &str) -> Result<bool, ApiError> {
+        Ok(self.lock()?.iter().any(|t| t.team_abbreviation == abbr))
+    }
+
+    /// the team with abbreviation `abbr`, or `404`
+    pub fn get(&self, abbr: &str) -> Result<Team, ApiError> {
+        self.lock()?
+            .iter()
+            .find(|t| t.team_abbreviation == abbr)
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(format!("team {} does not exist", abbr)))
+    }
+
+    /// add `labels` to the team, returning it as it was and as updated
+    fn label(&self, abbr: &str, labels: Vec<String>) -> Result<(Team, Team), ApiError> {
+        let mut teams = self.lock()?;
+        let team = teams
+            .iter_mut()
+            .find(|t| t.team_abbreviation == abbr)
+            .ok_or_else(|| ApiError::NotFound(format!("team {} does not exist", abbr)))?;
+        let before = team.clone();
+        add_labels(&mut team.labels, labels);
+        Ok((before, team.clone()))
+    }
+
+    /// replace the team with what `change` makes of it under one lock, when it
+    /// satisfies `if_match`, returning it as it was and as changed
+    fn update(
+        &self,
+        abbr: &str,
+        if_match: Option<&[String]>,
+        change: impl FnOnce(&Team) -> Result<Team, ApiError>,
+    ) -> Result<(Team, Team), ApiError> {
+        let mut teams = self.lock()?;
+        let team = teams
+            .iter_mut()
+            .find(|t| t.team_abbreviation == abbr)
+            .ok_or_else(|| ApiError::NotFound(format!("team {} does not exist", abbr)))?;
+        check_if_match(if_match, abbr, Some(team))?;
+        let changed = change(team)?;
+        Ok((std::mem::replace(team, changed.clone()), changed))
+    }
+
+    /// move the team to `division` under one lock, returning it as it was and
+    /// as moved
+    fn move_to(&self, abbr: &str, division: String) -> Result<(Team, Team), ApiError> {
+        let mut teams = self.lock()?;
+        let team = teams
+            .iter_mut()
+            .find(|t| t.team_abbreviation == abbr)
+            .ok_or_else(|| ApiError::NotFound(format!("team {} does not exist", abbr)))?;
+        let before = team.clone();
+        team.division = division;
+        Ok((before, team.clone()))
+    }
+
+    /// move every team in `from` to `to` under one lock, returning each
+    /// moved team as it was and as moved. `to` may not already have teams of
+    /// its own, so two divisions are never merged by a rename.
+    fn rename_division(&self, from: &str, to: &str) -> Result<Vec<(Team, Team)>, ApiError> {
+        let mut teams = self.lock()?;
+        if from != to && teams.iter().any(|t| t.division == to) {
+            return Err(ApiError::Conflict(format!("division {} already has teams", to)));
+        }
+        Ok(teams
+            .iter_mut()
+            .filter(|t| t.division == from && from != to)
+            .map(|team| {
+                let before = team.clone();
+                team.division = to.to_string();
+                (before, team.clone())
+            })
+            .collect())
+    }
+
+    fn in_division(&self, division: &str) -> Result<Vec<Team>, ApiError> {
+        Ok(self.lock()?.iter().filter(|t| t.division == division).cloned().collect())
+    }
+
+    fn insert(&self, team: Team) -> Result<(), ApiError> {
+        let mut teams = self.lock()?;
+        if teams.iter().any(|t| t.team_abbreviation == team.team_abbreviation) {
+            return Err(ApiError::Conflict(format!(
+                "team {} already exists",
+                team.team_abbreviation
+            )));
+        }
+        if teams.len() >= self.max_teams {
+            return Err(ApiError::StoreFull(format!(
+                "the store is limited to {} teams",
+                self.max_teams
+            )));
+        }
+        teams.push(team);
+        Ok(())
+    }
+
+    /// replace the team with `team`'s abbreviation, or add `team` when there's
+    /// none, under one lock; the team it replaced, `None` when it was added.
+    /// With `if_match` the team must be there and still match. A replaced
+    /// team keeps its `source`.
+    fn upsert(&self, team: Team, if_match: Option<&[String]>) -> Result<Option<Team>, ApiError> {
+        let mut teams = self.lock()?;
+        let existing = teams.iter_mut().find(|t| t.team_abbreviation == team.team_abbreviation);
+        check_if_match(if_match, &team.team_abbreviation, existing.as_deref())?;
+        if let Some(existing) = existing {
+            let team = Team { source: existing.source, ..team };
+            return Ok(Some(std::mem::replace(existing, team)));
+        }
+        if teams.len() >= self.max_teams {
+            return Err(ApiError::StoreFull(format!(
+                "the store is limited to {} teams",
+                self.max_teams
+            )));
+        }
+        teams.push(team);
+        Ok(None)
+    }
+
+    /// remove every team `matches` accepts under one lock, returning the teams
+    /// that went. With `soft_delete` they're kept as deleted, each replacing
+    /// an earlier deleted team of the same abbreviation.
+    fn remove_where(&self, matches: impl Fn(&Team) -> bool) -> Result<Vec<Team>, ApiError> {
+        let mut teams = self.lock()?;
+        let (removed, kept): (Vec<Team>, _) =
+            std::mem::take(&mut *teams).into_iter().partition(|t| matches(t));
+        *teams = kept;
+        if self.soft_delete {
+            let mut deleted = self.lock_deleted()?;
+            let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            for team in &removed {
+                deleted.retain(|t| t.team_abbreviation != team.team_abbreviation);
+                deleted.push(Team { deleted: true, deleted_at: Some(now.clone()), ..team.clone() });
+            }
+        }
+        Ok(removed)
+    }
+
+    /// remove the team with abbreviation `abbr`, or `404`
+    fn remove(&self, abbr: &str) -> Result<Team, ApiError> {
+        self.remove_where(|t| t.team_abbreviation == abbr)?
+            .pop()
+            .ok_or_else(|| ApiError::NotFound(format!("team {} does not exist", abbr)))
+    }
+
+    /// put the deleted team `abbr` back under one lock, unless its
+    /// abbreviation has been taken again since or the store is full
+    fn restore(&self, abbr: &str) -> Result<Team, ApiError> {
+        let mut teams = self.lock()?;
+        let mut deleted = self.lock_deleted()?;
+        let i = deleted
+            .iter()
+            .position(|t| t.team_abbreviation == abbr)
+            .ok_or_else(|| ApiError::NotFound(format!("team {} has not been deleted", abbr)))?;
+        if teams.iter().any(|t| t.team_abbreviation == abbr) {
+            return Err(ApiError::Conflict(format!("team {} already exists", abbr)));
+        }
+        if teams.len() >= self.max_teams {
+            return Err(ApiError::StoreFull(format!(
+                "the store is limited to {} teams",
+                self.max_teams
+            )));
+        }
+        let team = Team { deleted: false, deleted_at: None, ..deleted.remove(i) };
+        teams.push(team.clone());
+        Ok(team)
+    }
+
+    /// add `teams` under one lock. Abbreviations already taken (in the store or
+    /// earlier in the import) are skipped; teams sharing an area and
+    /// `join_year` are allowed but reported. With `strict`, any duplicate
+    /// rejects the whole import and nothing is added.
+    fn import(&self, teams: Vec<Team>, strict: bool) -> Result<ImportReport, ApiError> {
+        let mut stored = self.lock()?;
+        let mut abbrs: HashSet<String> = stored.iter().map(|t| t.team_abbreviation.clone()).collect();
+        let mut area_years: HashSet<(String, u32)> =
+            stored.iter().map(|t| (t.active_area.clone(), t.join_year)).collect();
+
+        let mut report = ImportReport::default();
+        let mut accepted = Vec::new();
+        for team in teams {
+            if !abbrs.insert(team.team_abbreviation.clone()) {
+                report.duplicate_abbreviations.push(team.team_abbreviation);
+                continue;
+            }
+            if !area_years.insert((team.active_area.clone(), team.join_year)) {
+                report.duplicate_area_years.push(AreaYear {
+                    active_area: team.active_area.clone(),
+                    join_year: team.join_year,
+                });
+            }
+            accepted.push(team);
+        }
+
+        let has_duplicates =
+            !report.duplicate_abbreviations.is_empty() || !report.duplicate_area_years.is_empty();
+        if strict && has_duplicates {
+            report.rejected = true;
+            return Ok(report);
+        }
+        if stored.len() + accepted.len() > self.max_teams {
+            return Err(ApiError::StoreFull(format!(
+                "the store is limited to {} teams",
+                self.max_teams
+            )));
+        }
+        report.imported = accepted.len();
+        stored.extend(accepted.iter().cloned());
+        report.added = accepted;
+        Ok(report)
+    }
+
+    /// replace `first` and `second` with a single team named `target`, all
+    /// under one lock so no reader sees the store half-merged; the two teams
+    /// as they were, and the merged one
+    fn merge(&self, first: &str, second: &str, target: &str) -> Result<([Team; 2], Team), ApiError> {
+        let mut teams = self.lock()?;
+        let find = |abbr: &str| {
+            teams
+                .iter()
+                .position(|t| t.team_abbreviation == abbr)
+                .ok_or_else(|| ApiError::NotFound(format!("team {} does not exist", abbr)))
+        };
+        let (i, j) = (find(first)?, find(second)?);
+        if target != first && target != second && teams.iter().any(|t| t.team_abbreviation == target) {
+            return Err(ApiError::Conflict(format!("team {} already exists", target)));
+        }
+
+        let merged = merged_team(&teams[i], &teams[j], target);
+        let first = std::mem::replace(&mut teams[i], merged.clone());
+        let second = teams.remove(j);
+        Ok(([first, second], merged))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AreaYear {
+    active_area: String,
+    join_year: u32,
+}
+
+/// what `POST /api/v0/teams/import` did
+#[derive(Debug, Default, Serialize)]
+struct ImportReport {
+    imported: usize,
+    /// `true` when `?strict=true` turned the duplicates down along with
+    /// everything else
+    rejected: bool,
+    duplicate_abbreviations: Vec<String>,
+    duplicate_area_years: Vec<AreaYear>,
+    /// the teams that went in, for the audit log
+    #[serde(skip)]
+    added: Vec<Team>,
+}
+
+/// `a` and `b` as one team: the earlier `join_year` and the division that came
+/// with it, and both areas and sets of labels
+fn merged_team(a: &Team, b: &Team, target: &str) -> Team {
+    let mut labels = a.labels.clone();
+    add_labels(&mut labels, b.labels.clone());
+    let earlier = if b.join_year < a.join_year { b } else { a };
+    let active_area = if a.active_area == b.active_area {
+        a.active_area.clone()
+    } else {
+        format!("{}・{}", a.active_area, b.active_area)
+    };
+    Team {
+        team_abbreviation: target.to_string(),
+        active_area,
+        join_year: earlier.join_year,
+        division: earlier.division.clone(),
+        labels,
+        source: Source::Runtime,
+        deleted: false,
+        deleted_at: None,
+    }
+}
+
+/// the `<teams>` root wrapping each team in a `<team>` element
+#[derive(Debug, Serialize)]
+#[serde(rename = "teams")]
+struct TeamsXml<'a> {
+    team: &'a [Team],
+}
+
+/// a team as a JSON:API resource object, keyed by its abbreviation
+#[derive(Debug, Serialize)]
+struct TeamResource<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: &'a str,
+    attributes: TeamAttributes<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct TeamAttributes<'a> {
+    active_area: &'a str,
+    join_year: u32,
+    division: &'a str,
+    labels: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct JsonApiDocument<'a> {
+    data: Vec<TeamResource<'a>>,
+}
+
+impl<'a> From<&'a Team> for TeamResource<'a> {
+    fn from(team: &'a Team) -> Self {
+        TeamResource {
+            kind: "team",
+            id: &team.team_abbreviation,
+            attributes: TeamAttributes {
+                active_area: &team.active_area,
+                join_year: team.join_year,
+                division: &team.division,
+                labels: &team.labels,
+            },
+        }
+    }
+}
+
+/// whether the `Accept` header lists any of `mimes`
+fn accepts(req: &HttpRequest, mimes: &[&str]) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| {
+            accept.split(',').any(|m| {
+                let mime = m.split(';').next().unwrap_or("").trim();
+                mimes.contains(&mime)
+            })
+        })
+}
+
+/// query parameters understood by every team list endpoint
+#[derive(Debug, Deserialize)]
+struct ListOptions {
+    /// answer an empty list with `204 No Content` instead of `200 []`
+    #[serde(default)]
+    empty204: bool,
+    /// comma-separated `Team` fields to keep in the plain json output
+    fields: Option<String>,
+    /// send the plain json array as it is serialized instead of all at once
+    #[serde(default)]
+    stream: bool,
+    /// comma-separated keys to sort by, in order, each `-`-prefixed for
+    /// descending
+    sort: Option<String>,
+    /// with either of these the list is paginated: `per_page` teams (50 by
+    /// default) of page `page` (1 by default)
+    page: Option<usize>,
+    per_page: Option<usize>,
+    /// or with either of these it is paginated by cursor instead: the `limit`
+    /// teams (50 by default) following the one the `next_cursor` in `after`
+    /// stands for
+    after: Option<String>,
+    limit: Option<usize>,
+    /// how the plain json output writes an empty list
+    #[serde(default)]
+    empty: EmptyAs,
+    /// add each team's `source` to the plain json output
+    #[serde(default)]
+    include_source: bool,
+    /// add each team's `display_name` to the plain json output
+    #[serde(default)]
+    display: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EmptyAs {
+    /// `[]`
+    #[default]
+    Array,
+    /// `null`, for clients that want no list rather than an empty one
+    Null,
+}
+
+/// where one page sits in a paginated list
+#[derive(Debug, Clone, Copy)]
+struct Page {
+    number: usize,
+    per_page: usize,
+    last: usize,
+}
+
+impl Page {
+    /// `?page=`/`?per_page=` over `total` teams, or `None` when the list isn't
+    /// paginated
+    fn of(options: &ListOptions, total: usize) -> Result<Option<Self>, ApiError> {
+        if options.page.is_none() && options.per_page.is_none() {
+            return Ok(None);
+        }
+        let number = options.page.unwrap_or_else(first_page);
+        let per_page = options.per_page.unwrap_or_else(default_per_page);
+        if number == 0 || per_page == 0 || per_page > PER_PAGE_MAX {
+            return Err(ApiError::BadRequest(format!(
+                "page must be at least 1 and per_page between 1 and {}",
+                PER_PAGE_MAX
+            )));
+        }
+        Ok(Some(Page { number, per_page, last: total.div_ceil(per_page).max(1) }))
+    }
+
+    fn slice<'a>(&self, teams: &'a [Team]) -> &'a [Team] {
+        let start = ((self.number - 1) * self.per_page).min(teams.len());
+        &teams[start..(start + self.per_page).min(teams.len())]
+    }
+}
+
+/// the RFC 5988 `Link` header for `page`: `first` and `last` always, `prev`
+/// and `next` when there is such a page. The urls are absolute, built from the
+/// host and scheme the client used (`Forwarded`/`X-Forwarded-*` when behind a
+/// proxy), and keep the rest of the query string, each pair re-encoded so
+/// nothing a client sent unescaped can break out of the `<...>`.
+fn link_header(req: &HttpRequest, page: Page) -> String {
+    let info = req.connection_info();
+    let rest: Vec<String> = req
+        .query_string()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            name != "page" && name != "per_page"
+        })
+        .map(|pair| {
+            let (name, value) = pair.split_at(pair.find('=').unwrap_or(pair.len()));
+            match value.strip_prefix('=') {
+                Some(value) => format!("{}={}", reencoded(name), reencoded(value)),
+                None => reencoded(name),
+            }
+        })
+        .collect();
+    let link = |number: usize, rel: &str| {
+        let mut url = format!(
+            "{}://{}{}?page={}&per_page={}",
+            info.scheme(),
+            info.host(),
+            req.path(),
+            number,
+            page.per_page
+        );
+        for pair in &rest {
+            url.push('&');
+            url.push_str(pair);
+        }
+        format!("<{}>; rel=\"{}\"", url, rel)
+    };
+
+    let mut links = vec![link(1, "first")];
+    if page.number > 1 {
+        links.push(link((page.number - 1).min(page.last), "prev"));
+    }
+    if page.number < page.last {
+        links.push(link(page.number + 1, "next"));
+    }
+    links.push(link(page.last, "last"));
+    links.join(", ")
+}
+
+/// a team's value for one of `SORT_KEYS`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+enum KeyValue {
+    Number(u32),
+    Text(String),
+}
+
+/// what a `next_cursor` stands for: the last team sent's value for each of
+/// the list's sort keys, then its abbreviation and when it was deleted (empty
+/// for a live team), and the `?sort=` they were taken under
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    key: Vec<KeyValue>,
+}
+
+impl Cursor {
+    fn of(team: &Team, sort: &str, keys: &[SortKey]) -> Self {
+        Cursor { sort: sort.to_string(), key: keys.iter().map(|key| key.value(team)).collect() }
+    }
+
+    /// the cursor as json in url-safe base64
+    fn encode(&self) -> Result<String, Error> {
+        Ok(base64::encode_config(&serde_json::to_vec(self)?, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// `after` decoded, or a `400` unless it is a `next_cursor` for `keys`
+    /// under `sort`
+    fn decode(after: &str, sort: &str, keys: &[SortKey]) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest("after is not a next_cursor this list sent".to_string());
+        let cursor = base64::decode_config(after.trim(), base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+            .ok_or_else(invalid)?;
+        if cursor.sort != sort {
+            let order = match cursor.sort.as_str() {
+                "" => "no ?sort=".to_string(),
+                sort => format!("?sort={}", sort),
+            };
+            return Err(ApiError::BadRequest(format!(
+                "after is a cursor for {}; keep the sort the listing started with",
+                order
+            )));
+        }
+        let fits = cursor.key.len() == keys.len()
+            && keys.iter().zip(&cursor.key).all(|(key, value)| {
+                matches!((key.field, value), (2, KeyValue::Number(_)) | (0 | 1 | 3 | 4, KeyValue::Text(_)))
+            });
+        if !fits {
+            return Err(invalid());
+        }
+        Ok(cursor)
+    }
+
+    /// whether `team` comes after the cursor in the order of `keys`
+    fn is_after(&self, team: &Team, keys: &[SortKey]) -> bool {
+        let ord = keys.iter().zip(&self.key).fold(Ordering::Equal, |ord, (key, value)| {
+            ord.then_with(|| {
+                let ord = key.value(team).cmp(value);
+                if key.descending {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            })
+        });
+        ord == Ordering::Greater
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CursorPage {
+    teams: Vec<serde_json::Value>,
+    next_cursor: Option<String>,
+}
+
+/// `?after=`/`?limit=`: the `limit` teams following the cursor `after`, or the
+/// first `limit` without one, as `{"teams":[...],"next_cursor":...}` in json
+/// whatever the `Accept`. The list is ordered by `?sort=` and then by
+/// abbreviation, then live teams before a deleted one `?include_deleted=true`
+/// lists under the same abbreviation, so a cursor picks up where its page left
+/// off even when teams were added or removed in between. `next_cursor` is `null` on the last page.
+fn cursor_page(
+    req: &HttpRequest,
+    teams: &[Team],
+    mut keys: Vec<SortKey>,
+    options: &ListOptions,
+    fields: Option<&[&str]>,
+) -> Result<HttpResponse, Error> {
+    if options.page.is_some() || options.per_page.is_some() {
+        let message = "after and limit can't be combined with page and per_page".to_string();
+        return Err(ApiError::BadRequest(message).into());
+    }
+    let limit = options.limit.unwrap_or_else(default_per_page);
+    if limit == 0 || limit > PER_PAGE_MAX {
+        return Err(ApiError::BadRequest(format!("limit must be between 1 and {}", PER_PAGE_MAX)).into());
+    }
+    keys.push(SortKey { field: 0, descending: false });
+    keys.push(SortKey { field: DELETED_AT, descending: false });
+    let sort = options.sort.as_deref().unwrap_or_default();
+    let teams = sorted(teams, &keys);
+    let start = match options.after.as_deref() {
+        Some(after) => {
+            let cursor = Cursor::decode(after, sort, &keys)?;
+            teams.iter().position(|team| cursor.is_after(team, &keys)).unwrap_or(teams.len())
+        }
+        None => 0,
+    };
+    let end = (start + limit).min(teams.len());
+    let page = &teams[start..end];
+    let next_cursor = match page.last() {
+        Some(last) if end < teams.len() => Some(Cursor::of(last, sort, &keys).encode()?),
+        _ => None,
+    };
+    let body = serde_json::to_vec(&CursorPage { teams: json_values(page, fields, options)?, next_cursor })?;
+    check_response_size(req, body.len())?;
+    Ok(HttpResponse::Ok().content_type("application/json").body(body))
+}
+
+/// the fields a team list can be sorted by
+const SORT_KEYS: &[&str] = &["team_abbreviation", "active_area", "join_year", "division"];
+
+/// the `SortKey` field past `SORT_KEYS` that orders by `deleted_at`, for a
+/// cursor's last tie-break; `?sort=` can't name it
+const DELETED_AT: usize = SORT_KEYS.len();
+
+/// one key of `?sort=`, by its index into `SORT_KEYS`
+#[derive(Debug, Clone, Copy)]
+struct SortKey {
+    field: usize,
+    descending: bool,
+}
+
+impl SortKey {
+    fn compare(self, a: &Team, b: &Team) -> Ordering {
+        let ord = match self.field {
+            0 => a.team_abbreviation.cmp(&b.team_abbreviation),
+            1 => a.active_area.cmp(&b.active_area),
+            2 => a.join_year.cmp(&b.join_year),
+            3 => a.division.cmp(&b.division),
+            _ => a.deleted_at.cmp(&b.deleted_at),
+        };
+        if self.descending {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    fn value(self, team: &Team) -> KeyValue {
+        match self.field {
+            0 => KeyValue::Text(team.team_abbreviation.clone()),
+            1 => KeyValue::Text(team.active_area.clone()),
+            2 => KeyValue::Number(team.join_year),
+            3 => KeyValue::Text(team.division.clone()),
+            _ => KeyValue::Text(team.deleted_at.clone().unwrap_or_default()),
+        }
+    }
+}
+
+/// parse `?sort=`, rejecting keys a team list can't be sorted by
+fn parse_sort(sort: &str) -> Result<Vec<SortKey>, ApiError> {
+    sort.split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let (name, descending) = match key.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (key, false),
+            };
+            match SORT_KEYS.iter().position(|k| *k == name) {
+                Some(field) => Ok(SortKey { field, descending }),
+                None => Err(ApiError::BadRequest(format!(
+                    "unknown sort key {}; valid keys are {}, each optionally prefixed with -",
+                    name,
+                    SORT_KEYS.join(", ")
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// `teams` ordered by each of `keys` in turn; ties keep their order
+fn sorted(teams: &[Team], keys: &[SortKey]) -> Vec<Team> {
+    let mut teams = teams.to_vec();
+    teams.sort_by(|a, b| {
+        keys.iter().fold(Ordering::Equal, |ord, key| ord.then_with(|| key.compare(a, b)))
+    });
+    teams
+}
+
+/// the names of `Team`'s fields, in declaration order
+const TEAM_FIELDS: &[&str] =
+    &["team_abbreviation", "active_area", "join_year", "division", "labels"];
+
+/// parse `?fields=`, rejecting names `Team` doesn't have
+fn parse_fields(fields: &str) -> Result<Vec<&str>, ApiError> {
+    let fields: Vec<&str> = fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect();
+    let unknown: Vec<&str> = fields.iter().copied().filter(|f| !TEAM_FIELDS.contains(f)).collect();
+    if !unknown.is_empty() {
+        return Err(ApiError::BadRequest(format!(
+            "unknown fields {}; valid fields are {}",
+            unknown.join(", "),
+            TEAM_FIELDS.join(", ")
+        )));
+    }
+    Ok(fields)
+}
+
+/// how a team is shown to people, e.g. `鹿島 (茨城県, since 1991)`
+fn display_name(team: &Team) -> String {
+    format!("{} ({}, since {})", team.team_abbreviation, team.active_area, team.join_year)
+}
+
+/// `teams` with only `fields` kept on each, when given, and with their
+/// `source` and `display_name` added as `options` asks
+fn json_values(
+    teams: &[Team],
+    fields: Option<&[&str]>,
+    options: &ListOptions,
+) -> Result<Vec<serde_json::Value>, Error> {
+    teams
+        .iter()
+        .map(|team| {
+            let mut value = serde_json::to_value(team)?;
+            if let Some(object) = value.as_object_mut() {
+                if let Some(fields) = fields {
+                    // a deleted team stays marked as one whatever the fields
+                    object.retain(|key, _| {
+                        fields.contains(&key.as_str()) || key == "deleted" || key == "deleted_at"
+                    });
+                }
+                if options.include_source {
+                    object.insert("source".to_string(), serde_json::to_value(team.source)?);
+                }
+                if options.display {
+                    object.insert("display_name".to_string(), display_name(team).into());
+                }
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+/// a team list as a plain json array by default, a JSON:API document for
+/// `application/vnd.api+json`, utf-8 xml for `application/xml`, or a protobuf
+/// `TeamList` for `application/x-protobuf`. `?fields=`
+/// trims the plain json down to the named fields, `?sort=` orders the list,
+/// e.g. `?sort=active_area,-join_year`, `?page=`/`?per_page=` paginate it with
+/// a `Link` header to the other pages, `?after=`/`?limit=` page through it by
+/// cursor instead (see `cursor_page`), and `?stream=true` streams it a team at
+/// a time. `?empty=null` writes an empty plain json list as `null` rather than
+/// `[]`, `?include_source=true` adds where each team came from and
+/// `?display=true` a `display_name` to show it by. A list
+/// serializing to more than `MAX_RESPONSE_BYTES` is refused with `413` rather
+/// than sent, unless it is streamed.
+fn team_list_response(req: &HttpRequest, teams: &[Team]) -> Result<HttpResponse, Error> {
+    let options = web::Query::<ListOptions>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let fields = options.fields.as_deref().map(parse_fields).transpose()?;
+    let keys = options.sort.as_deref().map(parse_sort).transpose()?;
+    if options.after.is_some() || options.limit.is_some() {
+        return cursor_page(req, teams, keys.unwrap_or_default(), &options, fields.as_deref());
+    }
+    let ordered;
+    let teams = match keys {
+        Some(keys) => {
+            ordered = sorted(teams, &keys);
+            &ordered[..]
+        }
+        None => teams,
+    };
+    if teams.is_empty() && options.empty204 {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    let page = Page::of(&options, teams.len())?;
+    let teams = page.map_or(teams, |page| page.slice(teams));
+    let mut res = HttpResponse::Ok();
+    if let Some(page) = page {
+        res.header(header::LINK, link_header(req, page));
+    }
+
+    let plain =
+        !accepts(req, &["application/vnd.api+json", "application/xml", "text/xml", protobuf::CONTENT_TYPE]);
+    let null = teams.is_empty() && options.empty == EmptyAs::Null;
+    let values = if plain && (fields.is_some() || options.include_source || options.display) {
+        Some(json_values(teams, fields.as_deref(), &options)?)
+    } else {
+        None
+    };
+    if options.stream && plain && !null {
+        res.content_type("application/json");
+        return Ok(match values {
+            Some(values) => res.streaming(export::json_array(values)),
+            None => res.streaming(export::json_array(teams.to_vec())),
+        });
+    }
+
+    let (content_type, body) = if accepts(req, &[protobuf::CONTENT_TYPE]) {
+        (protobuf::CONTENT_TYPE, protobuf::team_list(teams))
+    } else if accepts(req, &["application/vnd.api+json"]) {
+        let doc = JsonApiDocument { data: teams.iter().map(TeamResource::from).collect() };
+        ("application/vnd.api+json", serde_json::to_vec(&doc)?)
+    } else if accepts(req, &["application/xml", "text/xml"]) {
+        let xml = quick_xml::se::to_string(&TeamsXml { team: teams })
+            .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+        (
+            "application/xml; charset=utf-8",
+            format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}", xml).into_bytes(),
+        )
+    } else if null {
+        ("application/json", b"null".to_vec())
+    } else if let Some(values) = values {
+        ("application/json", serde_json::to_vec(&values)?)
+    } else {
+        ("application/json", serde_json::to_vec(teams)?)
+    };
+
+    check_response_size(req, body.len())?;
+
+    Ok(res.content_type(content_type).body(body))
+}
+
+/// a `413` for a team list that serializes to `len` bytes, over
+/// `MAX_RESPONSE_BYTES`
+fn check_response_size(req: &HttpRequest, len: usize) -> Result<(), ApiError> {
+    let max = req
+        .app_data::<web::Data<Config>>()
+        .map_or(usize::MAX, |config| config.max_response_bytes);
+    if len > max {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "the list would be {} bytes, over the {} byte limit; narrow it down, e.g. with ?fields=",
+            len, max
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TeamsQuery {
+    /// comma-separated abbreviations to look up instead of listing everything
+    abbrs: Option<String>,
+    /// only list teams carrying this label
+    label: Option<String>,
+    /// comma-separated areas; only list teams in any of them
+    active_area: Option<String>,
+    /// only list teams whose `active_area` matches this regex
+    area_regex: Option<String>,
+    /// only list the seeded teams, or only those added since
+    source: Option<Source>,
+    /// list the teams kept by `SOFT_DELETE` too, marked `"deleted":true`
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+/// the longest `?area_regex=` accepted
+const AREA_REGEX_MAX_LEN: usize = 256;
+
+/// compiled `?area_regex=` patterns, so a pattern that recurs is only
+/// compiled once
+pub type AreaRegexes = Lru<Regex>;
+
+/// `pattern` compiled, from `regexes` when it has been seen before. The regex
+/// engine runs in linear time whatever the pattern, so the limits here only
+/// keep the compiled form (and compiling it) small; a pattern over them is a
+/// `400` like one that doesn't parse.
+fn area_regex(regexes: &AreaRegexes, pattern: &str) -> Result<Regex, ApiError> {
+    if let Some(regex) = regexes.get(pattern) {
+        return Ok(regex);
+    }
+    if pattern.chars().count() > AREA_REGEX_MAX_LEN {
+        return Err(ApiError::BadRequest(format!(
+            "area_regex must be at most {} characters",
+            AREA_REGEX_MAX_LEN
+        )));
+    }
+    let regex = RegexBuilder::new(pattern)
+        .size_limit(64 * 1024)
+        .dfa_size_limit(256 * 1024)
+        .nest_limit(16)
+        .build()
+        .map_err(|e| ApiError::BadRequest(format!("area_regex is not a usable regex: {}", e)))?;
+    regexes.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+#[derive(Debug, Serialize)]
+struct TeamLookup {
+    found: Vec<Team>,
+    missing: Vec<String>,
+}
+
+/// look up `abbrs` in the order given, ignoring repeats
+fn lookup(teams: &[Team], abbrs: &str) -> TeamLookup {
+    let mut seen = HashSet::new();
+    let mut res = TeamLookup { found: Vec::new(), missing: Vec::new() };
+    for abbr in abbrs.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+        if !seen.insert(abbr) {
+            continue;
+        }
+        match teams.iter().find(|t| t.team_abbreviation == abbr) {
+            Some(team) => res.found.push(team.clone()),
+            None => res.missing.push(abbr.to_string()),
+        }
+    }
+    res
+}
+
+/// all teams, or with `?abbrs=` the requested ones plus those not found
+pub async fn all_teams(
+    req: HttpRequest,
+    query: web::Query<TeamsQuery>,
+    store: web::Data<TeamStore>,
+    regexes: web::Data<AreaRegexes>,
+) -> Result<HttpResponse, Error> {
+    let mut res = store.all()?;
+    if query.include_deleted {
+        res.extend(store.deleted()?);
+    }
+
+    if let Some(abbrs) = &query.abbrs {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&lookup(&res, abbrs))?));
+    }
+    if let Some(label) = &query.label {
+        res.retain(|t| t.labels.contains(label));
+    }
+    if let Some(areas) = &query.active_area {
+        let areas: HashSet<&str> = areas.split(',').map(str::trim).filter(|a| !a.is_empty()).collect();
+        res.retain(|t| areas.contains(t.active_area.as_str()));
+    }
+    if let Some(pattern) = &query.area_regex {
+        let regex = area_regex(&regexes, pattern)?;
+        res.retain(|t| regex.is_match(&t.active_area));
+    }
+    if let Some(source) = query.source {
+        res.retain(|t| t.source == source);
+    }
+
+    team_list_response(&req, &res)
+}
+
+/// `text` with the characters html gives meaning to escaped
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// what is escaped in a query string name or value: all but the unreserved
+/// characters and the `,` between sort keys
+const QUERY_VALUE: &AsciiSet =
+    &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~').remove(b',');
+
+/// `value` percent-encoded for a query string; the result needs no further
+/// escaping in html either
+fn query_encoded(value: &str) -> String {
+    utf8_percent_encode(value, QUERY_VALUE).to_string()
+}
+
+/// a query string name or value as a client sent it, decoded (`+` as a space,
+/// like `web::Query`) and encoded again with `query_encoded`
+fn reencoded(raw: &str) -> String {
+    query_encoded(&percent_decode_str(&raw.replace('+', " ")).decode_utf8_lossy())
+}
+
+/// the most rows `/api/v0/teams.html`, or teams the json lists, show on a
+/// page
+const PER_PAGE_MAX: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct HtmlQuery {
+    sort: Option<String>,
+    #[serde(default = "first_page")]
+    page: usize,
+    #[serde(default = "default_per_page")]
+    per_page: usize,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    50
+}
+
+/// a link to another page of the table that keeps the sort order
+fn page_link(label: &str, page: usize, query: &HtmlQuery) -> String {
+    let mut href = format!("?page={}&amp;per_page={}", page, query.per_page);
+    if let Some(sort) = &query.sort {
+        href.push_str("&amp;sort=");
+        href.push_str(&query_encoded(sort));
+    }
+    format!("<a href=\"{}\">{}</a>", href, label)
+}
+
+/// every team as a plain html table, `?page=` at a time (`?per_page=` rows,
+/// 50 by default) and ordered by `?sort=` like the json lists, for a quick
+/// look from a browser
+pub async fn teams_html(
+    query: web::Query<HtmlQuery>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    if query.page == 0 || query.per_page == 0 || query.per_page > PER_PAGE_MAX {
+        return Err(ApiError::BadRequest(format!(
+            "page must be at least 1 and per_page between 1 and {}",
+            PER_PAGE_MAX
+        ))
+        .into());
+    }
+    let mut teams = store.all()?;
+    if let Some(keys) = query.sort.as_deref().map(parse_sort).transpose()? {
+        teams = sorted(&teams, &keys);
+    }
+    let pages = teams.len().div_ceil(query.per_page).max(1);
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>teams</title></head>\n<body>\n<table>\n",
+    );
+    html.push_str("<tr><th>team_abbreviation</th><th>active_area</th><th>join_year</th><th>division</th><th>labels</th></tr>\n");
+    for team in teams.iter().skip((query.page - 1) * query.per_page).take(query.per_page) {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&team.team_abbreviation),
+            escape_html(&team.active_area),
+            team.join_year,
+            escape_html(&team.division),
+            escape_html(&team.labels.join(", "))
+        ));
+    }
+    html.push_str("</table>\n<p>");
+    if query.page > 1 {
+        html.push_str(&page_link("previous", query.page - 1, &query));
+        html.push(' ');
+    }
+    html.push_str(&format!("page {} of {}", query.page, pages));
+    if query.page < pages {
+        html.push(' ');
+        html.push_str(&page_link("next", query.page + 1, &query));
+    }
+    html.push_str("</p>\n</body>\n</html>\n");
+
+    Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body(html))
+}
+
+/// the byte order mark that tells Excel a csv file is utf-8
+const UTF8_BOM: &str = "\u{FEFF}";
+
+#[derive(Debug, Deserialize)]
+pub struct CsvQuery {
+    sort: Option<String>,
+    #[serde(default = "with_bom")]
+    bom: bool,
+}
+
+fn with_bom() -> bool {
+    true
+}
+
+/// `field` as an RFC 4180 csv field, quoted when it has to be
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// `labels` as one csv field's text: themselves a csv record, so a label
+/// holding a comma or a quote is quoted within the field and comes back whole
+/// from `labels_of_csv`
+fn csv_labels(labels: &[String]) -> String {
+    labels.iter().map(|label| csv_field(label)).collect::<Vec<_>>().join(",")
+}
+
+/// every team as a csv download, ordered by `?sort=` like the json lists.
+/// Lines end in CRLF and, unless `?bom=false`, the file starts with a utf-8
+/// BOM so Excel shows the Japanese names instead of mojibake.
+pub async fn teams_csv(
+    query: web::Query<CsvQuery>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let mut teams = store.all()?;
+    if let Some(keys) = query.sort.as_deref().map(parse_sort).transpose()? {
+        teams = sorted(&teams, &keys);
+    }
+
+    let mut csv = String::from(if query.bom { UTF8_BOM } else { "" });
+    csv.push_str(&TEAM_FIELDS.join(","));
+    csv.push_str("\r\n");
+    for team in &teams {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\r\n",
+            csv_field(&team.team_abbreviation),
+            csv_field(&team.active_area),
+            team.join_year,
+            csv_field(&team.division),
+            csv_field(&csv_labels(&team.labels))
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"teams.csv\"")
+        .body(csv))
+}
+
+/// `?dry_run=true` on a mutating team endpoint. The handler makes its change
+/// to a scratch copy of the store instead of the real one, with the same
+/// validation and the same errors, and answers with what would have changed.
+pub struct DryRun {
+    scratch: Option<TeamStore>,
+    /// the scratch copy's teams as it was made, what the preview compares
+    /// against; the real store may have moved on since
+    before: Vec<Team>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DryRunQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// what a dry run would have done, by abbreviation
+#[derive(Debug, Serialize)]
+struct Preview {
+    dry_run: bool,
+    would_add: Vec<String>,
+    would_change: Vec<String>,
+    would_delete: Vec<String>,
+}
+
+impl DryRun {
+    /// the store the handler should change
+    fn store<'a>(&'a self, real: &'a TeamStore) -> &'a TeamStore {
+        self.scratch.as_ref().unwrap_or(real)
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.scratch.is_some()
+    }
+
+    /// on a dry run, the response to send instead of the handler's own
+    fn preview(&self) -> Result<Option<HttpResponse>, Error> {
+        let scratch = match &self.scratch {
+            Some(scratch) => scratch.all()?,
+            None => return Ok(None),
+        };
+        let before = &self.before;
+        let find = |teams: &[Team], abbr: &str| -> Option<Team> {
+            teams.iter().find(|t| t.team_abbreviation == abbr).cloned()
+        };
+        let abbrs = |teams: &[Team], keep: &dyn Fn(&Team) -> bool| -> Vec<String> {
+            teams.iter().filter(|t| keep(t)).map(|t| t.team_abbreviation.clone()).collect()
+        };
+        let preview = Preview {
+            dry_run: true,
+            would_add: abbrs(&scratch, &|t| find(before, &t.team_abbreviation).is_none()),
+            would_change: abbrs(&scratch, &|t| {
+                find(before, &t.team_abbreviation).is_some_and(|old| old != *t)
+            }),
+            would_delete: abbrs(before, &|t| find(&scratch, &t.team_abbreviation).is_none()),
+        };
+
+        Ok(Some(
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(serde_json::to_string(&preview)?),
+        ))
+    }
+}
+
+impl FromRequest for DryRun {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+    type Config = ();
+
+    /// a `dry_run` that isn't `true` or `false` is a `400` rather than taken
+    /// as `false`: the client asked for a preview, and mustn't get the change
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let requested = web::Query::<DryRunQuery>::from_query(req.query_string())
+            .map(|q| q.dry_run)
+            .map_err(|_| ApiError::BadRequest("dry_run must be true or false".to_string()));
+        let scratch = requested.and_then(|requested| match req.app_data::<web::Data<TeamStore>>() {
+            Some(store) if requested => store.scratch_copy().map(Some),
+            _ => Ok(None),
+        });
+        let dry_run = scratch.and_then(|scratch| {
+            let before = scratch.as_ref().map(TeamStore::all).transpose()?.unwrap_or_default();
+            Ok(DryRun { scratch, before })
+        });
+        ready(dry_run.map_err(Error::from))
+    }
+}
+
+/// validate and add a team, returning it with `201 Created`; a team sent
+/// without a division is put in `DEFAULT_DIVISION`. With
+/// `If-None-Match: *` an abbreviation that's already taken is a `412` rather
+/// than a `409`; either way the check and the insert happen under one lock.
+pub async fn create_team(
+    req: HttpRequest,
+    team: web::Json<Team>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let team = prepared(team.into_inner(), &config)?;
+    let if_none_match = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|v| v.as_bytes().trim_ascii() == b"*");
+    dry_run.store(&store).insert(team.clone()).map_err(|e| match e {
+        ApiError::Conflict(msg) if if_none_match => ApiError::PreconditionFailed(msg),
+        e => e,
+    })?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    audit::record(&req, "create", &team.team_abbreviation, None, audit::snapshot(&team));
+
+    Ok(HttpResponse::Created()
+        .content_type("application/json")
+        .body(serde_json::to_string(&team)?))
+}
+
+/// a submitted team as it would be stored: in `DEFAULT_DIVISION` when it
+/// names none, validated, and with its labels deduplicated
+fn prepared(mut team: Team, config: &Config) -> Result<Team, ApiError> {
+    if team.division.is_empty() {
+        team.division = config.default_division.clone();
+    }
+    team.validate_at(config.current_year).map_err(ApiError::Validation)?;
+    let labels = std::mem::take(&mut team.labels);
+    add_labels(&mut team.labels, labels);
+    Ok(team)
+}
+
+/// a team's `ETag`: the sha-256 of it serialized, so it changes when any of
+/// its fields does
+fn team_etag(team: &Team) -> String {
+    let json = serde_json::to_vec(team).unwrap_or_default();
+    let hex: String = sha256(&json).iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// the entity tags listed in a header like `If-Match`, `*` included
+fn entity_tags(req: &HttpRequest, name: header::HeaderName) -> Option<Vec<String>> {
+    let value = req.headers().get(name)?.to_str().ok()?;
+    Some(value.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect())
+}
+
+/// fail with `412` unless `team`, the one stored at `abbr` (`None` when there
+/// is none), matches one of the `If-Match` tags; without the header anything
+/// goes
+fn check_if_match(if_match: Option<&[String]>, abbr: &str, team: Option<&Team>) -> Result<(), ApiError> {
+    let tags = match if_match {
+        Some(tags) => tags,
+        None => return Ok(()),
+    };
+    let team = team.ok_or_else(|| ApiError::PreconditionFailed(format!("team {} does not exist", abbr)))?;
+    let etag = team_etag(team);
+    if tags.iter().any(|tag| tag == "*" || *tag == etag) {
+        Ok(())
+    } else {
+        Err(ApiError::PreconditionFailed(format!("team {} has changed since its ETag was read", abbr)))
+    }
+}
+
+/// one team, with its `ETag`; a matching `If-None-Match` gets a `304`. A
+/// team `SOFT_DELETE` kept after its delete is a `410` saying when it went,
+/// rather than the `404` of one that never existed.
+pub async fn team(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let team = match store.get(&abbr) {
+        Err(ApiError::NotFound(missing)) => {
+            let deleted = store.deleted()?.into_iter().find(|t| t.team_abbreviation == *abbr);
+            return Err(match deleted {
+                Some(team) => ApiError::Gone(
+                    format!("team {} has been deleted", abbr),
+                    team.deleted_at.unwrap_or_default(),
+                ),
+                None => ApiError::NotFound(missing),
+            }
+            .into());
+        }
+        team => team?,
+    };
+    let etag = team_etag(&team);
+    let unchanged = entity_tags(&req, header::IF_NONE_MATCH)
+        .is_some_and(|tags| tags.iter().any(|tag| *tag == etag || tag == "*"));
+    if unchanged {
+        return Ok(HttpResponse::NotModified().header(header::ETAG, etag).finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .header(header::ETAG, etag)
+        .content_type("application/json")
+        .body(serde_json::to_string(&team)?))
+}
+
+/// create the team at `{abbr}` (`201`) or replace it whole (`200`) in one
+/// step. The body may leave `team_abbreviation` out, but if it has one it
+/// must be the path's. With `If-Match` only a team that is there and still
+/// has one of the `ETag`s given is replaced, anything else is a `412`.
+pub async fn upsert_team(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    team: web::Json<Team>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let mut team = team.into_inner();
+    if team.team_abbreviation.is_empty() {
+        team.team_abbreviation = abbr.into_inner();
+    } else if team.team_abbreviation != *abbr {
+        return Err(ApiError::BadRequest(format!(
+            "team_abbreviation {} doesn't match the path's {}",
+            team.team_abbreviation, abbr
+        ))
+        .into());
+    }
+    let team = prepared(team, &config)?;
+    let if_match = entity_tags(&req, header::IF_MATCH);
+    let replaced = dry_run.store(&store).upsert(team.clone(), if_match.as_deref())?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    let action = if replaced.is_some() { "update" } else { "create" };
+    let before = replaced.as_ref().and_then(audit::snapshot);
+    audit::record(&req, action, &team.team_abbreviation, before, audit::snapshot(&team));
+
+    let mut res = if replaced.is_none() { HttpResponse::Created() } else { HttpResponse::Ok() };
+    Ok(res
+        .header(header::ETAG, team_etag(&team))
+        .content_type("application/json")
+        .body(serde_json::to_string(&team)?))
+}
+
+/// the fields `PATCH /api/v0/teams/{abbr}` can change; the abbreviation
+/// isn't one of them
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TeamPatch {
+    active_area: Option<String>,
+    join_year: Option<u32>,
+    division: Option<String>,
+    labels: Option<Vec<String>>,
+}
+
+/// change the fields given of an existing team, validating the result as a
+/// whole. With `If-Match` the team must still have one of the `ETag`s given,
+/// or it's a `412` and nothing changes.
+pub async fn patch_team(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    patch: web::Json<TeamPatch>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let patch = patch.into_inner();
+    let if_match = entity_tags(&req, header::IF_MATCH);
+    let (before, team) = dry_run.store(&store).update(&abbr, if_match.as_deref(), |team| {
+        let mut team = team.clone();
+        if let Some(active_area) = patch.active_area {
+            team.active_area = active_area;
+        }
+        if let Some(join_year) = patch.join_year {
+            team.join_year = join_year;
+        }
+        if let Some(division) = patch.division {
+            team.division = division;
+        }
+        if let Some(labels) = patch.labels {
+            team.labels = labels;
+        }
+        prepared(team, &config)
+    })?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    if before != team {
+        audit::record(&req, "update", &team.team_abbreviation, audit::snapshot(&before), audit::snapshot(&team));
+    }
+
+    Ok(HttpResponse::Ok()
+        .header(header::ETAG, team_etag(&team))
+        .content_type("application/json")
+        .body(serde_json::to_string(&team)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    active_area: Option<String>,
+    division: Option<String>,
+    #[serde(default)]
+    all: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Deleted {
+    deleted: usize,
+}
+
+/// remove the teams matching every filter given (`?active_area=`,
+/// `?division=`), answering `{"deleted":N}`. Without a filter nothing is
+/// removed unless the request says `?all=true`.
+pub async fn delete_teams(
+    req: HttpRequest,
+    query: web::Query<DeleteQuery>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    if query.active_area.is_none() && query.division.is_none() && !query.all {
+        return Err(ApiError::BadRequest(
+            "give ?active_area= or ?division= to delete by, or ?all=true to delete every team"
+                .to_string(),
+        )
+        .into());
+    }
+    let removed = dry_run.store(&store).remove_where(|t| {
+        query.active_area.as_ref().is_none_or(|area| &t.active_area == area)
+            && query.division.as_ref().is_none_or(|division| &t.division == division)
+    })?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    for team in &removed {
+        audit::record(&req, "delete", &team.team_abbreviation, audit::snapshot(team), None);
+    }
+    let deleted = removed.len();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&Deleted { deleted })?))
+}
+
+/// delete one team, answering `204`. With `SOFT_DELETE` the team is only
+/// hidden, and `POST /api/v0/teams/{abbr}/restore` brings it back.
+pub async fn delete_team(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let removed = dry_run.store(&store).remove(&abbr)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    audit::record(&req, "delete", &removed.team_abbreviation, audit::snapshot(&removed), None);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// bring back a team deleted under `SOFT_DELETE`, returning it; `404` when
+/// there's no such deleted team, `409` when its abbreviation has been taken
+/// again in the meantime
+pub async fn restore_team(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let team = dry_run.store(&store).restore(&abbr)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    audit::record(&req, "restore", &team.team_abbreviation, None, audit::snapshot(&team));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&team)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    strict: bool,
+}
+
+/// add many teams at once, each prepared as for `POST /api/v0/teams`. Every
+/// team must be valid; duplicates are skipped and counted, or with
+/// `?strict=true` turn the whole import down with `409`.
+pub async fn import_teams(
+    req: HttpRequest,
+    teams: web::Json<Vec<Team>>,
+    query: web::Query<ImportQuery>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let teams = teams
+        .into_inner()
+        .into_iter()
+        .map(|team| prepared(team, &config))
+        .collect::<Result<Vec<_>, _>>()?;
+    let res = dry_run.store(&store).import(teams, query.strict)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    for team in &res.added {
+        audit::record(&req, "create", &team.team_abbreviation, None, audit::snapshot(team));
+    }
+
+    let mut builder = if res.rejected { HttpResponse::Conflict() } else { HttpResponse::Ok() };
+    Ok(builder.content_type("application/json").body(serde_json::to_string(&res)?))
+}
+
+/// what `POST /api/v0/teams/import/ndjson` did: how many lines went in and
+/// which didn't, by number from 1. Nothing else is kept per line, so the
+/// report stays small however many teams the import brings.
+#[derive(Debug, Default, Serialize)]
+struct NdjsonImport {
+    imported: usize,
+    failed: usize,
+    failed_lines: Vec<usize>,
+}
+
+/// parse, prepare and insert the team on one ndjson line, auditing it against
+/// `audit` unless that's a dry run's `None`; blank lines are skipped
+fn import_line(
+    bytes: &[u8],
+    line: usize,
+    store: &TeamStore,
+    config: &Config,
+    audit: Option<&HttpRequest>,
+    report: &mut NdjsonImport,
+) {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    if bytes.trim_ascii().is_empty() {
+        return;
+    }
+    let result = serde_json::from_slice::<Team>(bytes)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))
+        .and_then(|team| prepared(team, config))
+        .and_then(|team| store.insert(team.clone()).map(|_| team));
+    match result {
+        Ok(team) => {
+            report.imported += 1;
+            if let Some(req) = audit {
+                audit::record(req, "create", &team.team_abbreviation, None, audit::snapshot(&team));
+            }
+        }
+        Err(e) => {
+            log::debug!("ndjson import line {} failed: {}", line, e);
+            report.failed += 1;
+            report.failed_lines.push(line);
+        }
+    }
+}
+
+/// like `/api/v0/teams/import`, but for a body of one team per line, read,
+/// prepared as for `POST /api/v0/teams` and inserted a line at a time as it
+/// arrives and audited as it goes in, so memory use stays bounded however big
+/// the import. Lines that fail to parse, validate or insert (duplicates
+/// included) are reported by number without stopping the import. A single
+/// line over `MAX_BODY_BYTES` ends it with `413`.
+pub async fn import_ndjson(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let target = dry_run.store(&store);
+    let audit = if dry_run.is_dry_run() { None } else { Some(&req) };
+    let mut report = NdjsonImport::default();
+    let mut pending = BytesMut::new();
+    let mut line = 0;
+    while let Some(chunk) = payload.next().await {
+        pending.extend_from_slice(&chunk?);
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            let bytes = pending.split_to(end + 1);
+            line += 1;
+            import_line(&bytes[..end], line, target, &config, audit, &mut report);
+        }
+        if pending.len() > config.max_body_bytes {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "line {} is over the {} byte limit",
+                line + 1,
+                config.max_body_bytes
+            ))
+            .into());
+        }
+    }
+    if !pending.is_empty() {
+        import_line(&pending, line + 1, target, &config, audit, &mut report);
+    }
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&report)?))
+}
+
+/// the records of an RFC 4180 csv document: fields split on commas, records on
+/// CRLF or a bare LF, and a quoted field may hold commas, line breaks and
+/// doubled `""` quotes. A quote anywhere else is an error naming the row it's
+/// on, counted from 1.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    // whether `field` started with a quote, and whether it has been closed
+    let (mut quoted, mut closed) = (false, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted && !closed {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => closed = true,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => continue,
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            '"' if !quoted && field.is_empty() => {
+                quoted = true;
+                continue;
+            }
+            _ if closed => return Err(format!("row {} has text after a closing quote", records.len() + 1)),
+            '"' => return Err(format!("row {} has a quote inside an unquoted field", records.len() + 1)),
+            c => {
+                field.push(c);
+                continue;
+            }
+        }
+        quoted = false;
+        closed = false;
+    }
+    if quoted && !closed {
+        return Err(format!("row {} has a quote that is never closed", records.len() + 1));
+    }
+    if quoted || !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// the columns a csv import can't do without; the rest of `TEAM_FIELDS` may
+/// be left out
+const CSV_REQUIRED: &[&str] = &["team_abbreviation", "active_area", "join_year"];
+
+/// the `TEAM_FIELDS` a csv header names, in its order; `400` for a name
+/// that isn't one, one named twice or a required one missing
+fn csv_columns(header: &[String]) -> Result<Vec<&'static str>, ApiError> {
+    let mut columns = Vec::with_capacity(header.len());
+    for name in header.iter().map(|name| name.trim()) {
+        let column = TEAM_FIELDS.iter().copied().find(|f| *f == name).ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "unknown column {:?}; valid columns are {}",
+                name,
+                TEAM_FIELDS.join(", ")
+            ))
+        })?;
+        if columns.contains(&column) {
+            return Err(ApiError::BadRequest(format!("column {} appears twice", column)));
+        }
+        columns.push(column);
+    }
+    let missing: Vec<&str> = CSV_REQUIRED.iter().copied().filter(|f| !columns.contains(f)).collect();
+    if !missing.is_empty() {
+        return Err(ApiError::BadRequest(format!("missing columns {}", missing.join(", "))));
+    }
+    Ok(columns)
+}
+
+/// the labels `csv_labels` wrote into a field, or a hand-written
+/// comma-separated list: each trimmed, blank ones dropped
+fn labels_of_csv(field: &str) -> Result<Vec<String>, String> {
+    let invalid = || format!("labels {:?} are not a comma-separated list", field);
+    let mut records = csv_records(field).map_err(|_| invalid())?;
+    if records.len() > 1 {
+        return Err(invalid());
+    }
+    let labels = records.pop().unwrap_or_default();
+    Ok(labels.iter().map(|l| l.trim()).filter(|l| !l.is_empty()).map(str::to_string).collect())
+}
+
+/// the team on one csv row, `labels` being a comma-separated list within its
+/// field, see `labels_of_csv`
+fn csv_team(columns: &[&str], record: Vec<String>) -> Result<Team, String> {
+    if record.len() != columns.len() {
+        return Err(format!("the row has {} fields where the header has {}", record.len(), columns.len()));
+    }
+    let mut team = Team {
+        team_abbreviation: String::new(),
+        active_area: String::new(),
+        join_year: 0,
+        division: String::new(),
+        labels: Vec::new(),
+        source: Source::Runtime,
+        deleted: false,
+        deleted_at: None,
+    };
+    for (column, value) in columns.iter().zip(record) {
+        match *column {
+            "team_abbreviation" => team.team_abbreviation = value,
+            "active_area" => team.active_area = value,
+            "join_year" => {
+                team.join_year =
+                    value.trim().parse().map_err(|_| format!("join_year {:?} is not a year", value))?
+            }
+            "division" => team.division = value,
+            _ => team.labels = labels_of_csv(&value)?,
+        }
+    }
+    Ok(team)
+}
+
+/// a row of a csv import that was turned down, numbered as a spreadsheet
+/// shows it: the header is row 1
+#[derive(Debug, Serialize)]
+struct RejectedRow {
+    row: usize,
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<HashMap<&'static str, Vec<FieldError>>>,
+}
+
+/// what `POST /api/v0/import/csv` did, or on a dry run would do
+#[derive(Debug, Default, Serialize)]
+struct CsvImport {
+    dry_run: bool,
+    added: usize,
+    updated: usize,
+    rejected: usize,
+    errors: Vec<RejectedRow>,
+    /// each team that went in and the one it replaced, for the audit log
+    #[serde(skip)]
+    changes: Vec<(Option<Team>, Team)>,
+}
+
+/// add or replace teams from a csv body: a header row naming columns from
+/// `TEAM_FIELDS` in any order, `team_abbreviation`, `active_area` and
+/// `join_year` being required, then a team per row, as `/api/v0/teams.csv`
+/// writes them. Each row is prepared like a `PUT /api/v0/teams/{abbr}` body
+/// and replaces the team of its abbreviation when there is one. Rows that
+/// don't parse, validate or fit, or repeat an earlier row's abbreviation, are
+/// reported by number without stopping the import; a header that can't be
+/// used, or quoting that leaves the rows themselves unclear, is a `400`. With `?dry_run=true` the same report comes back and the
+/// store is left alone.
+pub async fn import_csv(
+    req: HttpRequest,
+    body: web::Bytes,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    let lang = Lang::from_headers(req.headers());
+    let text = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::BadRequest("request body is not valid UTF-8".to_string()))?;
+    let text = text.strip_prefix(UTF8_BOM).unwrap_or(text);
+    let mut records = csv_records(text).map_err(ApiError::BadRequest)?.into_iter();
+    let header = records.next().ok_or_else(|| ApiError::BadRequest("the csv has no header row".to_string()))?;
+    let columns = csv_columns(&header)?;
+
+    let target = dry_run.store(&store);
+    let mut report = CsvImport { dry_run: dry_run.is_dry_run(), ..CsvImport::default() };
+    let mut rows: HashMap<String, usize> = HashMap::new();
+    for (row, record) in (2..).zip(records) {
+        if record.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        let result = csv_team(&columns, record)
+            .map_err(|e| (e, None))
+            .and_then(|team| match rows.get(&team.team_abbreviation) {
+                Some(earlier) => {
+                    Err((format!("team {} is already on row {}", team.team_abbreviation, earlier), None))
+                }
+                None => Ok(team),
+            })
+            .and_then(|team| {
+                prepared(team, &config).map_err(|e| match &e {
+                    ApiError::Validation(errors) => (e.to_string(), Some(field_errors(errors, lang))),
+                    _ => (e.to_string(), None),
+                })
+            })
+            .and_then(|team| {
+                let before = target.upsert(team.clone(), None).map_err(|e| (e.to_string(), None))?;
+                Ok((before, team))
+            });
+        match result {
+            Ok((before, team)) => {
+                rows.insert(team.team_abbreviation.clone(), row);
+                if before.is_some() {
+                    report.updated += 1;
+                } else {
+                    report.added += 1;
+                }
+                report.changes.push((before, team));
+            }
+            Err((error, errors)) => {
+                report.rejected += 1;
+                report.errors.push(RejectedRow { row, error, errors });
+            }
+        }
+    }
+    if !report.dry_run {
+        for (before, team) in &report.changes {
+            let action = if before.is_some() { "update" } else { "create" };
+            let before = before.as_ref().and_then(audit::snapshot);
+            audit::record(&req, action, &team.team_abbreviation, before, audit::snapshot(team));
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&report)?))
+}
+
+/// the two teams to merge and the abbreviation the merged team goes by, which
+/// may be one of theirs
+#[derive(Debug, Deserialize)]
+pub struct MergeRequest {
+    first: String,
+    second: String,
+    target: String,
+}
+
+pub async fn merge_teams(
+    req: HttpRequest,
+    merge: web::Json<MergeRequest>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    if merge.first == merge.second {
+        return Err(ApiError::BadRequest("a team can't be merged with itself".to_string()).into());
+    }
+    if merge.target.trim().is_empty() {
+        return Err(ApiError::BadRequest("target must not be blank".to_string()).into());
+    }
+    let (before, res) = dry_run.store(&store).merge(&merge.first, &merge.second, &merge.target)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    audit::record(&req, "merge", &res.team_abbreviation, audit::snapshot(&before), audit::snapshot(&res));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+pub async fn teams_j1(
+    req: HttpRequest,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let res = store.in_division("J1")?;
+
+    team_list_response(&req, &res)
+}
+
+pub async fn teams_j2(
+    req: HttpRequest,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let res = store.in_division("J2")?;
+
+    team_list_response(&req, &res)
+}
+
+#[derive(Debug, Serialize)]
+struct DivisionDiff {
+    j1_only: Vec<String>,
+    j2_only: Vec<String>,
+    both: Vec<String>,
+}
+
+fn division_diff(j1: &[Team], j2: &[Team]) -> DivisionDiff {
+    let j1_abbrs: HashSet<&str> = j1.iter().map(|t| t.team_abbreviation.as_str()).collect();
+    let j2_abbrs: HashSet<&str> = j2.iter().map(|t| t.team_abbreviation.as_str()).collect();
+
+    let only = |teams: &[Team], other: &HashSet<&str>| -> Vec<String> {
+        teams
+            .iter()
+            .filter(|t| !other.contains(t.team_abbreviation.as_str()))
+            .map(|t| t.team_abbreviation.clone())
+            .collect()
+    };
+
+    DivisionDiff {
+        j1_only: only(j1, &j2_abbrs),
+        j2_only: only(j2, &j1_abbrs),
+        // a team sits in a single division today, so this stays empty unless
+        // the model starts allowing multi-division membership
+        both: j1
+            .iter()
+            .filter(|t| j2_abbrs.contains(t.team_abbreviation.as_str()))
+            .map(|t| t.team_abbreviation.clone())
+            .collect(),
+    }
+}
+
+/// abbreviations of the teams found only in J1, only in J2, and in both
+pub async fn teams_division_diff(
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let res = division_diff(&store.in_division("J1")?, &store.in_division("J2")?);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the decade label a join year falls into: the first year of the decade
+/// followed by "s", e.g. 1991 -> "1990s"
+fn decade_label(join_year: u32) -> String {
+    format!("{}s", join_year / 10 * 10)
+}
+
+/// number of teams per join decade, keyed by `decade_label` in ascending order
+pub async fn teams_by_decade(
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let mut res: BTreeMap<String, usize> = BTreeMap::new();
+    for team in store.all()? {
+        *res.entry(decade_label(team.join_year)).or_insert(0) += 1;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistogramQuery {
+    #[serde(default = "default_bucket")]
+    bucket: u32,
+}
+
+fn default_bucket() -> u32 {
+    5
+}
+
+/// bucket labels and counts, serialized as a json object in bucket order
+struct Histogram(Vec<(String, usize)>);
+
+impl Serialize for Histogram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(label, count)| (label, count)))
+    }
+}
+
+/// number of teams per `?bucket=`-year-wide (5 by default) age bracket, age
+/// being `CURRENT_YEAR` minus `join_year`, e.g. `{"0-4":0,"5-9":2}`. Every
+/// bracket from age 0 up to the oldest team's is listed; `{}` when there are
+/// no teams.
+pub async fn teams_age_histogram(
+    query: web::Query<HistogramQuery>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let width = query.bucket;
+    if width == 0 {
+        return Err(ApiError::BadRequest("bucket must be a positive number of years".to_string()).into());
+    }
+    let year = config.current_year;
+    let mut counts: Vec<usize> = Vec::new();
+    for team in store.all()? {
+        let bucket = (year.saturating_sub(team.join_year) / width) as usize;
+        if counts.len() <= bucket {
+            counts.resize(bucket + 1, 0);
+        }
+        counts[bucket] += 1;
+    }
+    let res = Histogram(
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let low = i as u32 * width;
+                (format!("{}-{}", low, low + width - 1), count)
+            })
+            .collect(),
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// teams grouped by `active_area`, then by `join_year`, both in ascending
+/// order; `{}` when there are no teams
+pub async fn teams_tree(
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let mut res: BTreeMap<String, BTreeMap<u32, Vec<Team>>> = BTreeMap::new();
+    for team in store.all()? {
+        res.entry(team.active_area.clone())
+            .or_default()
+            .entry(team.join_year)
+            .or_default()
+            .push(team);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// teams whose `join_year` is exactly the `{year}` path segment
+pub async fn teams_by_year(
+    req: HttpRequest,
+    year: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let year: u32 = year
+        .parse()
+        .map_err(|_| ApiError::BadRequest(format!("year must be a number, got {:?}", year.as_str())))?;
+    let res: Vec<Team> = store.all()?.into_iter().filter(|t| t.join_year == year).collect();
+
+    team_list_response(&req, &res)
+}
+
+/// the default `?before=` for the founding clubs: those that had joined by
+/// the end of the inaugural era
+const FOUNDING_BEFORE: u32 = 1994;
+
+#[derive(Debug, Deserialize)]
+pub struct FoundingQuery {
+    before: Option<u32>,
+}
+
+/// teams that joined before `?before=` (1994, the inaugural era, by
+/// default), which must be a year between 1900 and next year
+pub async fn teams_founding(
+    req: HttpRequest,
+    query: web::Query<FoundingQuery>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let before = query.before.unwrap_or(FOUNDING_BEFORE);
+    let latest = config.current_year + 1;
+    if !(1900..=latest).contains(&before) {
+        let message = format!("before must be between 1900 and {}, got {}", latest, before);
+        return Err(ApiError::BadRequest(message).into());
+    }
+    let res: Vec<Team> = store.all()?.into_iter().filter(|t| t.join_year < before).collect();
+
+    team_list_response(&req, &res)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RandomQuery {
+    /// `join_year` favours older teams: a team's weight is how many years
+    /// before the newest team's `join_year` it joined, plus one
+    weight: Option<String>,
+    /// seed the generator, for repeatable picks
+    seed: Option<u64>,
+}
+
+/// one team picked at random, uniformly unless `?weight=` says otherwise
+pub async fn random_team(
+    query: web::Query<RandomQuery>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let teams = store.all()?;
+    if teams.is_empty() {
+        return Err(ApiError::NotFound("there are no teams to pick from".to_string()).into());
+    }
+    let mut rng = match query.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let index = match query.weight.as_deref() {
+        None => rng.gen_range(0, teams.len()),
+        Some("join_year") => {
+            let newest = teams.iter().map(|t| t.join_year).max().unwrap_or(0);
+            let weights = teams.iter().map(|t| newest - t.join_year + 1);
+            WeightedIndex::new(weights)
+                .map_err(|e| ApiError::Internal(e.to_string()))?
+                .sample(&mut rng)
+        }
+        Some(other) => {
+            return Err(ApiError::BadRequest(format!(
+                "unknown weight {:?}, expected join_year",
+                other
+            ))
+            .into())
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&teams[index])?))
+}
+
+#[derive(Debug, Serialize)]
+struct Exists {
+    exists: bool,
+}
+
+/// whether a team exists, without serializing it: `200` or `404`
+pub async fn team_exists(
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let exists = store.contains(&abbr)?;
+    let mut res = if exists { HttpResponse::Ok() } else { HttpResponse::NotFound() };
+
+    Ok(res
+        .content_type("application/json")
+        .body(serde_json::to_string(&Exists { exists })?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// one `field op value` comparison of the `/teams/filter` grammar
+#[derive(Debug)]
+enum Clause {
+    JoinYear(Op, u32),
+    ActiveArea(Op, String),
+}
+
+impl Clause {
+    fn parse(clause: &str) -> Result<Self, ApiError> {
+        // two-character operators first, so `<=` isn't read as `<` followed by `=`
+        let ops = [
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("!=", Op::Ne),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+            ("=", Op::Eq),
+        ];
+        let (at, token, op) = ops
+            .iter()
+            .filter_map(|(token, op)| clause.find(token).map(|at| (at, *token, *op)))
+            .min_by_key(|(at, token, _)| (*at, std::cmp::Reverse(token.len())))
+            .ok_or_else(|| ApiError::BadRequest(format!("no operator in {:?}", clause)))?;
+        let field = clause[..at].trim();
+        let value = clause[at + token.len()..].trim();
+
+        match field {
+            "join_year" => value
+                .parse()
+                .map(|year| Clause::JoinYear(op, year))
+                .map_err(|_| ApiError::BadRequest(format!("join_year needs a number, got {:?}", value))),
+            "active_area" if op == Op::Eq || op == Op::Ne => {
+                Ok(Clause::ActiveArea(op, value.to_string()))
+            }
+            "active_area" => {
+                Err(ApiError::BadRequest("active_area only supports = and !=".to_string()))
+            }
+            _ => Err(ApiError::BadRequest(format!(
+                "unknown field {:?}, expected join_year or active_area",
+                field
+            ))),
+        }
+    }
+
+    fn matches(&self, team: &Team) -> bool {
+        match self {
+            Clause::JoinYear(op, year) => match op {
+                Op::Eq => team.join_year == *year,
+                Op::Ne => team.join_year != *year,
+                Op::Lt => team.join_year < *year,
+                Op::Le => team.join_year <= *year,
+                Op::Gt => team.join_year > *year,
+                Op::Ge => team.join_year >= *year,
+            },
+            Clause::ActiveArea(op, area) => (team.active_area == *area) == (*op == Op::Eq),
+        }
+    }
+}
+
+/// parse a `/teams/filter` expression: comparisons joined by ` and `, each
+/// either `join_year` with `= != < <= > >=` and a number, or `active_area`
+/// with `=`/`!=` and the rest of the clause as the area. There is no `or`,
+/// no grouping and no other fields.
+fn parse_filter(q: &str) -> Result<Vec<Clause>, ApiError> {
+    if q.trim().is_empty() {
+        return Err(ApiError::BadRequest("q must not be empty".to_string()));
+    }
+    q.split(" and ").map(Clause::parse).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FilterQuery {
+    q: String,
+}
+
+/// the teams matching every clause of `?q=`, e.g. `join_year>1995` or
+/// `active_area=茨城県 and join_year>=2000`
+pub async fn teams_filter(
+    req: HttpRequest,
+    query: web::Query<FilterQuery>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let clauses = parse_filter(&query.q)?;
+    let res: Vec<Team> = store
+        .all()?
+        .into_iter()
+        .filter(|t| clauses.iter().all(|c| c.matches(t)))
+        .collect();
+
+    team_list_response(&req, &res)
+}
+
+/// the labels to add with `POST /api/v0/teams/{abbr}/labels`
+#[derive(Debug, Validate, Deserialize)]
+pub struct NewLabels {
+    #[validate(custom = "validate_labels")]
+    labels: Vec<String>,
+}
+
+/// add labels to a team, ignoring ones it already has, and return the team
+pub async fn label_team(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    labels: web::Json<NewLabels>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    labels.validate().map_err(ApiError::Validation)?;
+    let (before, res) = dry_run.store(&store).label(&abbr, labels.into_inner().labels)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    audit::record(&req, "update", &res.team_abbreviation, audit::snapshot(&before), audit::snapshot(&res));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct Distance<'a> {
+    from: &'a str,
+    to: &'a str,
+    km: f64,
+}
+
+fn area_coordinates(team: &Team) -> Result<(f64, f64), ApiError> {
+    geo::coordinates(&team.active_area).ok_or_else(|| {
+        ApiError::Unprocessable(format!(
+            "no coordinates for {}'s area {}",
+            team.team_abbreviation, team.active_area
+        ))
+    })
+}
+
+/// the great-circle distance between two teams' areas, taking each area's
+/// prefectural office as its location, in km to one decimal
+pub async fn team_distance(
+    abbrs: web::Path<(String, String)>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let (a, b) = (store.get(&abbrs.0)?, store.get(&abbrs.1)?);
+    let km = geo::distance_km(area_coordinates(&a)?, area_coordinates(&b)?);
+    let res = Distance {
+        from: &a.team_abbreviation,
+        to: &b.team_abbreviation,
+        km: (km * 10.0).round() / 10.0,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NearQuery {
+    area: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NearTeam {
+    #[serde(flatten)]
+    team: Team,
+    /// from the reference area, in km to one decimal; `null` for an area with
+    /// no coordinates
+    km: Option<f64>,
+}
+
+/// `GET /api/v0/teams/near?area=埼玉県`: every team, nearest to `area` first by
+/// great-circle distance between prefectural offices, ties in abbreviation
+/// order. Teams whose area has no coordinates come last; a reference area
+/// without coordinates is a `400`.
+pub async fn teams_near(
+    query: web::Query<NearQuery>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let origin = geo::coordinates(&query.area)
+        .ok_or_else(|| ApiError::BadRequest(format!("no coordinates for the area {}", query.area)))?;
+    let mut res: Vec<NearTeam> = store
+        .all()?
+        .into_iter()
+        .map(|team| {
+            let km = geo::coordinates(&team.active_area)
+                .map(|at| (geo::distance_km(origin, at) * 10.0).round() / 10.0);
+            NearTeam { team, km }
+        })
+        .collect();
+    res.sort_by(|a, b| {
+        let distance = match (a.km, b.km) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        distance.then_with(|| a.team.team_abbreviation.cmp(&b.team.team_abbreviation))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct Tenure {
+    join_year: u32,
+    years_active: u32,
+}
+
+/// how many years a team has been active: `CURRENT_YEAR` minus its
+/// `join_year`, so a team joining this year has 0
+pub async fn team_tenure(
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let team = store.get(&abbr)?;
+    let res = Tenure {
+        join_year: team.join_year,
+        years_active: config.current_year.saturating_sub(team.join_year),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct TeamDivision {
+    team_abbreviation: String,
+    division: String,
+}
+
+/// just the division a team plays in
+pub async fn team_division(
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let team = store.get(&abbr)?;
+    let res = TeamDivision { team_abbreviation: team.team_abbreviation, division: team.division };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// every division a team plays in, as an array; a team is in exactly one
+/// for now, so it has one element
+pub async fn team_divisions(
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let team = store.get(&abbr)?;
+    let res: BTreeSet<String> = std::iter::once(team.division).collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AbbreviationCheck {
+    team_abbreviation: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AbbreviationVerdict {
+    valid: bool,
+    reason: Option<String>,
+}
+
+/// whether an abbreviation sticks to the `ABBR_CHARSET` character classes,
+/// for checking one before creating the team. Always `200`.
+pub async fn validate_abbreviation(
+    check: web::Json<AbbreviationCheck>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let reason = check_abbreviation(&check.team_abbreviation, &config.abbr_charset).err();
+    let res = AbbreviationVerdict { valid: reason.is_none(), reason };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the other teams that joined in the same year as `{abbr}`, `[]` when it
+/// joined alone; a team list like any other
+pub async fn team_cohort(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let team = store.get(&abbr)?;
+    let mut cohort = store.all()?;
+    cohort.retain(|t| t.join_year == team.join_year && t.team_abbreviation != team.team_abbreviation);
+
+    team_list_response(&req, &cohort)
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DivisionMove {
+    #[validate(custom = "validate_division")]
+    division: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DivisionRename {
+    #[validate(custom = "validate_division_name")]
+    from: String,
+    #[validate(custom = "validate_division_name")]
+    to: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DivisionRenamed {
+    from: String,
+    to: String,
+    changed: usize,
+}
+
+/// rename a division by moving every team in `from` to `to`, answering with
+/// how many teams changed. `to` may be a new name, see
+/// `validate_division_name`; renaming into a division that already has teams
+/// is a `409`; `?dry_run=true` lists the teams that would move.
+pub async fn rename_division(
+    req: HttpRequest,
+    body: web::Json<DivisionRename>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    body.validate().map_err(ApiError::Validation)?;
+    let DivisionRename { from, to } = body.into_inner();
+    let moved = dry_run.store(&store).rename_division(&from, &to)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    for (before, team) in &moved {
+        let abbr = &team.team_abbreviation;
+        audit::record(&req, "update", abbr, audit::snapshot(before), audit::snapshot(team));
+    }
+    let res = DivisionRenamed { from, to, changed: moved.len() };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// move a team to another division, answering with the team as moved. Moving
+/// it to the division it's already in changes nothing and is a `200` too.
+pub async fn move_team_division(
+    req: HttpRequest,
+    abbr: web::Path<String>,
+    body: web::Json<DivisionMove>,
+    store: web::Data<TeamStore>,
+    dry_run: DryRun,
+) -> Result<HttpResponse, Error> {
+    body.validate().map_err(ApiError::Validation)?;
+    let (before, res) = dry_run.store(&store).move_to(&abbr, body.into_inner().division)?;
+    if let Some(preview) = dry_run.preview()? {
+        return Ok(preview);
+    }
+    if before != res {
+        audit::record(&req, "update", &res.team_abbreviation, audit::snapshot(&before), audit::snapshot(&res));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// teams grouped by the first character (not byte) of `active_area`; teams
+/// with an empty area have no initial and are left out
+pub async fn teams_by_area_initial(
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let mut res: BTreeMap<char, Vec<Team>> = BTreeMap::new();
+    for team in store.all()? {
+        if let Some(initial) = team.active_area.chars().next() {
+            res.entry(initial).or_default().push(team);
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// how many of `teams` share each value of `key`, in ascending order
+fn counts_by(teams: &[Team], key: impl Fn(&Team) -> &str) -> BTreeMap<String, usize> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for team in teams {
+        *counts.entry(key(team).to_string()).or_default() += 1;
+    }
+    counts
+}
+
+#[derive(Debug, Serialize)]
+struct TopArea {
+    active_area: String,
+    count: usize,
+}
+
+/// the `active_area` with the most teams; a tie goes to the area that sorts
+/// first
+pub async fn teams_top_area(
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let counts = counts_by(&store.all()?, |t| &t.active_area);
+    // `max_by_key` keeps the last of equal maxima, so walk the areas backwards
+    let res = counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(active_area, count)| TopArea { active_area, count })
+        .ok_or_else(|| ApiError::NotFound("there are no teams to count".to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Serialize)]
+struct Dashboard {
+    total: usize,
+    by_division: BTreeMap<String, usize>,
+    by_area: BTreeMap<String, usize>,
+    earliest_join_year: Option<u32>,
+    latest_join_year: Option<u32>,
+    /// every team that joined in `earliest_join_year`, by abbreviation
+    oldest: Vec<Team>,
+    /// every team that joined in `latest_join_year`, by abbreviation
+    newest: Vec<Team>,
+}
+
+/// the teams that joined in `year`, by abbreviation
+fn joined_in(teams: &[Team], year: Option<u32>) -> Vec<Team> {
+    let mut res: Vec<Team> = teams.iter().filter(|t| Some(t.join_year) == year).cloned().collect();
+    res.sort_by(|a, b| a.team_abbreviation.cmp(&b.team_abbreviation));
+    res
+}
+
+/// `GET /api/v0/teams/dashboard`: the counts and extremes a dashboard shows,
+/// in one object, all worked out from the same snapshot of the store so they
+/// agree with each other. With no teams the years are `null` and the lists
+/// empty.
+pub async fn teams_dashboard(
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let teams = store.all()?;
+    let earliest_join_year = teams.iter().map(|t| t.join_year).min();
+    let latest_join_year = teams.iter().map(|t| t.join_year).max();
+    let res = Dashboard {
+        total: teams.len(),
+        by_division: counts_by(&teams, |t| &t.division),
+        by_area: counts_by(&teams, |t| &t.active_area),
+        earliest_join_year,
+        latest_join_year,
+        oldest: joined_in(&teams, earliest_join_year),
+        newest: joined_in(&teams, latest_join_year),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the longest `?q=` `/api/v0/teams/fuzzy` takes, in characters
+const FUZZY_QUERY_MAX: usize = 32;
+/// the largest `?max_distance=` it takes
+const FUZZY_DISTANCE_MAX: usize = 3;
+
+fn default_max_distance() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FuzzyQuery {
+    q: String,
+    #[serde(default = "default_max_distance")]
+    max_distance: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct FuzzyMatch {
+    #[serde(flatten)]
+    team: Team,
+    /// 1 for the abbreviation itself, down towards 0 the more edits away
+    score: f64,
+}
+
+/// the Levenshtein distance between `a` and `b`, counted in characters
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// `GET /api/v0/teams/fuzzy?q=鹿`: the teams whose abbreviation contains `q`
+/// or is at most `?max_distance=` edits (1 by default, at most
+/// `FUZZY_DISTANCE_MAX`) away from it, closest first. The score is one minus
+/// the edits over the longer of the two lengths; an abbreviation that takes
+/// an edit for every character scores 0 and is left out.
+pub async fn teams_fuzzy(
+    query: web::Query<FuzzyQuery>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let q: Vec<char> = query.q.trim().chars().collect();
+    if q.is_empty() || q.len() > FUZZY_QUERY_MAX {
+        let message = format!("q must be between 1 and {} characters", FUZZY_QUERY_MAX);
+        return Err(ApiError::BadRequest(message).into());
+    }
+    if query.max_distance > FUZZY_DISTANCE_MAX {
+        let message = format!("max_distance must be at most {}", FUZZY_DISTANCE_MAX);
+        return Err(ApiError::BadRequest(message).into());
+    }
+    let needle: String = q.iter().collect();
+
+    let mut res: Vec<FuzzyMatch> = store
+        .all()?
+        .into_iter()
+        .filter_map(|team| {
+            let abbr: Vec<char> = team.team_abbreviation.chars().collect();
+            let distance = edit_distance(&q, &abbr);
+            let score = 1.0 - distance as f64 / q.len().max(abbr.len()) as f64;
+            let close = distance <= query.max_distance || team.team_abbreviation.contains(&needle);
+            (close && score > 0.0).then_some(FuzzyMatch { team, score })
+        })
+        .collect();
+    res.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.team.team_abbreviation.cmp(&b.team.team_abbreviation))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, call};
+
+    fn team_with_join_year(join_year: serde_json::Value) -> Result<Team, serde_json::Error> {
+        let team = json!({"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": join_year});
+        serde_json::from_value(team)
+    }
+
+    #[test]
+    fn join_year_is_a_number_or_a_numeric_string() {
+        assert_eq!(team_with_join_year(json!(1999)).unwrap().join_year(), 1999);
+        assert_eq!(team_with_join_year(json!("1999")).unwrap().join_year(), 1999);
+        assert_eq!(team_with_join_year(json!(" 1999 ")).unwrap().join_year(), 1999);
+        let team = team_with_join_year(json!("1999")).unwrap();
+        assert_eq!(serde_json::to_value(&team).unwrap()["join_year"], json!(1999));
+
+        let out_of_range = u64::from(u32::MAX) + 1;
+        for bad in [json!("nineteen"), json!(""), json!("19.5"), json!(-1), json!(1.5), json!(out_of_range)] {
+            assert!(team_with_join_year(bad.clone()).is_err(), "{} was accepted", bad);
+        }
+        let e = team_with_join_year(json!("nineteen")).unwrap_err().to_string();
+        assert!(e.contains(r#"join_year must be a number or a numeric string, got "nineteen""#), "{}", e);
+    }
+
+    #[actix_rt::test]
+    async fn a_string_join_year_is_created_and_a_non_numeric_one_is_400() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let team = json!({"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": "1999"});
+        let req = TestRequest::post().uri("/api/v0/teams").set_json(&team).to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(test_support::json(&body)["join_year"], json!(1999));
+
+        let team = json!({"team_abbreviation": "岐阜", "active_area": "岐阜県", "join_year": "twenty"});
+        let req = TestRequest::post().uri("/api/v0/teams").set_json(&team).to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        test_support::assert_error_code(&body, "bad_request");
+        let error = test_support::json(&body)["error"].as_str().unwrap().to_string();
+        assert!(error.contains("join_year must be a number or a numeric string"), "{}", error);
+    }
+
+    /// `count` generated teams
+    fn many_teams(count: usize) -> Vec<Team> {
+        (0..count)
+            .map(|i| {
+                let team = json!({
+                    "team_abbreviation": format!("チーム{}", i),
+                    "active_area": "東京都",
+                    "join_year": 1991 + (i % 30) as u32,
+                    "labels": [format!("label {}", i)],
+                });
+                serde_json::from_value(team).unwrap()
+            })
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn streamed_team_lists_are_valid_json_past_max_response_bytes() {
+        let mut config = test_support::config();
+        config.max_response_bytes = 64 * 1024;
+        let teams = many_teams(5000);
+        let store = web::Data::new(TeamStore::new(teams.clone(), teams.len(), false));
+        let mut app = test_support::app(config, store).await;
+
+        let req = TestRequest::get().uri("/api/v0/teams").to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = TestRequest::get().uri("/api/v0/teams?stream=true").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.len() > 64 * 1024);
+        test_support::assert_json(&body, serde_json::to_value(&teams).unwrap());
+
+        let req = TestRequest::get().uri("/api/v0/teams?stream=true&fields=join_year").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let years = teams.iter().map(|team| json!({"join_year": team.join_year()})).collect();
+        test_support::assert_json(&body, serde_json::Value::Array(years));
+    }
+
+    #[actix_rt::test]
+    async fn an_empty_team_list_streams_as_an_empty_array() {
+        let config = test_support::config();
+        let store = web::Data::new(TeamStore::new(Vec::new(), 1, false));
+        let mut app = test_support::app(config, store).await;
+        let req = TestRequest::get().uri("/api/v0/teams?stream=true").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"[]"[..]));
+    }
+
+    #[actix_rt::test]
+    async fn cursor_pages_list_a_deleted_team_and_its_re_creation_once_each() {
+        let config = test_support::config();
+        let store = web::Data::new(TeamStore::new(many_teams(3), 10, true));
+        store.remove("チーム1").unwrap();
+        store.insert(many_teams(2).pop().unwrap()).unwrap();
+        let mut app = test_support::app(config, store).await;
+
+        let mut seen = Vec::new();
+        let mut uri = "/api/v0/teams?include_deleted=true&limit=1".to_string();
+        loop {
+            let (status, body) = call(&mut app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(status, StatusCode::OK);
+            let page = test_support::json(&body);
+            for team in page["teams"].as_array().unwrap() {
+                seen.push((team["team_abbreviation"].clone(), team["deleted"].clone()));
+            }
+            match page["next_cursor"].as_str() {
+                Some(cursor) => uri = format!("/api/v0/teams?include_deleted=true&limit=1&after={}", cursor),
+                None => break,
+            }
+        }
+        let expected = vec![
+            (json!("チーム0"), json!(null)),
+            (json!("チーム1"), json!(null)),
+            (json!("チーム1"), json!(true)),
+            (json!("チーム2"), json!(null)),
+        ];
+        assert_eq!(seen, expected);
+    }
+
+    #[actix_rt::test]
+    async fn page_links_percent_encode_the_query_they_keep() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let uri = "/api/v0/teams?page=1&per_page=2&sort=join_year,%20-division&note=a+b%3E";
+        let res = actix_web::test::call_service(&mut app, TestRequest::get().uri(uri).to_request()).await;
+        let link = res.headers().get(header::LINK).unwrap().to_str().unwrap().to_string();
+        let next = link.split(", ").find(|l| l.ends_with("rel=\"next\"")).unwrap();
+        let kept = "?page=2&per_page=2&sort=join_year,%20-division&note=a%20b%3E>; rel=\"next\"";
+        assert!(next.ends_with(kept), "{}", next);
+
+        let uri = "/api/v0/teams.html?page=1&per_page=2&sort=join_year,%20-division";
+        let (status, body) = call(&mut app, TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        let next = "<a href=\"?page=2&amp;per_page=2&amp;sort=join_year,%20-division\">next</a>";
+        assert!(html.contains(next), "{}", html);
+    }
+
+    #[actix_rt::test]
+    async fn labels_with_commas_and_quotes_survive_a_csv_export_and_import() {
+        let config = test_support::config();
+        let mut team = team("甲府", "山梨県", 1999, "J2");
+        team.labels = vec!["a, b".to_string(), "say \"hi\"".to_string(), "c".to_string()];
+        let store = web::Data::new(TeamStore::new(vec![team], 10, false));
+        let mut app = test_support::app(config, store.clone()).await;
+
+        let req = TestRequest::get().uri("/api/v0/teams.csv?bom=false").to_request();
+        let (status, csv) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        store.remove("甲府").unwrap();
+        let req = TestRequest::post().uri("/api/v0/import/csv").set_payload(csv).to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        assert_eq!(store.all().unwrap()[0].labels, ["a, b", "say \"hi\"", "c"]);
+
+        let csv = "team_abbreviation,active_area,join_year,labels\r\n甲府,山梨県,1999,\"x, y\"\r\n";
+        let req = TestRequest::post().uri("/api/v0/import/csv").set_payload(csv).to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::OK);
+        assert_eq!(store.all().unwrap()[0].labels, ["x", "y"]);
+    }
+
+    #[actix_rt::test]
+    async fn a_dry_run_previews_against_its_scratch_copy_not_the_live_store() {
+        let store = TeamStore::new(vec![team("甲府", "山梨県", 1999, "J2")], 10, false);
+        let scratch = store.scratch_copy().unwrap();
+        let dry_run = DryRun { before: scratch.all().unwrap(), scratch: Some(scratch) };
+        dry_run.store(&store).insert(team("岐阜", "岐阜県", 2008, "J2")).unwrap();
+        store.insert(team("鳥栖", "佐賀県", 1999, "J1")).unwrap();
+        store.remove("甲府").unwrap();
+
+        let res = dry_run.preview().unwrap().unwrap();
+        let res = actix_web::dev::ServiceResponse::new(TestRequest::default().to_http_request(), res);
+        let body = actix_web::test::read_body(res).await;
+        let expected = json!({"dry_run": true, "would_add": ["岐阜"], "would_change": [], "would_delete": []});
+        test_support::assert_json(&body, expected);
+    }
+
+    #[actix_rt::test]
+    async fn a_malformed_dry_run_is_400_and_changes_nothing() {
+        let config = test_support::config();
+        let store = test_support::seeded_store(&config);
+        let mut app = test_support::app(config, store.clone()).await;
+        for dry_run in ["1", "yes", "TRUE", ""] {
+            // 鹿島, percent-encoded
+            let uri = format!("/api/v0/teams/%E9%B9%BF%E5%B3%B6?dry_run={}", dry_run);
+            let (status, body) = call(&mut app, TestRequest::delete().uri(&uri).to_request()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "dry_run={}", dry_run);
+            test_support::assert_error_code(&body, "bad_request");
+        }
+        assert_eq!(store.all().unwrap(), seed_teams());
+    }
+
+    fn team(abbr: &str, area: &str, join_year: u32, division: &str) -> Team {
+        let team = json!({
+            "team_abbreviation": abbr, "active_area": area, "join_year": join_year, "division": division,
+        });
+        serde_json::from_value(team).unwrap()
+    }
+
+    fn abbreviations(teams: &[Team]) -> Vec<&str> {
+        teams.iter().map(Team::abbreviation).collect()
+    }
+
+    fn sort_fixture() -> Vec<Team> {
+        vec![
+            team("a", "東京都", 2001, "J2"),
+            team("b", "愛知県", 1993, "J1"),
+            team("c", "東京都", 1999, "J1"),
+            team("d", "愛知県", 1993, "J2"),
+            team("e", "東京都", 2001, "J1"),
+        ]
+    }
+
+    #[test]
+    fn teams_sort_by_each_key_in_turn() {
+        let teams = sort_fixture();
+        let by = |sort| abbreviations(&sorted(&teams, &parse_sort(sort).unwrap())).join("");
+        // 愛知県 sorts before 東京都, by code point
+        assert_eq!(by("active_area"), "bdace");
+        assert_eq!(by("-active_area"), "acebd");
+        assert_eq!(by("active_area,-join_year"), "bdaec");
+        assert_eq!(by("-join_year,division"), "eacbd");
+        assert_eq!(by("division,-team_abbreviation"), "ecbda");
+        assert_eq!(by(" join_year , -division "), "dbcae");
+        // ties keep the stored order
+        assert_eq!(by("join_year"), "bdcae");
+        assert_eq!(by(""), "abcde");
+    }
+
+    #[test]
+    fn unknown_sort_keys_list_the_valid_ones() {
+        for sort in ["name", "-labels", "active_area,--join_year", "join_year,+division"] {
+            let e = parse_sort(sort).unwrap_err();
+            assert_eq!(e.code(), "bad_request");
+            let message = e.to_string();
+            assert!(message.contains("team_abbreviation, active_area, join_year, division"), "{}", message);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn team_lists_are_sorted_by_sort() {
+        let config = test_support::config();
+        let store = web::Data::new(TeamStore::new(sort_fixture(), 10, false));
+        let mut app = test_support::app(config, store).await;
+        let uri = "/api/v0/teams?sort=active_area,-join_year&fields=team_abbreviation";
+        let (status, body) = call(&mut app, TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        let expected = ["b", "d", "a", "e", "c"].iter().map(|abbr| json!({"team_abbreviation": abbr}));
+        test_support::assert_json(&body, expected.collect());
+
+        let req = TestRequest::get().uri("/api/v0/teams?sort=active_area,founded").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        test_support::assert_error_code(&body, "bad_request");
+    }
+
+    #[actix_rt::test]
+    async fn workers_sharing_one_store_see_each_others_writes() {
+        // one store made outside the app factory, as `main` does, and an app
+        // per worker around clones of it
+        let config = test_support::config();
+        let store = test_support::seeded_store(&config);
+        let mut first = test_support::app(config.clone(), store.clone()).await;
+        let mut second = test_support::app(config, store.clone()).await;
+
+        let team = json!({"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": 1999});
+        let req = TestRequest::post().uri("/api/v0/teams").set_json(&team).to_request();
+        assert_eq!(call(&mut first, req).await.0, StatusCode::CREATED);
+
+        // 甲府, percent-encoded
+        let kofu = "/api/v0/teams/%E7%94%B2%E5%BA%9C";
+        let (status, body) = call(&mut second, TestRequest::get().uri(kofu).to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(test_support::json(&body)["active_area"], "山梨県");
+
+        let req = TestRequest::delete().uri(kofu).to_request();
+        assert!(call(&mut second, req).await.0.is_success());
+        let (status, _) = call(&mut first, TestRequest::get().uri(kofu).to_request()).await;
+        assert!(status.is_client_error(), "{}", status);
+        assert_eq!(store.all().unwrap().len(), seed_teams().len());
+    }
+
+    fn rename(body: serde_json::Value, dry_run: bool) -> actix_http::Request {
+        let uri = format!("/api/v0/divisions/rename?dry_run={}", dry_run);
+        TestRequest::post().uri(&uri).set_json(&body).to_request()
+    }
+
+    #[actix_rt::test]
+    async fn renaming_a_division_moves_every_team_in_it() {
+        let config = test_support::config();
+        let teams = vec![
+            team("a", "東京都", 2001, "J2"),
+            team("b", "愛知県", 1993, "J2"),
+            team("c", "栃木県", 1999, "J2"),
+        ];
+        let store = web::Data::new(TeamStore::new(teams, 10, false));
+        let mut app = test_support::app(config, store.clone()).await;
+        let divisions = || store.all().unwrap().iter().map(|t| t.division().to_string()).collect::<Vec<_>>();
+
+        // a dry run lists the teams that would move and changes nothing
+        let (status, body) = call(&mut app, rename(json!({"from": "J2", "to": "J1"}), true)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(test_support::json(&body)["would_change"], json!(["a", "b", "c"]));
+        assert_eq!(divisions(), ["J2", "J2", "J2"]);
+
+        let (status, body) = call(&mut app, rename(json!({"from": "J2", "to": "J1"}), false)).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(&body, json!({"from": "J2", "to": "J1", "changed": 3}));
+        assert_eq!(divisions(), ["J1", "J1", "J1"]);
+
+        // renaming a division into itself, or one left empty, changes nothing
+        for body in [json!({"from": "J1", "to": "J1"}), json!({"from": "J2", "to": "J2"})] {
+            let (status, res) = call(&mut app, rename(body.clone(), false)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(test_support::json(&res)["changed"], 0, "{}", body);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn renaming_into_a_division_with_teams_is_409() {
+        let config = test_support::config();
+        let store = web::Data::new(TeamStore::new(sort_fixture(), 10, false));
+        let mut app = test_support::app(config, store.clone()).await;
+        for dry_run in [true, false] {
+            let (status, body) = call(&mut app, rename(json!({"from": "J2", "to": "J1"}), dry_run)).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            test_support::assert_error_code(&body, "conflict");
+        }
+        assert_eq!(store.all().unwrap(), sort_fixture());
+    }
+
+    #[actix_rt::test]
+    async fn a_division_can_be_renamed_to_a_new_name() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let in_j2 = seed_teams().into_iter().filter(|t| t.division() == "J2").count();
+        let (status, body) = call(&mut app, rename(json!({"from": "J2", "to": "J2-League"}), false)).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(&body, json!({"from": "J2", "to": "J2-League", "changed": in_j2}));
+
+        let req = TestRequest::get().uri("/api/v0/teams?fields=division").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let teams = test_support::json(&body);
+        let divisions: BTreeSet<&str> =
+            teams.as_array().unwrap().iter().map(|t| t["division"].as_str().unwrap()).collect();
+        assert_eq!(divisions, ["J1", "J2-League"].iter().copied().collect());
+
+        let too_long = "J".repeat(DIVISION_NAME_MAX + 1);
+        for body in [json!({"from": "J1", "to": " "}), json!({"from": "J1", "to": too_long})] {
+            let (status, res) = call(&mut app, rename(body.clone(), false)).await;
+            assert!(status.is_client_error(), "{} for {}", status, body);
+            test_support::assert_error_code(&res, "validation_failed");
+        }
+        let (status, res) = call(&mut app, rename(json!({"from": "J2"}), false)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        test_support::assert_error_code(&res, "bad_request");
+    }
+
+    #[actix_rt::test]
+    async fn imported_teams_are_prepared_like_posted_ones() {
+        let mut config = test_support::config();
+        config.default_division = "J2".to_string();
+        let store = web::Data::new(TeamStore::new(Vec::new(), 10, false));
+        let mut app = test_support::app(config, store.clone()).await;
+        let teams = json!([
+            {"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": 1999, "labels": ["甲斐", "甲斐"]},
+            {"team_abbreviation": "岐阜", "active_area": "岐阜県", "join_year": 2008, "division": "J1"},
+        ]);
+        let req = TestRequest::post().uri("/api/v0/teams/import").set_json(&teams).to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::OK);
+        let kofu = store.get("甲府").unwrap();
+        assert_eq!((kofu.division(), kofu.labels()), ("J2", &["甲斐".to_string()][..]));
+        assert_eq!(store.get("岐阜").unwrap().division(), "J1");
+
+        let invalid = json!([
+            {"team_abbreviation": "松本", "active_area": "長野県", "join_year": 2012, "labels": [""]},
+        ]);
+        let req = TestRequest::post().uri("/api/v0/teams/import").set_json(&invalid).to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert!(status.is_client_error(), "{}", status);
+        test_support::assert_error_code(&body, "validation_failed");
+        assert!(store.get("松本").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn ndjson_imported_teams_are_prepared_like_posted_ones() {
+        let mut config = test_support::config();
+        config.default_division = "J2".to_string();
+        let store = web::Data::new(TeamStore::new(Vec::new(), 10, false));
+        let mut app = test_support::app(config, store.clone()).await;
+        let body = concat!(
+            r#"{"team_abbreviation":"甲府","active_area":"山梨県","join_year":1999,"labels":["甲斐","甲斐"]}"#,
+            "\n",
+            r#"{"team_abbreviation":"岐阜","active_area":"岐阜県","join_year":2008,"division":"J1"}"#,
+            "\n",
+            r#"{"team_abbreviation":"松本","active_area":"長野県","join_year":2012,"labels":[" "]}"#,
+            "\n",
+        );
+        let req = TestRequest::post().uri("/api/v0/teams/import/ndjson").set_payload(body).to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(&body, json!({"imported": 2, "failed": 1, "failed_lines": [3]}));
+
+        let kofu = store.get("甲府").unwrap();
+        assert_eq!((kofu.division(), kofu.labels()), ("J2", &["甲斐".to_string()][..]));
+        assert_eq!(store.get("岐阜").unwrap().division(), "J1");
+        assert!(store.get("松本").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn a_created_team_is_listed_and_an_unknown_one_is_404() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let team = json!({"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": 1999});
+        let req = TestRequest::post().uri("/api/v0/teams").set_json(&team).to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::CREATED);
+
+        let req = TestRequest::get().uri("/api/v0/teams?fields=team_abbreviation").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(
+            &body,
+            json!([
+                {"team_abbreviation": "鹿島"},
+                {"team_abbreviation": "浦和"},
+                {"team_abbreviation": "水戸"},
+                {"team_abbreviation": "甲府"},
+            ]),
+        );
+
+        // 京都, percent-encoded
+        let req = TestRequest::get().uri("/api/v0/teams/%E4%BA%AC%E9%83%BD").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        test_support::assert_error_code(&body, "not_found");
+    }
+
+    #[test]
+    fn join_years_up_to_the_current_year_are_allowed() {
+        assert!(join_year_not_after(1991, 2020).is_ok());
+        assert!(join_year_not_after(2020, 2020).is_ok());
+        let e = join_year_not_after(2021, 2020).unwrap_err();
+        assert_eq!(e.code, "join_year_in_future");
+        assert_eq!(e.params["current_year"], json!(2020));
+    }
+
+    #[actix_rt::test]
+    async fn a_team_joining_after_current_year_is_400() {
+        let mut config = test_support::config();
+        config.current_year = 2000;
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let team = json!({"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": 2001});
+        let req = TestRequest::post().uri("/api/v0/teams").set_json(&team).to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        test_support::assert_error_code(&body, "validation_failed");
+
+        let team = json!({"team_abbreviation": "甲府", "active_area": "山梨県", "join_year": 2000});
+        let req = TestRequest::post().uri("/api/v0/teams").set_json(&team).to_request();
+        assert_eq!(call(&mut app, req).await.0, StatusCode::CREATED);
+    }
+
+    #[actix_rt::test]
+    async fn tenure_counts_from_current_year() {
+        let mut config = test_support::config();
+        config.current_year = 2001;
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        // 鹿島, percent-encoded
+        let req = TestRequest::get().uri("/api/v0/teams/%E9%B9%BF%E5%B3%B6/tenure").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(&body, json!({"join_year": 1991, "years_active": 10}));
+
+        // 水戸 joined in 2000
+        let req = TestRequest::get().uri("/api/v0/teams/%E6%B0%B4%E6%88%B8/tenure").to_request();
+        let (_, body) = call(&mut app, req).await;
+        test_support::assert_json(&body, json!({"join_year": 2000, "years_active": 1}));
+    }
+
+    #[test]
+    fn teams_are_shown_by_abbreviation_area_and_join_year() {
+        let shown = seed_teams().iter().map(display_name).collect::<Vec<_>>();
+        assert_eq!(shown, ["鹿島 (茨城県, since 1991)", "浦和 (埼玉県, since 1991)", "水戸 (茨城県, since 2000)"]);
+    }
+
+    #[actix_rt::test]
+    async fn display_adds_the_display_name_to_the_list() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let req = TestRequest::get().uri("/api/v0/teams?display=true&fields=team_abbreviation").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(
+            &body,
+            json!([
+                {"team_abbreviation": "鹿島", "display_name": "鹿島 (茨城県, since 1991)"},
+                {"team_abbreviation": "浦和", "display_name": "浦和 (埼玉県, since 1991)"},
+                {"team_abbreviation": "水戸", "display_name": "水戸 (茨城県, since 2000)"},
+            ]),
+        );
+
+        let req = TestRequest::get().uri("/api/v0/teams?fields=team_abbreviation").to_request();
+        let (_, body) = call(&mut app, req).await;
+        assert!(test_support::json(&body)[0].get("display_name").is_none());
+    }
+
+    /// the abbreviations of the teams in a json team list
+    fn listed(body: &[u8]) -> Vec<String> {
+        let teams = test_support::json(body);
+        let abbreviation = |team: &serde_json::Value| team["team_abbreviation"].as_str().unwrap().to_string();
+        teams.as_array().unwrap().iter().map(abbreviation).collect()
+    }
+
+    #[actix_rt::test]
+    async fn the_founding_teams_are_those_joined_before_the_cutoff() {
+        let mut config = test_support::config();
+        config.current_year = 2020;
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let founding =
+            |query: &str| TestRequest::get().uri(&format!("/api/v0/teams/founding{}", query)).to_request();
+
+        let (status, body) = call(&mut app, founding("")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed(&body), ["鹿島", "浦和"]);
+        assert_eq!(listed(&call(&mut app, founding("?before=2001")).await.1), ["鹿島", "浦和", "水戸"]);
+        assert!(listed(&call(&mut app, founding("?before=1991")).await.1).is_empty());
+        assert_eq!(call(&mut app, founding("?before=2021")).await.0, StatusCode::OK);
+
+        for before in ["1899", "2022"] {
+            let (status, body) = call(&mut app, founding(&format!("?before={}", before))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", before);
+            test_support::assert_error_code(&body, "bad_request");
+        }
+    }
+
+    /// the abbreviations and scores `/api/v0/teams/fuzzy` answers `query` with
+    /// on the seed teams and 川崎 and 長崎
+    async fn fuzzy(query: &str) -> Vec<(String, f64)> {
+        let mut teams = seed_teams();
+        teams.extend([team("川崎", "神奈川県", 1999, "J1"), team("長崎", "長崎県", 2013, "J2")]);
+        let store = web::Data::new(TeamStore::new(teams, 10, false));
+        let query = web::Query::<FuzzyQuery>::from_query(query).unwrap();
+        let res = teams_fuzzy(query, store).await.unwrap();
+        let res = actix_web::dev::ServiceResponse::new(TestRequest::default().to_http_request(), res);
+        let matches = test_support::json(&actix_web::test::read_body(res).await);
+        let scored = |m: &serde_json::Value| {
+            (m["team_abbreviation"].as_str().unwrap().to_string(), m["score"].as_f64().unwrap())
+        };
+        matches.as_array().unwrap().iter().map(scored).collect()
+    }
+
+    #[actix_rt::test]
+    async fn part_of_an_abbreviation_finds_the_teams_containing_it() {
+        assert_eq!(fuzzy("q=鹿").await, [("鹿島".to_string(), 0.5)]);
+        assert_eq!(fuzzy("q=島").await, [("鹿島".to_string(), 0.5)]);
+        // the same score, so by abbreviation
+        assert_eq!(fuzzy("q=崎").await, [("川崎".to_string(), 0.5), ("長崎".to_string(), 0.5)]);
+        assert_eq!(fuzzy("q=川崎").await, [("川崎".to_string(), 1.0), ("長崎".to_string(), 0.5)]);
+        assert!(fuzzy("q=札").await.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn a_mistyped_kanji_is_found_within_max_distance() {
+        // 鹿嶋 for 鹿島
+        assert_eq!(fuzzy("q=鹿嶋").await, [("鹿島".to_string(), 0.5)]);
+        assert!(fuzzy("q=鹿嶋&max_distance=0").await.is_empty());
+        assert_eq!(fuzzy("q=%E9%B9%BF%E5%B6%8B").await, fuzzy("q=鹿嶋").await);
+    }
+
+    #[actix_rt::test]
+    async fn teams_in_any_of_several_areas_are_listed() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let in_areas = |areas: &str| {
+            let areas = utf8_percent_encode(areas, NON_ALPHANUMERIC);
+            let uri = format!("/api/v0/teams?active_area={}&fields=team_abbreviation", areas);
+            TestRequest::get().uri(&uri).to_request()
+        };
+
+        // no team is in 北海道
+        let (status, body) = call(&mut app, in_areas("茨城県,北海道,埼玉県")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed(&body), ["鹿島", "浦和", "水戸"]);
+        assert_eq!(listed(&call(&mut app, in_areas(" 北海道 , 埼玉県 ")).await.1), ["浦和"]);
+        assert_eq!(listed(&call(&mut app, in_areas("茨城県,")).await.1), ["鹿島", "水戸"]);
+        let (status, body) = call(&mut app, in_areas("北海道,沖縄県")).await;
+        assert_eq!(status, StatusCode::OK);
+        test_support::assert_json(&body, json!([]));
+    }
+
+    #[actix_rt::test]
+    async fn an_empty_list_is_an_array_unless_empty_null() {
+        let config = test_support::config();
+        let none = web::Data::new(TeamStore::new(Vec::new(), 10, false));
+        let mut app = test_support::app(config.clone(), none).await;
+        for (query, expected) in [
+            ("", "[]"),
+            ("?empty=array", "[]"),
+            ("?empty=null", "null"),
+            ("?empty=null&stream=true", "null"),
+            ("?empty=null&fields=team_abbreviation", "null"),
+        ] {
+            let req = TestRequest::get().uri(&format!("/api/v0/teams{}", query)).to_request();
+            let (status, body) = call(&mut app, req).await;
+            assert_eq!(status, StatusCode::OK, "{}", query);
+            assert_eq!(body, expected, "{}", query);
+        }
+        let req = TestRequest::get().uri("/api/v0/teams?empty=none").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        test_support::assert_error_code(&body, "bad_request");
+
+        // only an empty list is written as null
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let req = TestRequest::get().uri("/api/v0/teams?empty=null").to_request();
+        assert_eq!(listed(&call(&mut app, req).await.1), ["鹿島", "浦和", "水戸"]);
+        // 北海道, percent-encoded
+        let req = TestRequest::get().uri("/api/v0/teams?empty=null&active_area=%E5%8C%97%E6%B5%B7%E9%81%93");
+        let (status, body) = call(&mut app, req.to_request()).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"null"[..]));
+    }
+
+    #[actix_rt::test]
+    async fn the_csv_export_starts_with_a_bom_unless_bom_false() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+        let csv = "team_abbreviation,active_area,join_year,division,labels\r\n\
+                   鹿島,茨城県,1991,J1,\r\n\
+                   浦和,埼玉県,1991,J1,\r\n\
+                   水戸,茨城県,2000,J2,\r\n";
+        let with_bom = format!("{}{}", UTF8_BOM, csv);
+        for (query, expected) in [("", &with_bom[..]), ("?bom=true", &with_bom), ("?bom=false", csv)] {
+            let req = TestRequest::get().uri(&format!("/api/v0/teams.csv{}", query)).to_request();
+            let (status, body) = call(&mut app, req).await;
+            assert_eq!(status, StatusCode::OK, "{}", query);
+            assert_eq!(std::str::from_utf8(&body).unwrap(), expected, "{}", query);
+        }
+        let req = TestRequest::get().uri("/api/v0/teams.csv").to_request();
+        assert!(call(&mut app, req).await.1.starts_with(b"\xEF\xBB\xBF"));
+        let req = TestRequest::get().uri("/api/v0/teams.csv?bom=false").to_request();
+        assert!(call(&mut app, req).await.1.starts_with(b"team_abbreviation,"));
+    }
+}
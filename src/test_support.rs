@@ -0,0 +1,91 @@
+//! Shared pieces for handler tests: the app as `main` builds it, around a
+//! store seeded with the built-in teams and news, a way to call it that
+//! turns errors into the responses a client would see, and json assertions.
+
+use std::time::Duration;
+
+use actix_web::dev::{MessageBody, Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes};
+use actix_web::{test, App, Error};
+use actix_http::Request;
+use serde_json::Value;
+
+use crate::admin::StopHandle;
+use crate::config::Config;
+use crate::debug::Flaky;
+use crate::endpoint::{Endpoint, RouteTable};
+use crate::error;
+use crate::maintenance::Maintenance;
+use crate::metrics::RequestCounts;
+use crate::news::NewsStore;
+use crate::recent::RecentRequests;
+use crate::teams::{self, AreaRegexes, TeamStore};
+use crate::trace::Tracer;
+
+/// the configuration the environment gives, as `main` would read it
+pub fn config() -> Config {
+    Config::from_env()
+}
+
+/// a store holding the built-in teams, with `config`'s limits
+pub fn seeded_store(config: &Config) -> web::Data<TeamStore> {
+    web::Data::new(TeamStore::new(teams::seed_teams(), config.max_teams, config.soft_delete))
+}
+
+/// every route of the app, with the json error handler, `config`, the team
+/// `store`, the seeded news and the rest of the shared state `main` sets up,
+/// but none of the middleware
+pub async fn app(
+    config: Config,
+    store: web::Data<TeamStore>,
+) -> impl Service<Request = Request, Response = ServiceResponse, Error = Error> {
+    let route_table = crate::routes().iter().map(Endpoint::describe).collect::<RouteTable>();
+    let app = App::new()
+        .app_data(web::JsonConfig::default().error_handler(error::json_error))
+        .app_data(web::Data::new(config))
+        .app_data(store)
+        .app_data(web::Data::new(NewsStore::seeded()))
+        .app_data(web::Data::new(StopHandle::default()))
+        .app_data(web::Data::new(Flaky::default()))
+        .app_data(web::Data::new(RequestCounts::new(&route_table)))
+        .app_data(web::Data::new(route_table))
+        .app_data(web::Data::new(RecentRequests::from_env()))
+        .app_data(web::Data::new(Maintenance::default()))
+        .app_data(web::Data::new(Tracer::default()))
+        .app_data(web::Data::new(AreaRegexes::new(64, Duration::from_secs(3600))));
+    test::init_service(crate::routes().into_iter().fold(app, |app, endpoint| app.service(endpoint))).await
+}
+
+/// the status and body `app` answers `req` with, an error from a handler or
+/// middleware included as the response it would be sent as
+pub async fn call<S, B>(app: &mut S, req: Request) -> (StatusCode, Bytes)
+where
+    S: Service<Request = Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    match app.call(req).await {
+        Ok(res) => (res.status(), test::read_body(res).await),
+        Err(e) => {
+            let res = e.as_response_error().error_response();
+            let res = ServiceResponse::new(test::TestRequest::default().to_http_request(), res);
+            (res.status(), test::read_body(res).await)
+        }
+    }
+}
+
+/// `body` as json, failing the test when it isn't
+pub fn json(body: &[u8]) -> Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|e| panic!("not json ({}): {}", e, String::from_utf8_lossy(body)))
+}
+
+/// assert `body` is the json `expected`
+pub fn assert_json(body: &[u8], expected: Value) {
+    assert_eq!(json(body), expected);
+}
+
+/// assert `body` is a json error with `code`
+pub fn assert_error_code(body: &[u8], code: &str) {
+    assert_eq!(json(body)["code"], code, "in {}", String::from_utf8_lossy(body));
+}
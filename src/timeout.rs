@@ -0,0 +1,183 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_rt::time::timeout;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::config::env_or;
+use crate::error::ApiError;
+
+/// parse `ROUTE_TIMEOUTS`: comma-separated `path=ms` pairs
+pub fn parse_routes(spec: &str) -> Result<Vec<(String, Duration)>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            let path = parts.next().unwrap_or("").trim();
+            let ms = parts.next().and_then(|ms| ms.trim().parse().ok());
+            match ms {
+                Some(ms) if path.starts_with('/') => Ok((path.to_string(), Duration::from_millis(ms))),
+                _ => Err(format!("ROUTE_TIMEOUTS entry {:?} must look like /path=ms", entry)),
+            }
+        })
+        .collect()
+}
+
+/// a deadline that grows with the size of the input:
+/// `base + per_kb * ceil(bytes / 1024)`, clamped to `max`
+#[derive(Debug, Clone, Copy)]
+pub struct ScaledTimeout {
+    pub base: Duration,
+    pub per_kb: Duration,
+    pub max: Duration,
+}
+
+impl ScaledTimeout {
+    /// the deadline for `POST /something`, on when `CREATE_TIMEOUT_BASE_MS` is
+    /// set; `CREATE_TIMEOUT_PER_KB_MS` (1000 by default) is added per started
+    /// KiB of input, up to `CREATE_TIMEOUT_MAX_MS` (30000 by default)
+    pub fn create_from_env() -> Option<Self> {
+        let base = std::env::var("CREATE_TIMEOUT_BASE_MS").ok()?.parse().ok()?;
+        Some(ScaledTimeout {
+            base: Duration::from_millis(base),
+            per_kb: Duration::from_millis(env_or("CREATE_TIMEOUT_PER_KB_MS", 1000)),
+            max: Duration::from_millis(env_or("CREATE_TIMEOUT_MAX_MS", 30_000)),
+        })
+    }
+
+    pub fn for_size(&self, bytes: usize) -> Duration {
+        let kib = u32::try_from(bytes.div_ceil(1024)).unwrap_or(u32::MAX);
+        (self.base + self.per_kb * kib).min(self.max)
+    }
+}
+
+/// how much work a handler does: slow ones wait on httpbin, fast ones only
+/// touch local state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Fast,
+    Slow,
+}
+
+impl Class {
+    /// the httpbin-backed `/something` endpoints (and `/debug/batch` and
+    /// `/debug/bench/chain`, which run the chain, and `/proxy`) are slow,
+    /// everything else is fast
+    pub fn of(path: &str) -> Self {
+        if path == "/something"
+            || path.starts_with("/something/")
+            || path == "/debug/batch"
+            || path == "/debug/bench/chain"
+            || path == "/proxy"
+        {
+            Class::Slow
+        } else {
+            Class::Fast
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Class::Fast => "fast",
+            Class::Slow => "slow",
+        }
+    }
+}
+
+/// answer `504` when the handler hasn't produced a response within the
+/// deadline for its path. For streaming responses the deadline covers the
+/// time until the response starts, not the whole body.
+#[derive(Debug, Clone)]
+pub struct RequestTimeout {
+    fast: Duration,
+    slow: Duration,
+    routes: Arc<Vec<(String, Duration)>>,
+}
+
+impl RequestTimeout {
+    /// the deadline for each [`Class`] of handler, `FAST_REQUEST_TIMEOUT_MS`
+    /// and `SLOW_REQUEST_TIMEOUT_MS`, both `REQUEST_TIMEOUT_MS` (30000) by
+    /// default, overridden for the exact paths listed in `ROUTE_TIMEOUTS`
+    pub fn from_env() -> Self {
+        let routes = parse_routes(&std::env::var("ROUTE_TIMEOUTS").unwrap_or_default())
+            .unwrap_or_default();
+        let default = env_or("REQUEST_TIMEOUT_MS", 30_000);
+        RequestTimeout {
+            fast: Duration::from_millis(env_or("FAST_REQUEST_TIMEOUT_MS", default)),
+            slow: Duration::from_millis(env_or("SLOW_REQUEST_TIMEOUT_MS", default)),
+            routes: Arc::new(routes),
+        }
+    }
+
+    /// the deadline for `path`, and the class it was picked by, if any
+    fn for_path(&self, path: &str) -> (Duration, Option<Class>) {
+        if let Some((_, limit)) = self.routes.iter().find(|(route, _)| route == path) {
+            return (*limit, None);
+        }
+        match Class::of(path) {
+            Class::Fast => (self.fast, Some(Class::Fast)),
+            Class::Slow => (self.slow, Some(Class::Slow)),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RequestTimeout
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTimeoutMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestTimeoutMiddleware { service, limits: self.clone() })
+    }
+}
+
+pub struct RequestTimeoutMiddleware<S> {
+    service: S,
+    limits: RequestTimeout,
+}
+
+impl<S, B> Service for RequestTimeoutMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let (limit, class) = self.limits.for_path(req.path());
+        let path = req.path().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            match timeout(limit, fut).await {
+                Ok(res) => res,
+                Err(_) => {
+                    let deadline = class.map_or("ROUTE_TIMEOUTS", Class::name);
+                    log::warn!("{} timed out after {}ms ({} deadline)", path, limit.as_millis(), deadline);
+                    Err(ApiError::Timeout(format!("no response within {}ms", limit.as_millis())).into())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::io;
+use std::net::TcpListener;
+
+use actix_http::{body::MessageBody, Error, HttpService, Request, Response};
+use actix_server::Server;
+use actix_service::{map_config, IntoServiceFactory, Service, ServiceFactory};
+use actix_web::dev::AppConfig;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+
+use crate::config::Config;
+
+fn ssl_error(e: openssl::error::ErrorStack) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+/// the TLS acceptor for `TLS_CERT_FILE`/`TLS_KEY_FILE`, or `None` to serve
+/// plain http when neither is set. Over TLS, actix offers both `h2` and
+/// `http/1.1` through ALPN, so HTTP/2 clients get HTTP/2.
+pub fn acceptor(config: &Config) -> io::Result<Option<SslAcceptorBuilder>> {
+    let (cert, key) = match (&config.tls_cert_file, &config.tls_key_file) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => return Ok(None),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TLS_CERT_FILE and TLS_KEY_FILE must be set together",
+            ))
+        }
+    };
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(ssl_error)?;
+    builder.set_private_key_file(key, SslFiletype::PEM).map_err(ssl_error)?;
+    builder.set_certificate_chain_file(cert).map_err(ssl_error)?;
+    Ok(Some(builder))
+}
+
+/// the protocols the server will speak, for the startup log
+pub fn protocols(tls: bool, config: &Config) -> &'static str {
+    if tls {
+        return "https: h2, http/1.1";
+    }
+    if config.h2c {
+        return "http: h2c with prior knowledge only";
+    }
+    "http: http/1.1"
+}
+
+/// `H2C`: serve the apps `factory` makes as cleartext HTTP/2 on `listener`,
+/// for clients that know to speak it from the first byte (prior knowledge,
+/// `curl --http2-prior-knowledge`). actix-web's `HttpServer` only speaks
+/// HTTP/1.1 on plain tcp, so this builds the server from actix-http's h2
+/// service directly; HTTP/1.1 clients can't use it, and there's no `Upgrade:
+/// h2c` from HTTP/1.1 either. Ignored with TLS, which offers h2 through ALPN.
+pub fn h2c_server<F, I, S, B>(factory: F, listener: TcpListener, workers: usize) -> io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    Ok(Server::build()
+        .workers(workers)
+        .listen("h2c", listener, move || {
+            HttpService::build().h2(map_config(factory(), |_| AppConfig::default())).tcp()
+        })?
+        .run())
+}
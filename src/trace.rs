@@ -0,0 +1,314 @@
+//! With `OTEL_TRACING` set, every request becomes a server span and every
+//! `step_x` call to httpbin a client span under it, with the trace carried to
+//! httpbin (and picked up from the caller) in a W3C `traceparent` header.
+//! Finished spans are batched and exported as OTLP/HTTP json to
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (`http://localhost:4318` by default) under
+//! `OTEL_SERVICE_NAME` (`awc_examples` by default). Without the flag no
+//! exporter is started and requests pass straight through.
+
+use std::env;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::client::Client;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage};
+use futures::channel::mpsc;
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::config::env_flag;
+
+/// the header trace context travels in
+pub const TRACEPARENT: &str = "traceparent";
+
+/// the most spans sent in one export
+const BATCH_MAX: usize = 512;
+
+/// which span a request or call belongs to; stored in the request's
+/// extensions for the handlers' upstream calls to continue
+#[derive(Debug, Clone)]
+pub struct TraceContext {
+    trace_id: String,
+    span_id: String,
+}
+
+impl TraceContext {
+    fn root() -> Self {
+        TraceContext { trace_id: format!("{:032x}", rand::random::<u128>().max(1)), span_id: span_id() }
+    }
+
+    /// the context in a `traceparent` header: `00-<trace id>-<span id>-<flags>`
+    fn parse(traceparent: &str) -> Option<Self> {
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        let hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit()) && s.bytes().any(|b| b != b'0')
+        };
+        match parts.as_slice() {
+            ["00", trace_id, span_id, _] if hex(trace_id, 32) && hex(span_id, 16) => Some(TraceContext {
+                trace_id: trace_id.to_ascii_lowercase(),
+                span_id: span_id.to_ascii_lowercase(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn child(&self) -> Self {
+        TraceContext { trace_id: self.trace_id.clone(), span_id: span_id() }
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+fn span_id() -> String {
+    format!("{:016x}", rand::random::<u64>().max(1))
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnyValue {
+    string_value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct KeyValue {
+    key: &'static str,
+    value: AnyValue,
+}
+
+fn attribute(key: &'static str, value: impl ToString) -> KeyValue {
+    KeyValue { key, value: AnyValue { string_value: value.to_string() } }
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    /// 1 ok, 2 error
+    code: u8,
+}
+
+/// SPAN_KIND_SERVER
+const SERVER: u8 = 2;
+/// SPAN_KIND_CLIENT
+const CLIENT: u8 = 3;
+
+/// a finished span, laid out as OTLP json has it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Span {
+    trace_id: String,
+    span_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_span_id: Option<String>,
+    name: String,
+    kind: u8,
+    start_time_unix_nano: String,
+    end_time_unix_nano: String,
+    attributes: Vec<KeyValue>,
+    status: Status,
+}
+
+/// a span still running; `end` sends it to the exporter
+pub struct OpenSpan {
+    exporter: mpsc::UnboundedSender<Span>,
+    context: TraceContext,
+    parent: Option<String>,
+    name: String,
+    kind: u8,
+    start: u64,
+}
+
+impl OpenSpan {
+    pub fn context(&self) -> &TraceContext {
+        &self.context
+    }
+
+    pub fn end(self, ok: bool, attributes: Vec<(&'static str, String)>) {
+        let span = Span {
+            trace_id: self.context.trace_id,
+            span_id: self.context.span_id,
+            parent_span_id: self.parent,
+            name: self.name,
+            kind: self.kind,
+            start_time_unix_nano: self.start.to_string(),
+            end_time_unix_nano: unix_nanos().to_string(),
+            attributes: attributes.into_iter().map(|(key, value)| attribute(key, value)).collect(),
+            status: Status { code: if ok { 1 } else { 2 } },
+        };
+        // the exporter only goes away with the server
+        let _ = self.exporter.unbounded_send(span);
+    }
+}
+
+/// the tracing middleware, and the handle upstream calls open their spans
+/// through; a `Tracer` that isn't enabled opens none
+#[derive(Debug, Clone, Default)]
+pub struct Tracer {
+    exporter: Option<mpsc::UnboundedSender<Span>>,
+}
+
+impl Tracer {
+    /// start exporting when `OTEL_TRACING` is set; must be called from within
+    /// the runtime
+    pub fn start() -> Self {
+        if !env_flag("OTEL_TRACING") {
+            return Tracer::default();
+        }
+        let endpoint =
+            env::var("OTEL_EXPORTER_OTLP_ENDPOINT").unwrap_or_else(|_| "http://localhost:4318".to_string());
+        let service = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "awc_examples".to_string());
+        let (tx, rx) = mpsc::unbounded();
+        actix_rt::spawn(export(rx, format!("{}/v1/traces", endpoint.trim_end_matches('/')), service));
+        Tracer { exporter: Some(tx) }
+    }
+
+    /// a client span for a call made on behalf of the request traced in
+    /// `parent`
+    pub fn client_span(&self, parent: Option<&TraceContext>, name: &str) -> Option<OpenSpan> {
+        let (exporter, parent) = (self.exporter.as_ref()?, parent?);
+        Some(OpenSpan {
+            exporter: exporter.clone(),
+            context: parent.child(),
+            parent: Some(parent.span_id.clone()),
+            name: name.to_string(),
+            kind: CLIENT,
+            start: unix_nanos(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportRequest<'a> {
+    resource_spans: [ResourceSpans<'a>; 1],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceSpans<'a> {
+    resource: Resource,
+    scope_spans: [ScopeSpans<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Serialize)]
+struct Scope {
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+struct ScopeSpans<'a> {
+    scope: Scope,
+    spans: &'a [Span],
+}
+
+/// send spans on as they finish, whatever has piled up in one request. A
+/// collector that is down loses those spans and nothing else.
+async fn export(mut spans: mpsc::UnboundedReceiver<Span>, url: String, service: String) {
+    let client = Client::build().timeout(Duration::from_secs(5)).finish();
+    while let Some(first) = spans.next().await {
+        let mut batch = vec![first];
+        while batch.len() < BATCH_MAX {
+            match spans.try_recv() {
+                Ok(span) => batch.push(span),
+                Err(_) => break,
+            }
+        }
+        let body = ExportRequest {
+            resource_spans: [ResourceSpans {
+                resource: Resource { attributes: vec![attribute("service.name", &service)] },
+                scope_spans: [ScopeSpans { scope: Scope { name: "awc_examples" }, spans: &batch }],
+            }],
+        };
+        match client.post(&url).send_json(&body).await {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => log::warn!("couldn't export {} spans: {} answered {}", batch.len(), url, res.status()),
+            Err(e) => log::warn!("couldn't export {} spans to {}: {}", batch.len(), url, e),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Tracer
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TracerMiddleware { service, exporter: self.exporter.clone() })
+    }
+}
+
+pub struct TracerMiddleware<S> {
+    service: S,
+    exporter: Option<mpsc::UnboundedSender<Span>>,
+}
+
+impl<S, B> Service for TracerMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, LocalBoxFuture<'static, Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let exporter = match &self.exporter {
+            Some(exporter) => exporter.clone(),
+            None => return Either::Left(self.service.call(req)),
+        };
+        let parent = req
+            .headers()
+            .get(TRACEPARENT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(TraceContext::parse);
+        let context = parent.as_ref().map(TraceContext::child).unwrap_or_else(TraceContext::root);
+        let span = OpenSpan {
+            exporter,
+            context: context.clone(),
+            parent: parent.map(|p| p.span_id),
+            name: format!("{} {}", req.method(), req.path()),
+            kind: SERVER,
+            start: unix_nanos(),
+        };
+        let attributes = vec![("http.method", req.method().to_string()), ("http.target", req.path().to_string())];
+        req.extensions_mut().insert(context);
+
+        let fut = self.service.call(req);
+        Either::Right(Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            let mut attributes = attributes;
+            attributes.push(("http.status_code", status.as_u16().to_string()));
+            span.end(!status.is_server_error(), attributes);
+            res
+        }))
+    }
+}
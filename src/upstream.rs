@@ -0,0 +1,1414 @@
+//! Everything that talks to httpbin: the `create_something` step chain and
+//! its streaming, websocket, batch and trace variants, the proxy and the
+//! chain benchmark. Only built with the `upstream` feature (on by default).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    client::{Client, ClientRequest, ClientResponse, SendRequestError},
+    error::PayloadError,
+    http::{header, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    web::{self, Bytes, BytesMut},
+    Error, HttpRequest, HttpResponse,
+};
+use futures::channel::mpsc;
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::Lru;
+use crate::config::Config;
+use crate::dedup::SingleFlight;
+use crate::debug;
+use crate::endpoint::Endpoint;
+use crate::error::ApiError;
+use crate::metrics::{self, UpstreamFailure, UpstreamStats};
+use crate::outbound::OutboundLimit;
+use crate::request_id;
+use crate::retry::Backoff;
+use crate::trace::{self, TraceContext, Tracer};
+use crate::utf8_json::Utf8Json;
+use crate::ws;
+use crate::{between_steps, percentile_ms, SomeData, CHAIN_STEPS};
+
+// mirrors the full httpbin echo so the response deserializes; only `json`,
+// `origin`, `url` and (for `/something/sent-headers`) `headers` are used
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct HttpBinResponse {
+    args: HashMap<String, String>,
+    data: String,
+    files: HashMap<String, String>,
+    form: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    json: SomeData,
+    origin: String,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainQuery {
+    #[serde(default)]
+    stream: bool,
+    /// answer with every step and where it came from, not just the result
+    #[serde(default)]
+    trace: bool,
+    /// how many steps to run, `CHAIN_STEPS` by default
+    steps: Option<usize>,
+}
+
+/// where a step's result came from: the httpbin echo's `url` and `origin` and
+/// the `Date` it answered with, or the step cache
+#[derive(Debug, Clone, Default, Serialize)]
+struct Provenance {
+    url: Option<String>,
+    origin: Option<String>,
+    date: Option<String>,
+    cached: bool,
+}
+
+/// one step of the `?trace=true` output
+#[derive(Debug, Clone, Serialize)]
+pub struct StepRecord {
+    step: usize,
+    result: SomeData,
+    #[serde(flatten)]
+    provenance: Provenance,
+}
+
+/// one line of the `?stream=true` output, emitted after each step completes
+#[derive(Debug, Serialize)]
+struct StepProgress<'a> {
+    step: usize,
+    result: &'a SomeData,
+}
+
+#[derive(Debug, Serialize)]
+struct StepFailure {
+    step: usize,
+    error: String,
+}
+
+/// the final line of the `?stream=true` output
+#[derive(Debug, Serialize)]
+struct ChainResult<'a> {
+    result: &'a SomeData,
+}
+
+/// inbound header values to copy onto the outbound request
+type Forwarded = Vec<(HeaderName, HeaderValue)>;
+
+/// `step_x` results by serialized input, shared by all workers
+pub type StepCache = Lru<SomeData>;
+
+/// what makes two `create_something` chains the same: the body, the headers
+/// forwarded to httpbin, whether the step cache is used and the step count
+type ChainKey = (Vec<u8>, Forwarded, bool, usize);
+
+/// `create_something` chains in flight, for identical requests to share
+pub type ChainFlights = SingleFlight<ChainKey, Result<Vec<StepRecord>, ApiError>>;
+
+/// the httpbin `step_x` talks to, what it carries over from the inbound
+/// request, and the cache it may answer from instead
+#[derive(Clone)]
+struct Upstream {
+    base_url: String,
+    forwarded: Forwarded,
+    cache: Option<web::Data<StepCache>>,
+    backoff: Backoff,
+    outbound: OutboundLimit,
+    tracer: Tracer,
+    /// the inbound request's span, when it's traced
+    trace: Option<TraceContext>,
+    /// the `REQUEST_ID_HEADER` and the inbound request's id in it
+    request_id: Option<(HeaderName, HeaderValue)>,
+    /// how long each call may take instead of `CLIENT_TIMEOUT_SECS`, from
+    /// `X-Debug-Timeout-Ms`
+    timeout: Option<Duration>,
+    /// `UPSTREAM_CHUNK_TIMEOUT_MS`
+    chunk_timeout: Duration,
+    stats: UpstreamStats,
+    /// `GRAPHEME_LENGTH`, for validating each step's input
+    graphemes: bool,
+    /// `MAX_CHAIN_STEPS`, which every chain run through this is held to
+    max_steps: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheQuery {
+    #[serde(default)]
+    no_cache: bool,
+}
+
+impl Upstream {
+    /// the upstream for one inbound request: the inbound headers named in the
+    /// `FORWARD_HEADERS` allowlist come along, anything not listed,
+    /// credentials included, stays behind. `step_x` results are cached when
+    /// `STEP_CACHE_SIZE` is set, unless the request says `?no_cache=true`,
+    /// retried as `STEP_RETRIES` says, and sent no more than
+    /// `MAX_OUTBOUND_REQUESTS` at a time, and traced under the inbound
+    /// request's span with `OTEL_TRACING`. Fails fast when `OFFLINE_MODE` is
+    /// set, before any network I/O is attempted. With `DEBUG_ENDPOINTS` the
+    /// request can override some of that, see [`debug::Overrides`].
+    fn for_request(req: &HttpRequest, config: &Config) -> Result<Self, ApiError> {
+        let overrides = debug::Overrides::of(req, config)?;
+        if config.offline_mode || overrides.offline {
+            return Err(ApiError::Unavailable(
+                "httpbin-backed endpoints are disabled in offline mode".to_string(),
+            ));
+        }
+        let inbound = req.headers();
+        let forwarded = config
+            .forward_headers
+            .iter()
+            .flat_map(|name| inbound.get_all(name).map(move |v| (name.clone(), v.clone())))
+            .collect();
+        let no_cache = web::Query::<CacheQuery>::from_query(req.query_string())
+            .map(|q| q.no_cache)
+            .unwrap_or(false);
+        let cache = req
+            .app_data::<web::Data<StepCache>>()
+            .filter(|cache| cache.enabled() && !no_cache)
+            .cloned();
+        let outbound =
+            req.app_data::<web::Data<OutboundLimit>>().map(|limit| limit.get_ref().clone()).unwrap_or_default();
+        let tracer = req.app_data::<web::Data<Tracer>>().map(|t| t.get_ref().clone()).unwrap_or_default();
+        let stats =
+            req.app_data::<web::Data<UpstreamStats>>().map(|s| s.get_ref().clone()).unwrap_or_default();
+        Ok(Upstream {
+            base_url: config.httpbin_url.clone(),
+            forwarded,
+            cache,
+            backoff: config.step_retry,
+            outbound,
+            tracer,
+            trace: req.extensions().get::<TraceContext>().cloned(),
+            request_id: request_id::of(req)
+                .and_then(|id| HeaderValue::from_str(&id).ok())
+                .map(|id| (config.request_id_header.clone(), id)),
+            timeout: overrides.client_timeout,
+            chunk_timeout: config.upstream_chunk_timeout,
+            graphemes: config.grapheme_length,
+            stats,
+            max_steps: config.max_chain_steps,
+        })
+    }
+
+    /// `requested` steps, or `MAX_CHAIN_STEPS` when that's fewer; the chains
+    /// run this many whatever count they were handed
+    fn steps(&self, requested: usize) -> usize {
+        requested.min(self.max_steps)
+    }
+
+    /// a request for `path` on httpbin, carrying the forwarded headers and the
+    /// request id, and with any timeout override
+    fn request(&self, client: &Client, method: Method, path: &str) -> ClientRequest {
+        let mut req = client.request(method, format!("{}{}", self.base_url, path));
+        for (name, value) in &self.forwarded {
+            req = req.header(name.clone(), value.clone());
+        }
+        if let Some((name, value)) = &self.request_id {
+            req = req.set_header(name.clone(), value.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            req = req.timeout(timeout);
+        }
+        req
+    }
+}
+
+/// turn a non-2xx httpbin answer into an error instead of trying to read a
+/// body that isn't there. A `429` keeps its `Retry-After` so the client is
+/// told to back off for as long.
+fn check_status<S>(res: &ClientResponse<S>, path: &str) -> Result<(), ApiError> {
+    if res.status().is_success() {
+        return Ok(());
+    }
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after =
+            res.headers().get(header::RETRY_AFTER).and_then(|v| v.to_str().ok()).map(str::to_string);
+        return Err(ApiError::TooManyRequests(format!("httpbin is rate limiting {}", path), retry_after));
+    }
+    Err(ApiError::Upstream(format!("httpbin returned {} for {}", res.status(), path)))
+}
+
+/// the next chunk of an httpbin response body, or a `504` when none comes
+/// within `window` of the last; the client timeout only covers the wait for
+/// the response head, so without this a body that trickles in could hold the
+/// request forever
+async fn next_chunk<S>(body: &mut S, window: Duration, path: &str) -> Option<Result<Bytes, ApiError>>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    match actix_rt::time::timeout(window, body.next()).await {
+        Ok(chunk) => chunk.map(|chunk| chunk.map_err(|e| ApiError::Upstream(e.to_string()))),
+        Err(_) => Some(Err(ApiError::Timeout(format!(
+            "httpbin sent nothing more of {} for {}ms",
+            path,
+            window.as_millis()
+        )))),
+    }
+}
+
+/// one round trip of `step_x`: post the data and read back what httpbin echoed
+async fn post_once(
+    data: &SomeData,
+    client: &Client,
+    upstream: &Upstream,
+) -> Result<(HttpBinResponse, Option<String>), ApiError> {
+    let span = upstream.tracer.client_span(upstream.trace.as_ref(), "POST /post");
+    let mut request = upstream.request(client, Method::POST, "/post");
+    if let Some(span) = &span {
+        request = request.header(trace::TRACEPARENT, span.context().traceparent());
+    }
+    let res = read_echo(data, request, upstream).await;
+    if let Some(span) = span {
+        let url = format!("{}/post", upstream.base_url);
+        span.end(res.is_ok(), vec![("http.method", "POST".to_string()), ("http.url", url)]);
+    }
+    res
+}
+
+async fn read_echo(
+    data: &SomeData,
+    request: ClientRequest,
+    upstream: &Upstream,
+) -> Result<(HttpBinResponse, Option<String>), ApiError> {
+    // held until the echo is read, whichever way this returns
+    let _permit = upstream.outbound.acquire().await?;
+    let echo = async {
+        let mut res = request.send_json(data).await.map_err(|e| {
+            let failure = match e {
+                SendRequestError::Timeout => UpstreamFailure::Timeout,
+                _ => UpstreamFailure::Network,
+            };
+            (failure, ApiError::Upstream(e.to_string())) // <- convert SendRequestError to an ApiError
+        })?;
+        check_status(&res, "/post").map_err(|e| (UpstreamFailure::BadStatus, e))?;
+        let date = res.headers().get(header::DATE).and_then(|v| v.to_str().ok()).map(str::to_string);
+
+        let mut body = BytesMut::new();
+        while let Some(chunk) = next_chunk(&mut res, upstream.chunk_timeout, "/post").await {
+            let chunk = chunk.map_err(|e| match e {
+                ApiError::Timeout(_) => (UpstreamFailure::Timeout, e),
+                _ => (UpstreamFailure::Network, e),
+            })?;
+            body.extend_from_slice(&chunk);
+        }
+
+        let body = serde_json::from_slice(&body)
+            .map_err(|e| (UpstreamFailure::Parse, ApiError::Upstream(e.to_string())))?;
+        Ok((body, date))
+    };
+    let echo = echo.await;
+    upstream.stats.record(echo.as_ref().err().map(|(failure, _)| *failure));
+    echo.map_err(|(_, e)| e)
+}
+
+/// validate data, post json to httpbin, get it back in the response body, return deserialized
+async fn step_x(
+    data: SomeData,
+    client: &Client,
+    upstream: &Upstream,
+) -> Result<SomeData, Error> {
+    Ok(traced_step_x(data, client, upstream).await?.0)
+}
+
+/// `step_x`, along with where its result came from
+async fn traced_step_x(
+    data: SomeData,
+    client: &Client,
+    upstream: &Upstream,
+) -> Result<(SomeData, Provenance), Error> {
+    // validate data
+    data.validate(upstream.graphemes).map_err(ApiError::Validation)?;
+
+    let key = match &upstream.cache {
+        Some(cache) => {
+            let key = serde_json::to_string(&data)?;
+            if let Some(hit) = cache.get(&key) {
+                return Ok((hit, Provenance { cached: true, ..Provenance::default() }));
+            }
+            Some((cache, key))
+        }
+        None => None,
+    };
+
+    let (echo, _) = upstream.backoff.run(|| post_once(&data, client, upstream)).await;
+    let (body, date) = echo?; // <- retried with backoff when STEP_RETRIES allows
+    if let Some((cache, key)) = key {
+        cache.insert(key, body.json.clone());
+    }
+    let provenance = Provenance { url: Some(body.url), origin: Some(body.origin), date, cached: false };
+    Ok((body.json, provenance))
+}
+
+/// ask httpbin for a path that always answers 404, to show how `step_x`-style
+/// code turns an upstream failure into a `502 upstream_error` rather than a
+/// confusing deserialization error
+async fn not_found_upstream(
+    req: HttpRequest,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+    let res = upstream
+        .request(&client, Method::GET, "/status/404")
+        .send()
+        .await
+        .map_err(|e| ApiError::Upstream(e.to_string()))?;
+    check_status(&res, "/status/404")?;
+
+    // only reached if httpbin stops honouring /status/404
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct ProxyQuery {
+    url: String,
+}
+
+/// the path (and query) `url` names on the httpbin at `base_url`: `url` is a
+/// path like `/get?a=1` or a full url under `base_url`, and either way must
+/// end up on the same scheme and host, so `/proxy` can't be pointed anywhere
+/// else
+fn httpbin_path(base_url: &str, url: &str) -> Result<String, ApiError> {
+    let refused = || ApiError::BadRequest(format!("url must be a path on {}, got {:?}", base_url, url));
+    let path = url.strip_prefix(base_url).unwrap_or(url);
+    if !path.starts_with('/') {
+        return Err(refused());
+    }
+    let target: Uri = format!("{}{}", base_url, path).parse().map_err(|_| refused())?;
+    let base: Uri = base_url.parse().map_err(|_| refused())?;
+    if target.scheme() != base.scheme() || target.authority() != base.authority() {
+        return Err(refused());
+    }
+    Ok(path.to_string())
+}
+
+/// `GET /proxy?url=/get`: GET a path on the configured httpbin and send back
+/// its status, content type and body, read the way `step_x` reads the echo.
+/// Urls off that httpbin are refused with `400`, as are bodies over
+/// `MAX_RESPONSE_BYTES` with `502`. Only exists with `DEBUG_ENDPOINTS`.
+async fn proxy(
+    req: HttpRequest,
+    query: web::Query<ProxyQuery>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !config.debug_endpoints {
+        return Err(ApiError::NotFound("debug endpoints are disabled".to_string()).into());
+    }
+    let path = httpbin_path(&config.httpbin_url, &query.url)?;
+    let upstream = Upstream::for_request(&req, &config)?;
+    let span = upstream.tracer.client_span(upstream.trace.as_ref(), &format!("GET {}", path));
+    let mut request = upstream.request(&client, Method::GET, &path);
+    if let Some(span) = &span {
+        request = request.header(trace::TRACEPARENT, span.context().traceparent());
+    }
+
+    let read = async {
+        let _permit = upstream.outbound.acquire().await?;
+        let mut res = request.send().await.map_err(|e| ApiError::Upstream(e.to_string()))?;
+        let mut body = BytesMut::new();
+        while let Some(chunk) = next_chunk(&mut res, upstream.chunk_timeout, &path).await {
+            body.extend_from_slice(&chunk?);
+            if body.len() > config.max_response_bytes {
+                return Err(ApiError::Upstream(format!(
+                    "httpbin's answer for {} is over the {} byte limit",
+                    path, config.max_response_bytes
+                )));
+            }
+        }
+        Ok((res.status(), res.headers().get(header::CONTENT_TYPE).cloned(), body.freeze()))
+    };
+    let read: Result<_, ApiError> = read.await;
+    if let Some(span) = span {
+        let url = format!("{}{}", upstream.base_url, path);
+        span.end(read.is_ok(), vec![("http.method", "GET".to_string()), ("http.url", url)]);
+    }
+    let (status, content_type, body) = read?;
+
+    let mut res = HttpResponse::build(status);
+    if let Some(content_type) = content_type {
+        res.header(header::CONTENT_TYPE, content_type);
+    }
+    Ok(res.body(body))
+}
+
+#[derive(Debug, Serialize)]
+struct FieldDiff {
+    sent: serde_json::Value,
+    received: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RoundTrip {
+    equal: bool,
+    diff: BTreeMap<String, FieldDiff>,
+}
+
+/// the fields whose values differ between two json objects
+fn field_diff(sent: serde_json::Value, received: serde_json::Value) -> BTreeMap<String, FieldDiff> {
+    let (sent, received) = match (sent, received) {
+        (serde_json::Value::Object(sent), serde_json::Value::Object(received)) => (sent, received),
+        _ => return BTreeMap::new(),
+    };
+    let names: BTreeSet<&String> = sent.keys().chain(received.keys()).collect();
+    names
+        .into_iter()
+        .filter(|name| sent.get(*name) != received.get(*name))
+        .map(|name| {
+            let field = |o: &serde_json::Map<String, serde_json::Value>| {
+                o.get(name).cloned().unwrap_or(serde_json::Value::Null)
+            };
+            (name.clone(), FieldDiff { sent: field(&sent), received: field(&received) })
+        })
+        .collect()
+}
+
+/// run one `step_x` past any cache and check that httpbin echoed the data
+/// back unchanged, serialized byte for byte the same, listing the fields that
+/// differ when it didn't
+async fn verify_something(
+    req: HttpRequest,
+    some_data: web::Json<SomeData>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream { cache: None, ..Upstream::for_request(&req, &config)? };
+    let sent = some_data.into_inner();
+    let received = step_x(sent.clone(), &client, &upstream).await?;
+
+    let equal = serde_json::to_vec(&sent)? == serde_json::to_vec(&received)?;
+    let diff =
+        if equal { BTreeMap::new() } else { field_diff(serde_json::to_value(&sent)?, serde_json::to_value(&received)?) };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&RoundTrip { equal, diff })?))
+}
+
+/// make the `step_x` post to httpbin once and answer with the headers httpbin
+/// says it got, i.e. what the client sent upstream: the `User-Agent`, the
+/// forwarded headers, and no credentials unless they're allowed through
+async fn sent_headers(
+    req: HttpRequest,
+    some_data: web::Json<SomeData>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+    some_data.validate(config.grapheme_length).map_err(ApiError::Validation)?;
+    let (echo, _) = post_once(&some_data, &client, &upstream).await?;
+    let headers: BTreeMap<String, String> = echo.headers.into_iter().collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&headers)?))
+}
+
+/// `requested` steps, unless that's none or over `MAX_CHAIN_STEPS`; checked
+/// where the chain starts, whatever the request asked for, so no caller can
+/// make it run unbounded httpbin calls
+fn chain_steps(requested: usize, config: &Config) -> Result<usize, ApiError> {
+    if requested == 0 || requested > config.max_chain_steps {
+        return Err(ApiError::BadRequest(format!(
+            "the chain runs between 1 and {} steps, not {}",
+            config.max_chain_steps, requested
+        )));
+    }
+    Ok(requested)
+}
+
+/// how `chain_progress` puts an event (`step`, `error` or `done`) and its json
+/// payload on the wire
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    /// newline-delimited json, for `?stream=true`
+    Ndjson,
+    /// server-sent events, for `/something/sse`
+    Sse,
+    /// a websocket text message of `{"event":...,"data":...}`, for
+    /// `/ws/something`
+    WebSocket,
+}
+
+impl Framing {
+    fn frame<T: Serialize>(self, event: &str, value: &T) -> Result<Bytes, Error> {
+        let json = serde_json::to_string(value)?;
+        Ok(Bytes::from(match self {
+            Framing::Ndjson => format!("{}\n", json),
+            Framing::Sse => format!("event: {}\ndata: {}\n\n", event, json),
+            Framing::WebSocket => {
+                let event = serde_json::to_string(event)?;
+                let message = format!("{{\"event\":{},\"data\":{}}}", event, json);
+                return ws::encode(ws::Message::Text(message));
+            }
+        }))
+    }
+}
+
+/// run the same chain as `create_something`, `steps` steps long, yielding an
+/// event per step and a final one carrying the overall result. A failing step
+/// ends the stream with an error event, since the response status has already
+/// been sent.
+fn chain_progress(
+    data: SomeData,
+    steps: usize,
+    client: web::Data<Client>,
+    upstream: Upstream,
+    framing: Framing,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    let steps = upstream.steps(steps);
+    stream::unfold(Some((1, data)), move |state| {
+        let client = client.clone();
+        let upstream = upstream.clone();
+        async move {
+            let (step, data) = state?;
+            if step > steps {
+                return Some((framing.frame("done", &ChainResult { result: &data }), None));
+            }
+            match step_x(data, &client, &upstream).await {
+                Ok(d) => {
+                    let line = framing.frame("step", &StepProgress { step, result: &d });
+                    Some((line, Some((step + 1, d))))
+                }
+                Err(e) => {
+                    let line = framing.frame("error", &StepFailure { step, error: e.to_string() });
+                    Some((line, None))
+                }
+            }
+        }
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct MessageError {
+    error: String,
+}
+
+/// answer each `SomeData` text message with the chain's progress events, the
+/// same ones `/something/sse` sends, until the client closes or the session
+/// has lasted `WS_SESSION_MAX_SECS`. A message that isn't a `SomeData` gets an
+/// error event and the session carries on.
+async fn ws_session(
+    frames: impl Stream<Item = Result<ws::Frame, ws::ProtocolError>>,
+    out: mpsc::UnboundedSender<Result<Bytes, Error>>,
+    client: web::Data<Client>,
+    upstream: Upstream,
+    lifetime: Duration,
+) {
+    let send = |frame: Result<Bytes, Error>| out.unbounded_send(frame).is_ok();
+    let close = |code, description: &str| {
+        let reason = ws::CloseReason { code, description: Some(description.to_string()) };
+        send(ws::encode(ws::Message::Close(Some(reason))));
+    };
+    let session = async {
+        futures::pin_mut!(frames);
+        while let Some(frame) = frames.next().await {
+            match frame {
+                Ok(ws::Frame::Text(text)) => match serde_json::from_slice::<SomeData>(&text) {
+                    Ok(data) => {
+                        let framing = Framing::WebSocket;
+                        let progress =
+                            chain_progress(data, CHAIN_STEPS, client.clone(), upstream.clone(), framing);
+                        let mut progress = Box::pin(progress);
+                        while let Some(event) = progress.next().await {
+                            if !send(event) {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let error = MessageError { error: format!("not a SomeData: {}", e) };
+                        if !send(Framing::WebSocket.frame("error", &error)) {
+                            return;
+                        }
+                    }
+                },
+                Ok(ws::Frame::Binary(_)) | Ok(ws::Frame::Continuation(_)) => {
+                    let error = MessageError { error: "send SomeData as a text message".into() };
+                    if !send(Framing::WebSocket.frame("error", &error)) {
+                        return;
+                    }
+                }
+                Ok(ws::Frame::Ping(payload)) => {
+                    send(ws::encode(ws::Message::Pong(payload)));
+                }
+                Ok(ws::Frame::Pong(_)) => {}
+                Ok(ws::Frame::Close(reason)) => {
+                    send(ws::encode(ws::Message::Close(reason)));
+                    return;
+                }
+                Err(e) => {
+                    close(ws::CloseCode::Protocol, &e.to_string());
+                    return;
+                }
+            }
+        }
+    };
+    if actix_rt::time::timeout(lifetime, session).await.is_err() {
+        close(ws::CloseCode::Normal, "session lifetime exceeded");
+    }
+}
+
+/// `/ws/something`: the chain over a websocket, see `ws_session`
+async fn ws_something(
+    req: HttpRequest,
+    payload: web::Payload,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+    let mut res = ws::handshake(&req)?;
+    let (out, frames) = mpsc::unbounded();
+    actix_rt::spawn(ws_session(ws::frames(payload), out, client, upstream, config.ws_session_max));
+
+    Ok(res.streaming(frames))
+}
+
+async fn create_something(
+    req: HttpRequest,
+    some_data: Utf8Json<SomeData>,
+    query: web::Query<ChainQuery>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    dedup: web::Data<ChainFlights>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+    let step_count = chain_steps(query.steps.unwrap_or(CHAIN_STEPS), &config)?;
+
+    if query.stream && query.trace {
+        return Err(ApiError::BadRequest("stream and trace can't be combined".to_string()).into());
+    }
+    if query.stream {
+        // validate up front so bad input still gets a 400 instead of a 200 stream
+        some_data.validate(config.grapheme_length).map_err(ApiError::Validation)?;
+        let progress = chain_progress(some_data.into_inner(), step_count, client, upstream, Framing::Ndjson);
+        return Ok(HttpResponse::Ok().content_type("application/x-ndjson").streaming(progress));
+    }
+
+    let body = serde_json::to_vec(&*some_data)?;
+    let deadline = config.create_timeout.map(|scaled| scaled.for_size(body.len()));
+    let chain = async {
+        let mut steps: Vec<StepRecord> = Vec::with_capacity(step_count);
+        let mut d = some_data.into_inner();
+        for step in 1..=upstream.steps(step_count) {
+            let (result, provenance) = traced_step_x(d, &client, &upstream).await?;
+            d = between_steps(result.clone());
+            steps.push(StepRecord { step, result, provenance });
+        }
+        Ok::<_, Error>(steps)
+    };
+    let chain = async {
+        match deadline {
+            Some(limit) => actix_rt::time::timeout(limit, chain).await.unwrap_or_else(|_| {
+                Err(ApiError::Timeout(format!("the chain did not finish within {}ms", limit.as_millis()))
+                    .into())
+            }),
+            None => chain.await,
+        }
+        .map_err(|e| e.as_error::<ApiError>().cloned().unwrap_or_else(|| ApiError::Internal(e.to_string())))
+    };
+    let mut steps = if dedup.enabled() {
+        // identical bodies only share a result when they'd reach httpbin the
+        // same way
+        let key = (body, upstream.forwarded.clone(), upstream.cache.is_some(), step_count);
+        let (steps, shared) = dedup.run(key, chain).await;
+        if shared {
+            log::debug!("answered with the result of an identical request in flight");
+        }
+        steps?
+    } else {
+        chain.await?
+    };
+    if query.trace {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&steps)?));
+    }
+    let d = steps.pop().map(|step| step.result);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&d).unwrap()))
+}
+
+/// the `create_something` chain as server-sent events, for watching it from a
+/// browser: `event: step` after each step and `event: done` with the result.
+/// The input comes from the query string since `EventSource` can only GET.
+async fn sse_something(
+    req: HttpRequest,
+    some_data: web::Query<SomeData>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+    some_data.validate(config.grapheme_length).map_err(ApiError::Validation)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .streaming(chain_progress(some_data.into_inner(), CHAIN_STEPS, client, upstream, Framing::Sse)))
+}
+
+#[derive(Debug, Serialize)]
+struct ChainTrace {
+    result: SomeData,
+    steps_ms: Vec<u128>,
+}
+
+/// run the `create_something` chain, reporting how long each step took
+async fn trace_something(
+    req: HttpRequest,
+    some_data: web::Json<SomeData>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+
+    let mut d = some_data.into_inner();
+    let mut steps_ms = Vec::with_capacity(CHAIN_STEPS);
+    for _ in 0..CHAIN_STEPS {
+        let started = Instant::now();
+        d = step_x(d, &client, &upstream).await?;
+        steps_ms.push(started.elapsed().as_millis());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&ChainTrace { result: d, steps_ms })?))
+}
+
+/// the three `step_x` steps of `create_something`, for callers running it more
+/// than once
+async fn run_chain(
+    data: SomeData,
+    client: &Client,
+    upstream: &Upstream,
+) -> Result<SomeData, Error> {
+    let mut d = data;
+    for _ in 0..CHAIN_STEPS {
+        d = step_x(d, client, upstream).await?;
+    }
+    Ok(d)
+}
+
+/// the outcome for one item of `/something/batch`
+#[derive(Debug, Serialize)]
+struct BatchItem {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<SomeData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// run the chain for every item, at most `concurrency` at a time, except that
+/// the items at `fail_indices` fail without running. Results come back in
+/// input order, and a failing item is reported in place without aborting the
+/// others.
+async fn run_batch(
+    items: Vec<SomeData>,
+    client: &Client,
+    upstream: &Upstream,
+    concurrency: usize,
+    fail_indices: &BTreeSet<usize>,
+) -> Vec<BatchItem> {
+    stream::iter(items.into_iter().enumerate())
+        .map(|(index, data)| async move {
+            if fail_indices.contains(&index) {
+                let error = format!("item {} was made to fail by fail_indices", index);
+                return BatchItem { index, result: None, error: Some(error) };
+            }
+            match run_chain(data, client, upstream).await {
+                Ok(d) => BatchItem { index, result: Some(d), error: None },
+                Err(e) => BatchItem { index, result: None, error: Some(e.to_string()) },
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await
+}
+
+/// run the chain for every item, at most `BATCH_CONCURRENCY` at a time so a
+/// big batch doesn't flood httpbin
+async fn batch_something(
+    req: HttpRequest,
+    items: web::Json<Vec<SomeData>>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+    let res =
+        run_batch(items.into_inner(), &client, &upstream, config.batch_concurrency, &BTreeSet::new()).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct FailQuery {
+    /// comma-separated indices of the items to fail
+    #[serde(default)]
+    fail_indices: String,
+}
+
+/// `/something/batch` with the items at `?fail_indices=1,3` failing instead of
+/// running, for checking how a client handles a batch that partly failed
+async fn batch_partial_failure(
+    req: HttpRequest,
+    items: web::Json<Vec<SomeData>>,
+    query: web::Query<FailQuery>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !config.debug_endpoints {
+        return Err(ApiError::NotFound("debug endpoints are disabled".to_string()).into());
+    }
+    let fail_indices = query
+        .fail_indices
+        .split(',')
+        .map(str::trim)
+        .filter(|index| !index.is_empty())
+        .map(|index| {
+            index.parse().map_err(|_| {
+                ApiError::BadRequest(format!("fail_indices must be item indices, got {:?}", index))
+            })
+        })
+        .collect::<Result<BTreeSet<usize>, _>>()?;
+    let upstream = Upstream::for_request(&req, &config)?;
+    let res = run_batch(items.into_inner(), &client, &upstream, config.batch_concurrency, &fail_indices).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the most iterations `/debug/bench/chain` runs
+const BENCH_CHAIN_MAX: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct ChainBenchQuery {
+    iterations: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChainBench {
+    iterations: usize,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    total_ms: f64,
+    /// chains finished per second
+    throughput: f64,
+}
+
+/// run the `create_something` chain `?iterations=` times (100 by default, at
+/// most `BENCH_CHAIN_MAX`) one after another on a fixed input, and report the
+/// latency percentiles and throughput. Stops at the first chain that fails.
+/// Only exists with `DEBUG_ENDPOINTS`.
+async fn bench_chain(
+    req: HttpRequest,
+    query: web::Query<ChainBenchQuery>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !config.debug_endpoints {
+        return Err(ApiError::NotFound("debug endpoints are disabled".to_string()).into());
+    }
+    let iterations = query.iterations.unwrap_or(100);
+    if iterations == 0 || iterations > BENCH_CHAIN_MAX {
+        let message = format!("iterations must be between 1 and {}", BENCH_CHAIN_MAX);
+        return Err(ApiError::BadRequest(message).into());
+    }
+    let upstream = Upstream::for_request(&req, &config)?;
+    let input = SomeData { id: "bench".to_string(), name: "bench".to_string() };
+
+    let started = Instant::now();
+    let mut latencies = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let chain_started = Instant::now();
+        run_chain(input.clone(), &client, &upstream).await?;
+        latencies.push(chain_started.elapsed());
+    }
+    let total = started.elapsed();
+    latencies.sort_unstable();
+    let res = ChainBench {
+        iterations,
+        p50_ms: percentile_ms(&latencies, 50),
+        p95_ms: percentile_ms(&latencies, 95),
+        p99_ms: percentile_ms(&latencies, 99),
+        total_ms: total.as_secs_f64() * 1000.0,
+        throughput: iterations as f64 / total.as_secs_f64(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// the transform `/something/reversed` applies between steps: `name` reversed
+/// character by character
+fn reverse_name(data: SomeData) -> SomeData {
+    SomeData { name: data.name.chars().rev().collect(), ..data }
+}
+
+/// the `create_something` chain with `reverse_name` applied between steps, for
+/// comparing against the plain chain
+async fn reversed_something(
+    req: HttpRequest,
+    some_data: web::Json<SomeData>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let upstream = Upstream::for_request(&req, &config)?;
+
+    let mut d = some_data.into_inner();
+    for step in 0..CHAIN_STEPS {
+        if step > 0 {
+            d = reverse_name(d);
+        }
+        d = step_x(d, &client, &upstream).await?;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&d)?))
+}
+
+/// `User-Agent` sent on outbound requests: the crate name and version, unless
+/// overridden with `USER_AGENT`
+pub fn user_agent() -> String {
+    env::var("USER_AGENT").unwrap_or_else(|_| {
+        format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+    })
+}
+
+/// a worker's client for httpbin, sending `user_agent` and giving up on a
+/// request after `timeout`
+pub fn client(user_agent: &str, timeout: Duration) -> Client {
+    Client::build().header(header::USER_AGENT, user_agent).timeout(timeout).finish()
+}
+
+/// the endpoints this module adds to the app's route list
+pub fn routes() -> Vec<Endpoint> {
+    vec![
+        Endpoint::new("/something").route(Method::POST, create_something),
+        Endpoint::new("/something/batch").route(Method::POST, batch_something),
+        Endpoint::new("/something/sse").route(Method::GET, sse_something),
+        Endpoint::new("/ws/something").route(Method::GET, ws_something),
+        Endpoint::new("/something/sent-headers").route(Method::POST, sent_headers),
+        Endpoint::new("/something/verify").route(Method::POST, verify_something),
+        Endpoint::new("/something/trace").route(Method::POST, trace_something),
+        Endpoint::new("/something/reversed").route(Method::POST, reversed_something),
+        Endpoint::new("/something/not-found-upstream").route(Method::GET, not_found_upstream),
+        Endpoint::new("/proxy").route(Method::GET, proxy),
+        Endpoint::new("/debug/batch").route(Method::POST, batch_partial_failure),
+        Endpoint::new("/debug/bench/chain").route(Method::GET, bench_chain),
+        Endpoint::new("/admin/upstream-stats").route(Method::GET, metrics::upstream_stats),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use actix_web::test::{self, TestRequest, TestServer};
+    use actix_web::{App, HttpResponse, ResponseError};
+    use futures::future::ready;
+    use serde_json::json;
+
+    use super::*;
+
+    /// an `Upstream` for a request without headers, talking to `srv`
+    fn upstream(srv: &TestServer) -> Upstream {
+        upstream_with(srv, false, &[]).unwrap()
+    }
+
+    /// an `Upstream` for a request with `headers`, talking to `srv`, with
+    /// `DEBUG_ENDPOINTS` as `debug_endpoints` says
+    fn upstream_with(
+        srv: &TestServer,
+        debug_endpoints: bool,
+        headers: &[(&str, &str)],
+    ) -> Result<Upstream, ApiError> {
+        let mut config = httpbin_config(srv);
+        config.debug_endpoints = debug_endpoints;
+        for_request(&config, headers)
+    }
+
+    /// the configuration the environment gives, talking to `srv`
+    fn httpbin_config(srv: &TestServer) -> Config {
+        let mut config = Config::from_env();
+        config.httpbin_url = srv.url("").trim_end_matches('/').to_string();
+        config.offline_mode = false;
+        config
+    }
+
+    /// the `Upstream` of a request with `headers` under `config`
+    fn for_request(config: &Config, headers: &[(&str, &str)]) -> Result<Upstream, ApiError> {
+        let req = headers.iter().fold(TestRequest::default(), |req, (name, value)| req.header(*name, *value));
+        Upstream::for_request(&req.to_http_request(), config)
+    }
+
+    /// an httpbin whose `/post` echoes `SomeData` along with the headers it
+    /// was sent, as httpbin does
+    fn headers_server() -> TestServer {
+        test::start(|| {
+            App::new().route(
+                "/post",
+                web::post().to(|req: HttpRequest, data: web::Json<SomeData>| {
+                    let headers = req
+                        .headers()
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+                        .collect::<HashMap<_, _>>();
+                    ready(HttpResponse::Ok().json(json!({
+                        "args": {}, "data": "", "files": {}, "form": {}, "headers": headers,
+                        "json": data.into_inner(), "origin": "127.0.0.1", "url": "/post",
+                    })))
+                }),
+            )
+        })
+    }
+
+    /// the headers `headers_server` says a `step_x` post by `client` under
+    /// `upstream` sent
+    async fn sent_by(client: &Client, upstream: &Upstream) -> HashMap<String, String> {
+        let data = SomeData { id: "1".to_string(), name: "鹿島".to_string() };
+        post_once(&data, client, upstream).await.unwrap().0.headers
+    }
+
+    async fn sent(upstream: &Upstream) -> HashMap<String, String> {
+        sent_by(&Client::new(), upstream).await
+    }
+
+    const CREDENTIALS: &[(&str, &str)] = &[
+        ("Authorization", "Bearer secret"),
+        ("Proxy-Authorization", "Basic c2VjcmV0"),
+        ("Cookie", "session=secret"),
+        ("X-API-Key", "secret"),
+        ("X-Team", "KAS"),
+    ];
+
+    #[actix_rt::test]
+    async fn no_inbound_header_reaches_httpbin_by_default() {
+        let srv = headers_server();
+        let mut config = httpbin_config(&srv);
+        config.forward_headers = Vec::new();
+        let headers = sent(&for_request(&config, CREDENTIALS).unwrap()).await;
+        for (name, _) in CREDENTIALS {
+            assert!(!headers.contains_key(&name.to_lowercase()), "{} was forwarded: {:?}", name, headers);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn credentials_are_left_out_of_forward_headers_unless_allowed() {
+        let srv = headers_server();
+        let mut config = httpbin_config(&srv);
+        let list = "authorization, Proxy-Authorization, cookie, x-team";
+        config.forward_headers = crate::config::allowed_forward_headers(list, false);
+        let headers = sent(&for_request(&config, CREDENTIALS).unwrap()).await;
+        assert_eq!(headers.get("x-team").map(String::as_str), Some("KAS"));
+        for name in ["authorization", "proxy-authorization", "cookie", "x-api-key"] {
+            assert!(!headers.contains_key(name), "{} was forwarded: {:?}", name, headers);
+        }
+
+        config.forward_headers = crate::config::allowed_forward_headers(list, true);
+        let headers = sent(&for_request(&config, CREDENTIALS).unwrap()).await;
+        assert_eq!(headers.get("authorization").map(String::as_str), Some("Bearer secret"));
+        assert_eq!(headers.get("cookie").map(String::as_str), Some("session=secret"));
+        assert!(!headers.contains_key("x-api-key"));
+    }
+
+    #[actix_rt::test]
+    async fn httpbin_is_sent_the_user_agent() {
+        let srv = headers_server();
+        let upstream = upstream(&srv);
+        let default = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        if env::var("USER_AGENT").is_err() {
+            assert_eq!(user_agent(), default);
+        }
+        for agent in [default.as_str(), "team-bot/1.0 (+https://example.com)"] {
+            let headers = sent_by(&client(agent, Duration::from_secs(5)), &upstream).await;
+            assert_eq!(headers.get("user-agent").map(String::as_str), Some(agent));
+        }
+    }
+
+    /// the most `/post` calls `echo_server` has had in flight at once
+    #[derive(Clone, Default)]
+    struct InFlight {
+        now: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    }
+
+    /// an httpbin whose `/post` echoes `SomeData` after `10ms` times however
+    /// far from `last_id` its id is, so the later items of a batch answer first
+    fn echo_server(last_id: u64, in_flight: InFlight) -> TestServer {
+        test::start(move || {
+            let in_flight = in_flight.clone();
+            App::new().route(
+                "/post",
+                web::post().to(move |data: web::Json<SomeData>| {
+                    let in_flight = in_flight.clone();
+                    async move {
+                        let now = in_flight.now.fetch_add(1, Ordering::SeqCst) + 1;
+                        in_flight.most.fetch_max(now, Ordering::SeqCst);
+                        let wait = last_id.saturating_sub(data.id.parse().unwrap_or(0)) * 10;
+                        actix_rt::time::delay_for(Duration::from_millis(wait)).await;
+                        in_flight.now.fetch_sub(1, Ordering::SeqCst);
+                        Ok::<_, Error>(HttpResponse::Ok().json(json!({
+                            "args": {}, "data": "", "files": {}, "form": {}, "headers": {},
+                            "json": data.into_inner(), "origin": "127.0.0.1", "url": "/post",
+                        })))
+                    }
+                }),
+            )
+        })
+    }
+
+    fn items(count: u64) -> Vec<SomeData> {
+        (0..count).map(|id| SomeData { id: id.to_string(), name: format!("item {}", id) }).collect()
+    }
+
+    #[actix_rt::test]
+    async fn batch_results_come_back_in_input_order() {
+        let srv = echo_server(5, InFlight::default());
+        let results = run_batch(items(6), &Client::new(), &upstream(&srv), 6, &BTreeSet::new()).await;
+        let ids = results.iter().map(|item| item.result.as_ref().unwrap().id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["0", "1", "2", "3", "4", "5"]);
+        assert!(results.iter().enumerate().all(|(i, item)| item.index == i && item.error.is_none()));
+    }
+
+    #[actix_rt::test]
+    async fn batches_run_at_most_concurrency_items_at_once() {
+        for concurrency in [1, 2, 3] {
+            let in_flight = InFlight::default();
+            let srv = echo_server(5, in_flight.clone());
+            let upstream = upstream(&srv);
+            let results = run_batch(items(6), &Client::new(), &upstream, concurrency, &BTreeSet::new()).await;
+            assert_eq!(results.len(), 6);
+            assert_eq!(in_flight.most.load(Ordering::SeqCst), concurrency);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn failing_items_are_reported_in_place() {
+        let srv = echo_server(3, InFlight::default());
+        let fail = [1, 3].iter().copied().collect();
+        let results = run_batch(items(4), &Client::new(), &upstream(&srv), 2, &fail).await;
+        let outcomes = results.iter().map(|item| (item.index, item.result.is_some())).collect::<Vec<_>>();
+        assert_eq!(outcomes, [(0, true), (1, false), (2, true), (3, false)]);
+        assert!(results[1].error.as_ref().unwrap().contains("fail_indices"));
+    }
+
+    /// check `/status/429` on an httpbin that rate limits with `retry_after`
+    async fn rate_limited(retry_after: Option<&'static str>) -> Result<(), ApiError> {
+        let srv = test::start(move || {
+            App::new().route(
+                "/status/429",
+                web::get().to(move || {
+                    let mut res = HttpResponse::TooManyRequests();
+                    if let Some(retry_after) = retry_after {
+                        res.header(header::RETRY_AFTER, retry_after);
+                    }
+                    res.finish()
+                }),
+            )
+        });
+        let res = Client::new().get(srv.url("/status/429")).send().await.unwrap();
+        check_status(&res, "/status/429")
+    }
+
+    fn retry_after(e: &ApiError) -> Option<String> {
+        let res = e.error_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        res.headers().get(header::RETRY_AFTER).map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[actix_rt::test]
+    async fn a_retry_after_in_seconds_is_passed_on() {
+        let e = rate_limited(Some("120")).await.unwrap_err();
+        assert_eq!(e.code(), "too_many_requests");
+        assert_eq!(retry_after(&e).as_deref(), Some("120"));
+    }
+
+    #[actix_rt::test]
+    async fn a_retry_after_date_is_passed_on() {
+        let date = "Wed, 21 Oct 2026 07:28:00 GMT";
+        let e = rate_limited(Some(date)).await.unwrap_err();
+        assert_eq!(e.code(), "too_many_requests");
+        assert_eq!(retry_after(&e).as_deref(), Some(date));
+    }
+
+    #[actix_rt::test]
+    async fn a_429_without_retry_after_sends_none() {
+        let e = rate_limited(None).await.unwrap_err();
+        assert_eq!(e.code(), "too_many_requests");
+        assert_eq!(retry_after(&e), None);
+    }
+
+    #[actix_rt::test]
+    async fn other_failures_are_upstream_errors() {
+        let srv =
+            test::start(|| App::new().route("/status/503", web::get().to(HttpResponse::ServiceUnavailable)));
+        let res = Client::new().get(srv.url("/status/503")).send().await.unwrap();
+        let e = check_status(&res, "/status/503").unwrap_err();
+        assert_eq!(e.code(), "upstream_error");
+        assert_eq!(e.error_response().headers().get(header::RETRY_AFTER), None);
+
+        let srv = test::start(|| App::new().route("/get", web::get().to(HttpResponse::Ok)));
+        let res = Client::new().get(srv.url("/get")).send().await.unwrap();
+        assert!(check_status(&res, "/get").is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn debug_headers_take_the_upstream_offline_only_in_debug_mode() {
+        let srv = echo_server(0, InFlight::default());
+        let offline = [("X-Debug-Offline", "true")];
+        assert_eq!(upstream_with(&srv, true, &offline).err().unwrap().code(), "unavailable");
+
+        let upstream = upstream_with(&srv, false, &offline).unwrap();
+        let data = SomeData { id: "0".to_string(), name: "鹿島".to_string() };
+        assert_eq!(step_x(data, &Client::new(), &upstream).await.unwrap().id, "0");
+    }
+
+    #[actix_rt::test]
+    async fn debug_headers_shorten_the_client_timeout_only_in_debug_mode() {
+        // item 0 is answered after 300ms
+        let srv = echo_server(30, InFlight::default());
+        let timeout = [("X-Debug-Timeout-Ms", "20")];
+        let data = SomeData { id: "0".to_string(), name: "鹿島".to_string() };
+
+        let upstream = upstream_with(&srv, true, &timeout).unwrap();
+        let e = step_x(data.clone(), &Client::new(), &upstream).await.unwrap_err();
+        assert_eq!(e.as_error::<ApiError>().unwrap().code(), "upstream_error");
+
+        let upstream = upstream_with(&srv, false, &timeout).unwrap();
+        assert!(step_x(data, &Client::new(), &upstream).await.is_ok());
+    }
+
+    /// an httpbin whose `/post` echoes `SomeData` in `pieces` chunks, `gap`
+    /// apart
+    fn trickling_server(pieces: usize, gap: Duration) -> TestServer {
+        test::start(move || {
+            App::new().route(
+                "/post",
+                web::post().to(move |data: web::Json<SomeData>| {
+                    let echo = json!({
+                        "args": {}, "data": "", "files": {}, "form": {}, "headers": {},
+                        "json": data.into_inner(), "origin": "127.0.0.1", "url": "/post",
+                    });
+                    let echo = Bytes::from(echo.to_string());
+                    let (len, size) = (echo.len(), echo.len() / pieces + 1);
+                    let chunks = (0..len).step_by(size).map(|at| echo.slice(at..len.min(at + size)));
+                    let body = stream::iter(chunks.collect::<Vec<_>>()).then(move |chunk| async move {
+                        actix_rt::time::delay_for(gap).await;
+                        Ok::<_, Error>(chunk)
+                    });
+                    ready(HttpResponse::Ok().content_type("application/json").streaming(body))
+                }),
+            )
+        })
+    }
+
+    #[actix_rt::test]
+    async fn a_body_stalling_past_the_chunk_timeout_is_a_504() {
+        let srv = trickling_server(3, Duration::from_millis(300));
+        let mut upstream = upstream(&srv);
+        upstream.chunk_timeout = Duration::from_millis(50);
+        let data = SomeData { id: "1".to_string(), name: "鹿島".to_string() };
+        let e = step_x(data, &Client::new(), &upstream).await.unwrap_err();
+        let e = e.as_error::<ApiError>().unwrap();
+        assert_eq!(e.code(), "timeout");
+        assert_eq!(e.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(e.to_string().contains("sent nothing more of /post for 50ms"), "{}", e);
+    }
+
+    #[actix_rt::test]
+    async fn a_body_trickling_in_within_the_chunk_timeout_is_read() {
+        // 10 chunks 30ms apart take longer than the window in all, but each
+        // comes within it
+        let srv = trickling_server(10, Duration::from_millis(30));
+        let mut upstream = upstream(&srv);
+        upstream.chunk_timeout = Duration::from_millis(200);
+        let data = SomeData { id: "1".to_string(), name: "鹿島".to_string() };
+        assert_eq!(step_x(data, &Client::new(), &upstream).await.unwrap().name, "鹿島");
+    }
+
+    #[actix_rt::test]
+    async fn create_something_answers_over_h2c() {
+        let httpbin = echo_server(1, InFlight::default());
+        let config = web::Data::new(httpbin_config(&httpbin));
+        let flights = web::Data::new(ChainFlights::new(Duration::from_secs(0)));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = move || {
+            App::new()
+                .data(Client::new())
+                .app_data(config.clone())
+                .app_data(flights.clone())
+                .route("/something", web::post().to(create_something))
+        };
+        let server = crate::tls::h2c_server(app, listener, 1).unwrap();
+
+        // a client that speaks HTTP/2 from the first byte, as
+        // `curl --http2-prior-knowledge` does
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (h2, connection) = h2::client::handshake(tcp).await.unwrap();
+        actix_rt::spawn(async move {
+            let _ = connection.await;
+        });
+        let req = http::Request::post(format!("http://{}/something", addr))
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap();
+        let (res, mut send) = h2.ready().await.unwrap().send_request(req, false).unwrap();
+        send.send_data(Bytes::from_static(br#"{"id":"1","name":"h2c"}"#), true).unwrap();
+        let res = res.await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.version(), http::Version::HTTP_2);
+        let mut body = res.into_body();
+        let mut read = Vec::new();
+        while let Some(chunk) = body.data().await {
+            read.extend_from_slice(&chunk.unwrap());
+        }
+        let data: SomeData = serde_json::from_slice(&read).unwrap();
+        assert_eq!(data.id, "1");
+        server.stop(false).await;
+    }
+
+    #[test]
+    fn names_are_reversed_by_character() {
+        let reversed = |name: &str| reverse_name(SomeData { id: "7".to_string(), name: name.to_string() });
+        assert_eq!(reversed("JohnDoe").name, "eoDnhoJ");
+        assert_eq!(reversed("鹿島アントラーズ").name, "ズーラトンア島鹿");
+        // by `char`, not grapheme: a combining accent moves with the reversal
+        assert_eq!(reversed("e\u{301}x").name, "x\u{301}e");
+        assert_eq!(reversed("").name, "");
+        assert_eq!(reversed("浦和").id, "7");
+        assert_eq!(reverse_name(reversed("川崎フロンターレ")).name, "川崎フロンターレ");
+    }
+
+    #[actix_rt::test]
+    async fn chains_stop_at_max_chain_steps_whatever_they_are_asked_for() {
+        let srv = echo_server(1, InFlight::default());
+        let mut upstream = upstream(&srv);
+        upstream.max_steps = 2;
+        let data = SomeData { id: "1".to_string(), name: "鹿島".to_string() };
+        let progress = chain_progress(data, 50, web::Data::new(Client::new()), upstream, Framing::Ndjson);
+        let lines = progress.collect::<Vec<_>>().await;
+        let events = lines
+            .into_iter()
+            .map(|line| serde_json::from_slice::<serde_json::Value>(&line.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        let steps = events.iter().filter_map(|event| event["step"].as_u64()).collect::<Vec<_>>();
+        assert_eq!(steps, [1, 2]);
+        assert!(events.last().unwrap().get("result").is_some());
+    }
+}
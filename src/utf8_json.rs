@@ -0,0 +1,108 @@
+use std::ops::Deref;
+
+use actix_web::dev::Payload;
+use actix_web::error::JsonPayloadError;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+
+use crate::error::{self, ApiError};
+
+/// a json body like `web::Json`, except that bytes that aren't UTF-8 are told
+/// apart from malformed json: they're a `400` saying the body is not valid
+/// UTF-8 before any parsing is attempted
+#[derive(Debug)]
+pub struct Utf8Json<T>(pub T);
+
+impl<T> Utf8Json<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Utf8Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Utf8Json<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = req.mime_type().ok().flatten().is_some_and(|mime| {
+            mime.subtype().as_str() == "json" || mime.suffix().is_some_and(|suffix| suffix.as_str() == "json")
+        });
+        let req = req.clone();
+        let body = web::Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            if !json {
+                return Err(error::json_error(JsonPayloadError::ContentType, &req));
+            }
+            let body = body.await?;
+            if std::str::from_utf8(&body).is_err() {
+                return Err(ApiError::BadRequest("request body is not valid UTF-8".to_string()).into());
+            }
+            serde_json::from_slice(&body)
+                .map(Utf8Json)
+                .map_err(|e| error::json_error(JsonPayloadError::Deserialize(e), &req))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{App, HttpResponse};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_support::{call, json};
+
+    /// what an echo of `Utf8Json<Value>` answers `body` with
+    async fn send(content_type: &str, body: &'static [u8]) -> (StatusCode, Value) {
+        let echo = |body: Utf8Json<Value>| HttpResponse::Ok().json(body.into_inner());
+        let mut app = test::init_service(App::new().route("/", web::post().to(echo))).await;
+        let req = TestRequest::post().uri("/").header(header::CONTENT_TYPE, content_type).set_payload(body);
+        let (status, body) = call(&mut app, req.to_request()).await;
+        (status, json(&body))
+    }
+
+    #[actix_rt::test]
+    async fn invalid_utf8_is_its_own_400() {
+        let invalid: [&'static [u8]; 5] = [
+            b"{\"name\":\"\xff\"}",
+            b"{\"name\":\"\xc0\xaf\"}",
+            b"{\"name\":\"\xe3\x81\"}",
+            b"{\"name\":\"\xed\xa0\x80\"}",
+            b"\x80{}",
+        ];
+        for body in invalid {
+            let (status, body) = send("application/json", body).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["code"], "bad_request");
+            assert_eq!(body["error"], "request body is not valid UTF-8");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn malformed_json_is_told_apart() {
+        let (status, body) = send("application/json", b"{\"name\":").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "bad_request");
+        assert_ne!(body["error"], "request body is not valid UTF-8");
+    }
+
+    #[actix_rt::test]
+    async fn utf8_json_is_parsed() {
+        let (status, body) = send("application/json", "{\"name\":\"鹿島\"}".as_bytes()).await;
+        assert_eq!((status, body), (StatusCode::OK, json!({"name": "鹿島"})));
+        let (status, _) = send("application/merge-patch+json", b"{}").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
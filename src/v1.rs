@@ -0,0 +1,106 @@
+//! `/api/v1`: the same teams as `/api/v0`, in the v1 representation, where a
+//! team's area is an object (`"area":{"name":...}`) rather than a flat
+//! `active_area`, leaving room for more about it. `Team` and `TeamStore` are
+//! shared by both versions; each version only decides how a team is written
+//! out, `/api/v0` by `Team`'s own serialization and `/api/v1` through
+//! `TeamV1`, so the two don't drift apart on what a team is.
+
+use actix_web::{web, Error, HttpResponse};
+use serde::Serialize;
+
+use crate::teams::{Team, TeamStore};
+
+#[derive(Debug, Serialize)]
+struct Area<'a> {
+    name: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct TeamV1<'a> {
+    team_abbreviation: &'a str,
+    area: Area<'a>,
+    join_year: u32,
+    division: &'a str,
+    labels: &'a [String],
+}
+
+impl<'a> From<&'a Team> for TeamV1<'a> {
+    fn from(team: &'a Team) -> Self {
+        TeamV1 {
+            team_abbreviation: team.abbreviation(),
+            area: Area { name: team.active_area() },
+            join_year: team.join_year(),
+            division: team.division(),
+            labels: team.labels(),
+        }
+    }
+}
+
+/// `GET /api/v1/teams`: every team
+pub async fn all_teams(store: web::Data<TeamStore>) -> Result<HttpResponse, Error> {
+    let teams = store.all()?;
+    let res: Vec<TeamV1> = teams.iter().map(TeamV1::from).collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&res)?))
+}
+
+/// `GET /api/v1/teams/{abbr}`: one team, `404` when there's no such team
+pub async fn team(
+    abbr: web::Path<String>,
+    store: web::Data<TeamStore>,
+) -> Result<HttpResponse, Error> {
+    let team = store.get(&abbr)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&TeamV1::from(&team))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use crate::test_support::{self, call};
+
+    #[actix_rt::test]
+    async fn v1_nests_the_area_that_v0_sends_flat() {
+        let config = test_support::config();
+        let mut app = test_support::app(config.clone(), test_support::seeded_store(&config)).await;
+
+        let (status, v1) = call(&mut app, TestRequest::get().uri("/api/v1/teams").to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        let v1 = test_support::json(&v1);
+        assert_eq!(
+            v1[0],
+            json!({
+                "team_abbreviation": "鹿島", "area": {"name": "茨城県"}, "join_year": 1991,
+                "division": "J1", "labels": [],
+            })
+        );
+        let (_, v0) = call(&mut app, TestRequest::get().uri("/api/v0/teams").to_request()).await;
+        let v0 = test_support::json(&v0);
+        assert_eq!(v0[0]["active_area"], "茨城県");
+        assert!(v0[0].get("area").is_none() && v1[0].get("active_area").is_none());
+        // the same teams in the same order, only written out differently
+        let (v0, v1) = (v0.as_array().unwrap(), v1.as_array().unwrap());
+        assert_eq!(v0.len(), v1.len());
+        for (v0, v1) in v0.iter().zip(v1) {
+            assert_eq!(v0["team_abbreviation"], v1["team_abbreviation"]);
+            assert_eq!(v0["active_area"], v1["area"]["name"]);
+        }
+
+        // 浦和, percent-encoded
+        let req = TestRequest::get().uri("/api/v1/teams/%E6%B5%A6%E5%92%8C").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(test_support::json(&body)["area"], json!({"name": "埼玉県"}));
+        let req = TestRequest::get().uri("/api/v1/teams/%E4%BA%AC%E9%83%BD").to_request();
+        let (status, body) = call(&mut app, req).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        test_support::assert_error_code(&body, "not_found");
+    }
+}
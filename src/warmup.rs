@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpResponse};
+use futures::future::{err, ok, Either, Ready};
+use serde::Serialize;
+
+use crate::config::env_or;
+
+/// the path that keeps answering while the server warms up
+pub const HEALTH_PATH: &str = "/healthz";
+
+#[derive(Debug, Serialize)]
+struct Status {
+    status: &'static str,
+}
+
+/// answer everything but `/healthz` with `503 {"status":"starting"}` until
+/// the warmup window has passed, so traffic doesn't reach the handlers before
+/// the server is ready for it. Every worker shares the same flag.
+#[derive(Debug, Clone)]
+pub struct Warmup {
+    ready: Arc<AtomicBool>,
+}
+
+impl Warmup {
+    /// start the `WARMUP_MS` window (0, no warmup, by default); must be
+    /// called from within the runtime
+    pub fn start() -> Self {
+        let window = Duration::from_millis(env_or("WARMUP_MS", 0));
+        let ready = Arc::new(AtomicBool::new(window == Duration::from_millis(0)));
+        if !ready.load(Ordering::SeqCst) {
+            let flag = ready.clone();
+            actix_rt::spawn(async move {
+                delay_for(window).await;
+                flag.store(true, Ordering::SeqCst);
+                log::info!("warmup finished after {}ms", window.as_millis());
+            });
+        }
+        Warmup { ready }
+    }
+}
+
+impl<S, B> Transform<S> for Warmup
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = WarmupMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(WarmupMiddleware { service, ready: self.ready.clone() })
+    }
+}
+
+pub struct WarmupMiddleware<S> {
+    service: S,
+    ready: Arc<AtomicBool>,
+}
+
+impl<S, B> Service for WarmupMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if self.ready.load(Ordering::SeqCst) || req.path() == HEALTH_PATH {
+            return Either::Left(self.service.call(req));
+        }
+        let res = HttpResponse::ServiceUnavailable().json(Status { status: "starting" });
+        Either::Right(err(res.into()))
+    }
+}
+
+/// liveness: `200 {"status":"ok"}` whenever the process is serving at all
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Status { status: "ok" })
+}
@@ -0,0 +1,48 @@
+//! Just enough websocket plumbing to run a session straight from a handler:
+//! the handshake, decoding the client's frames from the upgraded request's
+//! payload, and encoding ours onto a streaming response.
+
+use std::io;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::Codec;
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::{Error, HttpRequest};
+use futures::{stream, Stream, StreamExt};
+
+pub use actix_http::ws::{CloseCode, CloseReason, Frame, Message, ProtocolError};
+
+/// check the upgrade request and start the `101 Switching Protocols` response
+pub fn handshake(req: &HttpRequest) -> Result<HttpResponseBuilder, Error> {
+    Ok(actix_http::ws::handshake(req.head())?)
+}
+
+/// one message as a server-to-client frame
+pub fn encode(message: Message) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::new();
+    Codec::new().encode(message, &mut buf)?;
+    Ok(buf.freeze())
+}
+
+/// the client's frames as they arrive, until it hangs up
+pub fn frames(payload: web::Payload) -> impl Stream<Item = Result<Frame, ProtocolError>> {
+    stream::unfold(Some((payload, BytesMut::new(), Codec::new())), |state| async move {
+        let (mut payload, mut buf, mut codec) = state?;
+        loop {
+            match codec.decode(&mut buf) {
+                Ok(Some(frame)) => return Some((Ok(frame), Some((payload, buf, codec)))),
+                Ok(None) => {}
+                Err(e) => return Some((Err(e), None)),
+            }
+            match payload.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    let e = io::Error::other(e.to_string());
+                    return Some((Err(ProtocolError::Io(e)), None));
+                }
+                None => return None,
+            }
+        }
+    })
+}